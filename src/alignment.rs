@@ -0,0 +1,496 @@
+
+/* Many-to-many alignment of spellings with pronunciations.
+ *
+ * Proposing every substring of the spelling against every substring of the
+ * pronunciation finds every candidate rule, but almost all of them pair letters
+ * with sounds they have nothing to do with. An AlignmentModel instead learns,
+ * by expectation maximization over the whole dictionary, how likely each chunk
+ * of up to max_graphemes letters is to be spoken as each chunk of up to
+ * max_phonemes sounds:
+ *
+ *   t|h|a|t    th|a|t
+ *   ϑ| |æ|t    ϑ |æ|t
+ *
+ * Chunks map either several letters to at most one sound, or one letter to
+ * several sounds, and every letter is in some chunk, though a chunk can be
+ * silent. Each word is weighted the same regardless of frequency, so the common
+ * words don't drown out the regular patterns.
+ *
+ * The best alignment of a word then seeds candidate rules: every chunk, and
+ * every pair of neighbouring chunks, whose sounds are likely enough given its
+ * letters, with up to one glyph of context either side.
+ */
+
+use crate::astarlike2::{SubWithImprovement, generalized_context};
+use crate::dictionary::Dictionary;
+use crate::glyphs::{AugGlyph, Glyph, encode, decode};
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList, Anterior, Posterior, ContextElem, context_from_glyphs};
+use serde::{Serialize, Deserialize};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+pub const DEFAULT_MIN_PROB: f64 = 0.05;
+
+type Chunk = (Vec<Glyph>, Vec<Glyph>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignmentModel {
+  pub max_graphemes: usize,
+  pub max_phonemes: usize,
+  /// Chunks whose sounds are less likely than this given their letters don't
+  /// seed candidates.
+  pub min_prob: f64,
+  /// Joint probabilities, summing to 1.
+  probs: HashMap<Chunk, f64>,
+  grapheme_totals: HashMap<Vec<Glyph>, f64>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlignedChunk {
+  pub graphemes: Vec<Glyph>,
+  pub phonemes: Vec<Glyph>,
+  /// How likely these sounds are given these letters.
+  pub prob: f64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alignment {
+  pub chunks: Vec<AlignedChunk>,
+  pub log_prob: f64
+}
+
+impl std::fmt::Display for Alignment {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let pairs: Vec<String> = self.chunks.iter().map(|c| format!("{}→{}", encode(&c.graphemes), encode(&c.phonemes))).collect();
+    write!(f, "{}", pairs.join(" "))
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChunkProb {
+  graphemes: String,
+  phonemes: String,
+  prob: f64
+}
+
+#[derive(Serialize, Deserialize)]
+struct ModelFile {
+  max_graphemes: usize,
+  max_phonemes: usize,
+  min_prob: f64,
+  chunks: Vec<ChunkProb>
+}
+
+/// One line of export_alignments.
+#[derive(Serialize, Deserialize)]
+pub struct ExportedAlignment {
+  pub spelling: String,
+  pub pronunciation: String,
+  pub frequency: f64,
+  /// Letters, sounds and how likely the sounds are given the letters.
+  pub chunks: Vec<(String, String, f64)>,
+  pub log_prob: f64
+}
+
+/// Calls `f` with every chunk shape that fits in `letters` letters and `sounds`
+/// sounds.
+fn for_each_shape(max_graphemes: usize, max_phonemes: usize, letters: usize, sounds: usize, mut f: impl FnMut(usize, usize)) {
+  for a in 1 ..= max_graphemes.min(letters) {
+    for b in 0 ..= max_phonemes.min(sounds) {
+      if a == 1 || b <= 1 {
+        f(a, b);
+      }
+    }
+  }
+}
+
+/// Adds the expected count of every chunk over all alignments of one word to
+/// `counts`, and returns the total probability of the word.
+fn expected_counts(spelling: &[Glyph], pronunciation: &[Glyph], max_graphemes: usize, max_phonemes: usize, prob: &dyn Fn(&[Glyph], &[Glyph]) -> f64, counts: &mut HashMap<Chunk, f64>) -> f64 {
+  let (n, m) = (spelling.len(), pronunciation.len());
+  let mut alpha = vec![vec![0.0; m + 1]; n + 1];
+  alpha[0][0] = 1.0;
+  for i in 1 ..= n {
+    for j in 0 ..= m {
+      let mut sum = 0.0;
+      for_each_shape(max_graphemes, max_phonemes, i, j, |a, b| {
+        sum += alpha[i - a][j - b] * prob(&spelling[i - a .. i], &pronunciation[j - b .. j]);
+      });
+      alpha[i][j] = sum;
+    }
+  }
+  let total = alpha[n][m];
+  if total <= 0.0 {
+    return 0.0;
+  }
+
+  let mut beta = vec![vec![0.0; m + 1]; n + 1];
+  beta[n][m] = 1.0;
+  for i in (0 .. n).rev() {
+    for j in (0 ..= m).rev() {
+      let mut sum = 0.0;
+      for_each_shape(max_graphemes, max_phonemes, n - i, m - j, |a, b| {
+        sum += prob(&spelling[i .. i + a], &pronunciation[j .. j + b]) * beta[i + a][j + b];
+      });
+      beta[i][j] = sum;
+    }
+  }
+
+  for i in 0 .. n {
+    for j in 0 ..= m {
+      if alpha[i][j] == 0.0 {
+        continue;
+      }
+      for_each_shape(max_graphemes, max_phonemes, n - i, m - j, |a, b| {
+        let (g, p) = (&spelling[i .. i + a], &pronunciation[j .. j + b]);
+        let c = alpha[i][j] * prob(g, p) * beta[i + a][j + b] / total;
+        if c > 0.0 {
+          *counts.entry((g.to_vec(), p.to_vec())).or_insert(0.0) += c;
+        }
+      });
+    }
+  }
+  total
+}
+
+impl AlignmentModel {
+  fn from_counts(max_graphemes: usize, max_phonemes: usize, min_prob: f64, counts: HashMap<Chunk, f64>) -> AlignmentModel {
+    let total: f64 = counts.values().sum();
+    let probs: HashMap<Chunk, f64> = counts.into_iter().map(|(c, n)| (c, n / total)).collect();
+    let mut grapheme_totals: HashMap<Vec<Glyph>, f64> = HashMap::new();
+    for ((g, _), p) in &probs {
+      *grapheme_totals.entry(g.clone()).or_insert(0.0) += p;
+    }
+    AlignmentModel { max_graphemes, max_phonemes, min_prob, probs, grapheme_totals }
+  }
+
+  /// One round of expectation maximization, or the first estimate if `prev` is
+  /// None, which counts every alignment of every word the same. Also returns
+  /// the log likelihood of the dictionary under `prev`, counting only the words
+  /// it can align.
+  fn em_step(prev: Option<&AlignmentModel>, dictionary: &Dictionary, max_graphemes: usize, max_phonemes: usize) -> (AlignmentModel, f64) {
+    let prob = |g: &[Glyph], p: &[Glyph]| match prev {
+      Some(model) => model.joint(g, p),
+      None => 1.0
+    };
+    let (counts, log_likelihood) = dictionary.words.par_iter().fold(|| (HashMap::new(), 0.0), |(mut counts, ll), w| {
+      let total = expected_counts(&w.spelling, &w.pronunciation, max_graphemes, max_phonemes, &prob, &mut counts);
+      (counts, if total > 0.0 { ll + total.ln() } else { ll })
+    }).reduce(|| (HashMap::new(), 0.0), |(mut a, lla), (b, llb)| {
+      for (c, n) in b {
+        *a.entry(c).or_insert(0.0) += n;
+      }
+      (a, lla + llb)
+    });
+    let min_prob = prev.map_or(DEFAULT_MIN_PROB, |m| m.min_prob);
+    (AlignmentModel::from_counts(max_graphemes, max_phonemes, min_prob, counts), log_likelihood)
+  }
+
+  pub fn initial(dictionary: &Dictionary, max_graphemes: usize, max_phonemes: usize) -> AlignmentModel {
+    AlignmentModel::em_step(None, dictionary, max_graphemes, max_phonemes).0
+  }
+
+  /// The next model, and the log likelihood of the dictionary under this one.
+  pub fn improve(&self, dictionary: &Dictionary) -> (AlignmentModel, f64) {
+    AlignmentModel::em_step(Some(self), dictionary, self.max_graphemes, self.max_phonemes)
+  }
+
+  pub fn train(dictionary: &Dictionary, max_graphemes: usize, max_phonemes: usize, iterations: usize) -> AlignmentModel {
+    let mut model = AlignmentModel::initial(dictionary, max_graphemes, max_phonemes);
+    for _ in 0 .. iterations {
+      model = model.improve(dictionary).0;
+    }
+    model
+  }
+
+  pub fn joint(&self, graphemes: &[Glyph], phonemes: &[Glyph]) -> f64 {
+    // Chunk is a pair of Vecs, so looking one up means building it.
+    self.probs.get(&(graphemes.to_vec(), phonemes.to_vec())).copied().unwrap_or(0.0)
+  }
+
+  /// How likely `phonemes` is given `graphemes`.
+  pub fn conditional(&self, graphemes: &[Glyph], phonemes: &[Glyph]) -> f64 {
+    match self.grapheme_totals.get(graphemes) {
+      Some(&total) if total > 0.0 => self.joint(graphemes, phonemes) / total,
+      _ => 0.0
+    }
+  }
+
+  /// Every chunk the model knows, most likely first.
+  pub fn chunks(&self) -> Vec<(Vec<Glyph>, Vec<Glyph>, f64)> {
+    let mut res: Vec<_> = self.probs.iter().map(|((g, p), &prob)| (g.clone(), p.clone(), prob)).collect();
+    res.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| (&a.0, &a.1).cmp(&(&b.0, &b.1))));
+    res
+  }
+
+  /// The most likely alignment, or None if the model can't align the word at all.
+  pub fn align(&self, spelling: &[Glyph], pronunciation: &[Glyph]) -> Option<Alignment> {
+    let (n, m) = (spelling.len(), pronunciation.len());
+    let mut best = vec![vec![f64::NEG_INFINITY; m + 1]; n + 1];
+    let mut back = vec![vec![(0, 0); m + 1]; n + 1];
+    best[0][0] = 0.0;
+    for i in 1 ..= n {
+      for j in 0 ..= m {
+        for_each_shape(self.max_graphemes, self.max_phonemes, i, j, |a, b| {
+          let p = self.joint(&spelling[i - a .. i], &pronunciation[j - b .. j]);
+          if p > 0.0 && best[i - a][j - b] + p.ln() > best[i][j] {
+            best[i][j] = best[i - a][j - b] + p.ln();
+            back[i][j] = (a, b);
+          }
+        });
+      }
+    }
+    if best[n][m] == f64::NEG_INFINITY {
+      return None;
+    }
+
+    let mut chunks = vec![];
+    let (mut i, mut j) = (n, m);
+    while i > 0 {
+      let (a, b) = back[i][j];
+      let (graphemes, phonemes) = (spelling[i - a .. i].to_vec(), pronunciation[j - b .. j].to_vec());
+      let prob = self.conditional(&graphemes, &phonemes);
+      chunks.push(AlignedChunk { graphemes, phonemes, prob });
+      i -= a;
+      j -= b;
+    }
+    chunks.reverse();
+    Some(Alignment { chunks, log_prob: best[n][m] })
+  }
+
+  pub fn load(path: &str) -> Result<AlignmentModel, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let file: ModelFile = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    if file.max_graphemes == 0 {
+      return Err(format!("{}: max_graphemes has to be at least 1", path));
+    }
+    let counts = file.chunks.into_iter().map(|c| ((decode(&c.graphemes), decode(&c.phonemes)), c.prob)).collect();
+    Ok(AlignmentModel::from_counts(file.max_graphemes, file.max_phonemes, file.min_prob, counts))
+  }
+
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    let file = ModelFile {
+      max_graphemes: self.max_graphemes,
+      max_phonemes: self.max_phonemes,
+      min_prob: self.min_prob,
+      chunks: self.chunks().into_iter().map(|(g, p, prob)| ChunkProb { graphemes: encode(&g), phonemes: encode(&p), prob }).collect()
+    };
+    std::fs::write(path, serde_json::to_string_pretty(&file).unwrap()).map_err(|e| format!("{}: {}", path, e))
+  }
+
+  /// Writes the best alignment of every word the model can align as JSON lines,
+  /// and returns how many there were.
+  pub fn export_alignments(&self, dictionary: &Dictionary, path: &str) -> Result<usize, String> {
+    let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut out = std::io::BufWriter::new(file);
+    let mut exported = 0;
+    for w in &dictionary.words {
+      let Some(alignment) = self.align(&w.spelling, &w.pronunciation) else { continue };
+      let line = ExportedAlignment {
+        spelling: encode(&w.spelling),
+        pronunciation: encode(&w.pronunciation),
+        frequency: w.frequency,
+        chunks: alignment.chunks.iter().map(|c| (encode(&c.graphemes), encode(&c.phonemes), c.prob)).collect(),
+        log_prob: alignment.log_prob
+      };
+      serde_json::to_writer(&mut out, &line).map_err(|e| format!("{}: {}", path, e))?;
+      out.write_all(b"\n").map_err(|e| format!("{}: {}", path, e))?;
+      exported += 1;
+    }
+    out.flush().map_err(|e| format!("{}: {}", path, e))?;
+    Ok(exported)
+  }
+
+  /// A stand-in for astarlike2::find_improving_edits that only proposes what the
+  /// word's alignment suggests. `spelling` and `pronunciation` are the plain
+  /// word; the rest is as there. Chunks that earlier rules have split between
+  /// synthetic glyphs can't be proposed. Words the model can't align at all get
+  /// every candidate, as from astarlike2::find_improving_edits.
+  #[allow(clippy::too_many_arguments)]
+  pub fn find_improving_edits(&self, spelling: &[Glyph], pronunciation: &[Glyph], transformed_spelling: &[AugGlyph], back_transformed_pronunciation: &[AugGlyph], rules: &HLSubstitutionList, base_cost: u32, mid: u32, class_contexts: bool, cost: &(dyn Fn(&[AugGlyph]) -> u32 + Sync)) -> Vec<SubWithImprovement> {
+    let Some(alignment) = self.align(spelling, pronunciation) else {
+      return crate::astarlike2::find_improving_edits(&transformed_spelling.to_vec(), &back_transformed_pronunciation.to_vec(), rules, base_cost, mid, class_contexts, cost);
+    };
+    let word = transformed_spelling;
+    // Where each transformed glyph starts in the plain word, and the same for
+    // the pronunciation.
+    let letter_starts = starts(word, |g| rules.deapply_anterior_copied(&vec![*g]).len());
+    let sound_starts = starts(back_transformed_pronunciation, |g| rules.apply_posterior_copied(&vec![*g]).len());
+
+    let mut seen = HashSet::new();
+    let mut res = vec![];
+    for (i, i2, j, j2) in alignment.spans(self.min_prob) {
+      let (Some(t1), Some(t2), Some(u1), Some(u2)) = (
+        letter_starts.iter().position(|&s| s == i),
+        letter_starts.iter().position(|&s| s == i2),
+        sound_starts.iter().position(|&s| s == j),
+        sound_starts.iter().position(|&s| s == j2)
+      ) else { continue };
+
+      let mut pre_keys: Vec<Vec<ContextElem>> = vec![vec![]];
+      if t1 > 0 {
+        pre_keys.push(context_from_glyphs(&word[t1 - 1 .. t1]));
+        if class_contexts {
+          pre_keys.extend(generalized_context(&word[t1 - 1 .. t1]));
+        }
+      }
+      let mut post_keys: Vec<Vec<ContextElem>> = vec![vec![]];
+      if t2 < word.len() {
+        post_keys.push(context_from_glyphs(&word[t2 .. t2 + 1]));
+        if class_contexts {
+          post_keys.extend(generalized_context(&word[t2 .. t2 + 1]));
+        }
+      }
+
+      for pre_key in &pre_keys {
+        for post_key in &post_keys {
+          let (k1, k2) = (t1 - pre_key.len(), t2 + post_key.len());
+          let can_be_at_start = k1 == 0 || !word[k1 - 1].is_letter_or_phonetic();
+          let can_be_at_end = k2 == word.len() || !word[k2].is_letter_or_phonetic();
+          for &at_start in if can_be_at_start {[true, false].iter()} else {[false].iter()} {
+            for &at_end in if can_be_at_end {[true, false].iter()} else {[false].iter()} {
+              let sub = HLSubstitution {
+                anterior: Anterior {
+                  at_start,
+                  at_end,
+                  pre_key: pre_key.clone(),
+                  at_key: word[t1 .. t2].to_vec(),
+                  post_key: post_key.clone()
+                },
+                mid,
+                posterior: Posterior {
+                  content: back_transformed_pronunciation[u1 .. u2].to_vec()
+                }
+              };
+              if !seen.insert(sub.clone()) {
+                continue;
+              }
+              if let Some(new_step_one) = sub.apply_copied(&word.to_vec()) {
+                let new_cost = cost(&rules.apply_posterior_copied(&new_step_one));
+                if new_cost < base_cost {
+                  res.push(SubWithImprovement { sub, improvement: base_cost - new_cost });
+                }
+              }
+            }
+          }
+        }
+      }
+    }
+    res
+  }
+}
+
+impl Alignment {
+  /// The letters and sounds, as (letter start, letter end, sound start, sound
+  /// end), of every chunk and pair of neighbouring chunks worth proposing: all
+  /// of them at least `min_prob` likely, and not all silent.
+  pub fn spans(&self, min_prob: f64) -> Vec<(usize, usize, usize, usize)> {
+    let mut res = vec![];
+    let (mut i, mut j) = (0, 0);
+    for (k, chunk) in self.chunks.iter().enumerate() {
+      let (mut i2, mut j2) = (i, j);
+      for c in self.chunks[k ..].iter().take(2) {
+        if c.prob < min_prob {
+          break;
+        }
+        i2 += c.graphemes.len();
+        j2 += c.phonemes.len();
+        if j2 > j {
+          res.push((i, i2, j, j2));
+        }
+      }
+      i += chunk.graphemes.len();
+      j += chunk.phonemes.len();
+    }
+    res
+  }
+}
+
+/// The position in the expanded word where each glyph starts, followed by the
+/// expanded length.
+fn starts(glyphs: &[AugGlyph], expanded_len: impl Fn(&AugGlyph) -> usize) -> Vec<usize> {
+  let mut res = vec![0];
+  for g in glyphs {
+    res.push(res.last().unwrap() + expanded_len(g));
+  }
+  res
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::astarlike2::IterativeSystem;
+  use crate::dictionary::from_words;
+  use crate::glyphs::{augment, aug_decode};
+  use std::sync::Arc;
+
+  fn th_dictionary() -> Dictionary {
+    from_words(&[
+      ("the", "ϑʌ", 1.0), ("this", "ϑɪs", 1.0), ("that", "ϑæt", 1.0), ("thin", "ϑɪn", 1.0), ("sat", "sæt", 1.0),
+      ("sit", "sɪt", 1.0), ("tin", "tɪn", 1.0), ("hat", "hæt", 1.0), ("hit", "hɪt", 1.0)
+    ])
+  }
+
+  #[test]
+  fn learns_chunks() {
+    let dictionary = th_dictionary();
+    let mut model = AlignmentModel::initial(&dictionary, 2, 1);
+    let mut prev_ll = f64::NEG_INFINITY;
+    for _ in 0 .. 10 {
+      let (next, ll) = model.improve(&dictionary);
+      assert!(ll >= prev_ll - 1e-9);
+      prev_ll = ll;
+      model = next;
+    }
+    let alignment = model.align(&decode("that"), &decode("ϑæt")).unwrap();
+    assert_eq!(alignment.to_string(), "th→ϑ a→æ t→t");
+    assert!(model.conditional(&decode("th"), &decode("ϑ")) > 0.9);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.json").to_str().unwrap().to_owned();
+    model.save(&path).unwrap();
+    let loaded = AlignmentModel::load(&path).unwrap();
+    assert_eq!(loaded.align(&decode("this"), &decode("ϑɪs")).unwrap().to_string(), "th→ϑ i→ɪ s→s");
+    let export_path = dir.path().join("alignments.jsonl").to_str().unwrap().to_owned();
+    assert_eq!(model.export_alignments(&dictionary, &export_path).unwrap(), dictionary.words.len());
+  }
+
+  #[test]
+  fn seeds_a_subset_of_the_brute_force_candidates() {
+    let dictionary = th_dictionary();
+    let model = AlignmentModel::train(&dictionary, 2, 1, 10);
+    let rules = HLSubstitutionList { substitutions: vec![HLSubstitution::decode("[a]→0→æ").unwrap()] };
+    let (spelling, pronunciation) = (decode("that"), decode("ϑæt"));
+    let transformed = aug_decode("th{0}t");
+    let back_transformed = aug_decode("ϑ{0}t");
+    let target = augment(&pronunciation);
+    let cost = |out: &[AugGlyph]| crate::astarlike2::distance(&out.to_vec(), &target);
+    let base_cost = cost(&rules.apply_posterior_copied(&transformed));
+
+    let seeded = model.find_improving_edits(&spelling, &pronunciation, &transformed, &back_transformed, &rules, base_cost, 1, false, &cost);
+    let all = crate::astarlike2::find_improving_edits(&transformed, &back_transformed, &rules, base_cost, 1, false, &cost);
+    let encoded: Vec<String> = seeded.iter().map(|s| s.sub.encode()).collect();
+    assert!(encoded.contains(&"[th]→1→ϑ".to_owned()));
+    // Keys can take in earlier rules' synthetic glyphs.
+    assert!(encoded.contains(&"[th{0}]→1→ϑ{0}".to_owned()));
+    assert!(seeded.iter().all(|s| all.iter().any(|a| a.sub == s.sub && a.improvement == s.improvement)));
+    assert!(seeded.len() < all.len());
+
+    // One letter can't be two sounds, so the model can't align this at all.
+    let (spelling, pronunciation) = (decode("ax"), decode("æks"));
+    assert!(model.align(&spelling, &pronunciation).is_none());
+    let (transformed, target) = (augment(&spelling), augment(&pronunciation));
+    let cost = |out: &[AugGlyph]| crate::astarlike2::distance(&out.to_vec(), &target);
+    let base_cost = cost(&transformed);
+    let seeded = model.find_improving_edits(&spelling, &pronunciation, &transformed, &target, &rules, base_cost, 1, false, &cost);
+    let all = crate::astarlike2::find_improving_edits(&transformed, &target, &rules, base_cost, 1, false, &cost);
+    assert!(!seeded.is_empty());
+    assert_eq!(seeded.len(), all.len());
+
+    let mut system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+    system.alignment = Some(Arc::new(model));
+    let crate::astarlike2::Outcome::FoundImprovement(first, _) = system.find_next_rule(&crate::gaussian_astarlike22::GaussianSystem { scale: 4.0 }, false) else { panic!("Failed to find improvement") };
+    assert_eq!(first.encode(), "[th]→0→ϑ");
+  }
+}
@@ -0,0 +1,339 @@
+
+/* Simulated annealing over whole HLSubstitutionLists, with parallel tempering.
+ *
+ * Unlike the astarlike searches, which only ever add a rule to the end, this
+ * edits the whole list: it inserts rules proposed by find_improving_edits for
+ * badly-served words, deletes rules, swaps neighbouring rules and widens rule
+ * contexts. Moves that would leave a {n} pointing at a rule that isn't earlier
+ * in the list are never made.
+ *
+ * Several chains run at once, each at its own temperature on a geometric ladder,
+ * and neighbouring chains swap lists now and then so that a good list found by a
+ * hot chain can be refined by a cold one. The whole ladder cools geometrically
+ * over the run. It's meant to start from a greedy result and shake it out of
+ * whatever local optimum the greedy search got stuck in.
+ */
+
+use crate::glyphs::{AugGlyph, augment};
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList, ContextElem, GlyphClass};
+use crate::astarlike2::find_improving_edits;
+use crate::objective::{Objective, evaluate};
+use crate::dictionary::Dictionary;
+use rand::{Rng, SeedableRng, rngs::SmallRng, distributions::{Distribution, WeightedIndex}};
+use rayon::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct AnnealingConfig {
+  /// How many chains to run in parallel.
+  pub chains: usize,
+  /// Starting temperatures of the hottest and coldest chains.
+  pub max_temperature: f64,
+  pub min_temperature: f64,
+  /// By the last round every temperature has been multiplied by this.
+  pub final_cooling: f64,
+  pub rounds: usize,
+  /// Moves each chain makes between swap attempts.
+  pub moves_per_round: usize,
+  pub class_contexts: bool,
+  pub seed: u64
+}
+
+impl Default for AnnealingConfig {
+  fn default() -> AnnealingConfig {
+    AnnealingConfig {
+      chains: 4,
+      max_temperature: 1.0,
+      min_temperature: 0.01,
+      final_cooling: 0.01,
+      rounds: 50,
+      moves_per_round: 20,
+      class_contexts: false,
+      seed: 0
+    }
+  }
+}
+
+impl AnnealingConfig {
+  /// The temperature of each chain in `round`, coldest first.
+  pub fn temperatures(&self, round: usize) -> Vec<f64> {
+    let cooling = self.final_cooling.powf(round as f64 / (self.rounds.max(2) - 1) as f64);
+    (0 .. self.chains).map(|i| {
+      let t = if self.chains == 1 { 0.0 } else { i as f64 / (self.chains - 1) as f64 };
+      self.min_temperature * (self.max_temperature / self.min_temperature).powf(t) * cooling
+    }).collect()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Move {
+  Insert,
+  Delete,
+  Swap,
+  Widen
+}
+
+pub const MOVES: [Move; 4] = [Move::Insert, Move::Delete, Move::Swap, Move::Widen];
+
+#[derive(Clone)]
+struct Scored {
+  rules: HLSubstitutionList,
+  word_costs: Vec<u32>,
+  score: f64
+}
+
+fn score(rules: &HLSubstitutionList, dictionary: &Dictionary, objective: &dyn Objective) -> Scored {
+  let e = evaluate(objective, rules, dictionary);
+  Scored {
+    rules: rules.clone(),
+    word_costs: e.word_costs,
+    score: e.score
+  }
+}
+
+fn mentions(sub: &HLSubstitution, mid: u32) -> bool {
+  sub.anterior.glyphs().iter().chain(sub.posterior.content.iter()).any(|g| *g == AugGlyph::Synthetic(mid))
+}
+
+/// A rule proposed by one of the words that the rules do worst on, weighted by
+/// how much that costs.
+fn propose_insert<R: Rng>(rng: &mut R, current: &Scored, dictionary: &Dictionary, class_contexts: bool, objective: &dyn Objective) -> Option<HLSubstitution> {
+  let weights: Vec<f64> = dictionary.words.iter().zip(&current.word_costs).map(|(w, &d)| w.frequency * (d as f64)).collect();
+  let i = WeightedIndex::new(&weights).ok()?.sample(rng);
+  let w = &dictionary.words[i];
+
+  let spelling = augment(&w.spelling);
+  let mut transformed = spelling.clone();
+  let base_pronunciation = augment(&w.pronunciation);
+  let mut back_transformed = base_pronunciation.clone();
+  for s in &current.rules.substitutions {
+    s.apply_anterior(&mut transformed);
+    s.deapply_posterior(&mut back_transformed);
+  }
+
+  let edits = find_improving_edits(&transformed, &back_transformed, &current.rules, current.word_costs[i], current.rules.next_open_mid(), class_contexts, &|out| objective.word_cost(out, &base_pronunciation, &spelling));
+  if edits.is_empty() {
+    return None;
+  }
+  Some(edits[rng.gen_range(0 .. edits.len())].sub.clone())
+}
+
+/// Every way of making one rule's context a bit less specific.
+fn widenings(sub: &HLSubstitution) -> Vec<HLSubstitution> {
+  let mut res = vec![];
+  let mut push = |f: &dyn Fn(&mut HLSubstitution)| {
+    let mut s = sub.clone();
+    f(&mut s);
+    if s.anterior.check_context().is_ok() {
+      res.push(s);
+    }
+  };
+
+  if sub.anterior.at_start { push(&|s| s.anterior.at_start = false); }
+  if sub.anterior.at_end { push(&|s| s.anterior.at_end = false); }
+  if !sub.anterior.pre_key.is_empty() { push(&|s| { s.anterior.pre_key.remove(0); }); }
+  if !sub.anterior.post_key.is_empty() { push(&|s| { s.anterior.post_key.pop(); }); }
+
+  let generalize = |e: &ContextElem| match e {
+    ContextElem::Glyph(AugGlyph::Real(g)) if g.is_vowel() => Some(ContextElem::Class(GlyphClass::Vowel)),
+    ContextElem::Glyph(AugGlyph::Real(g)) if g.is_letter_or_phonetic() => Some(ContextElem::Class(GlyphClass::Consonant)),
+    _ => None
+  };
+  for i in 0 .. sub.anterior.pre_key.len() {
+    if let Some(e) = generalize(&sub.anterior.pre_key[i]) {
+      push(&|s| s.anterior.pre_key[i] = e.clone());
+    }
+  }
+  for i in 0 .. sub.anterior.post_key.len() {
+    if let Some(e) = generalize(&sub.anterior.post_key[i]) {
+      push(&|s| s.anterior.post_key[i] = e.clone());
+    }
+  }
+
+  res
+}
+
+/// Applies a random move of the given kind, or returns None if it has nothing to
+/// work with. The result always passes check_back_refs.
+pub fn propose<R: Rng>(rng: &mut R, kind: Move, rules: &HLSubstitutionList, insert: impl FnOnce(&mut R) -> Option<HLSubstitution>) -> Option<HLSubstitutionList> {
+  let subs = &rules.substitutions;
+  let mut res = rules.clone();
+
+  match kind {
+    Move::Insert => {
+      res.substitutions.push(insert(rng)?);
+    },
+    Move::Delete => {
+      let deletable: Vec<usize> = (0 .. subs.len()).filter(|&i| !subs[i+1 ..].iter().any(|s| mentions(s, subs[i].mid))).collect();
+      if deletable.is_empty() {
+        return None;
+      }
+      res.substitutions.remove(deletable[rng.gen_range(0 .. deletable.len())]);
+    },
+    Move::Swap => {
+      let swappable: Vec<usize> = (0 .. subs.len().saturating_sub(1)).filter(|&i| !mentions(&subs[i+1], subs[i].mid)).collect();
+      if swappable.is_empty() {
+        return None;
+      }
+      let i = swappable[rng.gen_range(0 .. swappable.len())];
+      res.substitutions.swap(i, i+1);
+    },
+    Move::Widen => {
+      let options: Vec<(usize, HLSubstitution)> = subs.iter().enumerate().flat_map(|(i, s)| widenings(s).into_iter().map(move |w| (i, w))).collect();
+      if options.is_empty() {
+        return None;
+      }
+      let (i, widened) = options[rng.gen_range(0 .. options.len())].clone();
+      res.substitutions[i] = widened;
+    }
+  }
+
+  debug_assert!(res.check_back_refs().is_ok());
+  Some(res)
+}
+
+struct Chain {
+  current: Scored,
+  rng: SmallRng,
+  accepted: usize,
+  proposed: usize
+}
+
+impl Chain {
+  fn run(&mut self, dictionary: &Dictionary, temperature: f64, moves: usize, class_contexts: bool, objective: &dyn Objective) {
+    for _ in 0 .. moves {
+      let kind = MOVES[self.rng.gen_range(0 .. MOVES.len())];
+      let current = &self.current;
+      let Some(candidate) = propose(&mut self.rng, kind, &current.rules, |rng| propose_insert(rng, current, dictionary, class_contexts, objective)) else { continue };
+      self.proposed += 1;
+
+      let candidate = score(&candidate, dictionary, objective);
+      let delta = candidate.score - self.current.score;
+      if delta <= 0.0 || (temperature > 0.0 && self.rng.gen::<f64>() < (-delta / temperature).exp()) {
+        self.current = candidate;
+        self.accepted += 1;
+      }
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct AnnealingResult {
+  pub best: HLSubstitutionList,
+  pub best_score: f64,
+  pub start_score: f64,
+  /// Moves accepted and proposed, summed over chains.
+  pub accepted: usize,
+  pub proposed: usize,
+  pub swaps: usize
+}
+
+/// Anneals starting from `start`, which should usually be the result of a greedy
+/// search. Lists are scored exactly by `objective`, and the best list any chain
+/// saw is returned.
+pub fn anneal(start: &HLSubstitutionList, dictionary: &Dictionary, config: &AnnealingConfig, objective: &dyn Objective) -> AnnealingResult {
+  let start = score(start, dictionary, objective);
+  let start_score = start.score;
+  let mut best = start.clone();
+
+  let mut chains: Vec<Chain> = (0 .. config.chains.max(1)).map(|i| Chain {
+    current: start.clone(),
+    rng: SmallRng::seed_from_u64(config.seed.wrapping_add(i as u64)),
+    accepted: 0,
+    proposed: 0
+  }).collect();
+  let mut swap_rng = SmallRng::seed_from_u64(config.seed.wrapping_sub(1));
+  let mut swaps = 0;
+
+  for round in 0 .. config.rounds {
+    let temperatures = config.temperatures(round);
+
+    chains.par_iter_mut().zip(temperatures.par_iter()).for_each(|(chain, &t)| {
+      chain.run(dictionary, t, config.moves_per_round, config.class_contexts, objective);
+    });
+
+    for chain in &chains {
+      if chain.current.score < best.score {
+        best = chain.current.clone();
+      }
+    }
+
+    // Chains are ordered coldest first.
+    for i in 0 .. chains.len().saturating_sub(1) {
+      let (t1, t2) = (temperatures[i], temperatures[i+1]);
+      if t1 <= 0.0 {
+        continue;
+      }
+      let (s1, s2) = (chains[i].current.score, chains[i+1].current.score);
+      let p = ((s1 - s2) * (1.0 / t1 - 1.0 / t2)).exp();
+      if swap_rng.gen::<f64>() < p {
+        let (a, b) = chains.split_at_mut(i+1);
+        std::mem::swap(&mut a[i].current, &mut b[0].current);
+        swaps += 1;
+      }
+    }
+  }
+
+  AnnealingResult {
+    best: best.rules,
+    best_score: best.score,
+    start_score,
+    accepted: chains.iter().map(|c| c.accepted).sum(),
+    proposed: chains.iter().map(|c| c.proposed).sum(),
+    swaps
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dictionary::from_words;
+  use crate::objective::WeightedObjective;
+
+  #[test]
+  fn moves_respect_back_refs() {
+    let rules = HLSubstitutionList::decode("
+      [c]→0→k
+      [{0}h]→1→ʧ
+      ^[a]b→2→æ
+    ").unwrap();
+    let mut rng = SmallRng::seed_from_u64(0);
+
+    for _ in 0 .. 100 {
+      for kind in [Move::Delete, Move::Swap, Move::Widen] {
+        let res = propose(&mut rng, kind, &rules, |_| None).unwrap();
+        assert!(res.check_back_refs().is_ok());
+        // While [{0}h] is there, [c] can't be deleted or moved after it.
+        let c = res.substitutions.iter().position(|s| s.mid == 0);
+        if let Some(ch) = res.substitutions.iter().position(|s| s.mid == 1) {
+          assert!(c.unwrap() < ch);
+        }
+      }
+    }
+
+    assert_eq!(widenings(&rules.substitutions[2]).iter().map(|s| s.encode()).collect::<Vec<_>>(), vec![
+      "[a]b→2→æ",
+      "^[a]→2→æ",
+      "^[a]{C}→2→æ",
+    ]);
+  }
+
+  #[test]
+  fn does_not_get_worse() {
+    let dictionary = from_words(&[("cat", "kæt", 3.0), ("chat", "ʧæt", 2.0), ("act", "ækt", 1.0), ("cab", "kæb", 1.0)]);
+    let start = HLSubstitutionList::decode("[c]→0→k").unwrap();
+    let config = AnnealingConfig {
+      rounds: 10,
+      moves_per_round: 10,
+      ..AnnealingConfig::default()
+    };
+
+    let res = anneal(&start, &dictionary, &config, &WeightedObjective::default());
+    assert!(res.best_score < res.start_score);
+    assert!(res.best.check_back_refs().is_ok());
+    assert!(res.proposed > 0);
+
+    // Same seed, same result.
+    let again = anneal(&start, &dictionary, &config, &WeightedObjective::default());
+    assert_eq!(again.best.substitutions, res.best.substitutions);
+  }
+}
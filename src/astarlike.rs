@@ -235,7 +235,15 @@ pub struct WorkingData<Estimator> {
   best_possible: KeyedPriorityQueue<HLSubstitution, FloatOrd<f64>>,
   best_possible_rev: KeyedPriorityQueue<HLSubstitution, FloatOrd<f64>>,
   
-  introducing_working_index: usize
+  introducing_working_index: usize,
+  words_explored: usize
+}
+
+impl<Estimator> WorkingData<Estimator> {
+  /// How many (edit, word) pairs have been scored exactly so far.
+  pub fn words_explored(&self) -> usize {
+    self.words_explored
+  }
 }
 
 fn improving_edits_at_i<'d, 'h, T>(r: &ReferenceData<'d, 'h, T>, i: usize) -> Vec<SubWithImprovement> {
@@ -261,7 +269,8 @@ pub fn init_working_data<'d, 'h, T, E>(_r: &ReferenceData<'d, 'h, T>) -> Working
     best_possible,
     best_possible_rev,
     
-    introducing_working_index: 0
+    introducing_working_index: 0,
+    words_explored: 0
   }
 }
 
@@ -544,6 +553,8 @@ fn do_work<'d, 'h, T: Table<Estimate, Estimator> + Send + Sync, Estimate: Clone
     }
   }).collect();
   
+  w.words_explored += result_chunk.iter().map(|res| res.next_to_explore_index - w.working_table[&res.edit].next_to_explore_index).sum::<usize>();
+  
   for result in result_chunk {
     w.best_possible_rev.set_priority(&result.edit, FloatOrd(result.best_possible)).unwrap();
     w.best_possible.push(result.edit.clone(), FloatOrd(-result.best_possible));
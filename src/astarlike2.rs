@@ -1,13 +1,18 @@
 
-use crate::glyphs::{AugGlyph, aug_encode};
-use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList, Anterior, Posterior};
-use keyed_priority_queue::KeyedPriorityQueue;
+use crate::glyphs::{AugGlyph, Glyph, aug_encode, encode, augment, strip_aug};
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList, Anterior, Posterior, ContextElem, GlyphClass, context_from_glyphs};
 use levenshtein_diff as levenshtein;
-use float_ord::FloatOrd;
 use std::collections::HashMap;
 use rayon::prelude::*;
-use noisy_float::prelude::*;
-use crate::dictionary::Dictionary;
+use crate::dictionary::{Dictionary, DictionaryWord};
+use crate::genastarlike;
+use crate::objective::{Objective, WeightedObjective};
+use crate::constraints::{Constraints, ConstrainedObjective, ProtectionGuard, Violation};
+use crate::search_log::SearchLog;
+use crate::ngram_index::{NgramIndex, DEFAULT_MAX_N};
+use crate::alignment::AlignmentModel;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
 
 #[derive(Clone)]
 pub struct AugDictionary {
@@ -36,7 +41,20 @@ pub struct SubWithImprovement {
   pub improvement: u32
 }
 
-pub fn find_improving_edits(transformed_spelling: &Vec<AugGlyph>, back_transformed_pronunciation: &Vec<AugGlyph>, base_pronunciation: &Vec<AugGlyph>, rules: &HLSubstitutionList, base_distance: u32, mid: u32) -> Vec<SubWithImprovement> {
+/// The vowel/consonant class of a single-glyph context, if it has one.
+pub fn generalized_context(context: &[AugGlyph]) -> Option<Vec<ContextElem>> {
+  match context {
+    [AugGlyph::Real(g)] if g.is_vowel() => Some(vec![ContextElem::Class(GlyphClass::Vowel)]),
+    [AugGlyph::Real(g)] if g.is_letter_or_phonetic() => Some(vec![ContextElem::Class(GlyphClass::Consonant)]),
+    _ => None
+  }
+}
+
+/// `cost` says what the word would cost if the rules produced a given output, and
+/// `base_cost` is what it costs now; usually both are distances from the
+/// pronunciation. If `class_contexts` is set, single-glyph pre- and post-keys are
+/// also tried generalized to {V} or {C}.
+pub fn find_improving_edits(transformed_spelling: &Vec<AugGlyph>, back_transformed_pronunciation: &Vec<AugGlyph>, rules: &HLSubstitutionList, base_cost: u32, mid: u32, class_contexts: bool, cost: &(dyn Fn(&[AugGlyph]) -> u32 + Sync)) -> Vec<SubWithImprovement> {
   let word = transformed_spelling;
   let pronunciation = back_transformed_pronunciation;
   (0 .. word.len()).into_par_iter().map(|k1| {
@@ -54,27 +72,39 @@ pub fn find_improving_edits(transformed_spelling: &Vec<AugGlyph>, back_transform
               for &at_start in if can_be_at_start {[true, false].iter()} else {[false].iter()} {
                 for &at_end in if can_be_at_end {[true, false].iter()} else {[false].iter()} {
                   let whole_key = &word[k1 .. k2];
-                  let hlsub = HLSubstitution {
-                    anterior: Anterior {
-                      at_start,
-                      at_end,
-                      pre_key: whole_key[.. s1].to_vec(),
-                      at_key: whole_key[s1 .. s2].to_vec(),
-                      post_key: whole_key[s2 ..].to_vec()
-                    },
-                    mid,
-                    posterior: Posterior {
-                      content: pronunciation[sc1 .. sc2].to_vec()
-                    }
-                  };
-                  if let Some(new_step_one) = hlsub.apply_copied(&word) {
-                    let new_transformed = rules.apply_posterior_copied(&new_step_one);
-                    let new_distance = distance(&new_transformed, base_pronunciation);
-                    if new_distance < base_distance {
-                      res.push(SubWithImprovement {
-                        sub: hlsub,
-                        improvement: base_distance - new_distance
-                      });
+                  
+                  let mut pre_keys = vec![context_from_glyphs(&whole_key[.. s1])];
+                  let mut post_keys = vec![context_from_glyphs(&whole_key[s2 ..])];
+                  if class_contexts {
+                    pre_keys.extend(generalized_context(&whole_key[.. s1]));
+                    post_keys.extend(generalized_context(&whole_key[s2 ..]));
+                  }
+                  
+                  for pre_key in &pre_keys {
+                    for post_key in &post_keys {
+                      let hlsub = HLSubstitution {
+                        anterior: Anterior {
+                          at_start,
+                          at_end,
+                          pre_key: pre_key.clone(),
+                          at_key: whole_key[s1 .. s2].to_vec(),
+                          post_key: post_key.clone()
+                        },
+                        mid,
+                        posterior: Posterior {
+                          content: pronunciation[sc1 .. sc2].to_vec()
+                        }
+                      };
+                      if let Some(new_step_one) = hlsub.apply_copied(&word) {
+                        let new_transformed = rules.apply_posterior_copied(&new_step_one);
+                        let new_cost = cost(&new_transformed);
+                        if new_cost < base_cost {
+                          res.push(SubWithImprovement {
+                            sub: hlsub,
+                            improvement: base_cost - new_cost
+                          });
+                        }
+                      }
                     }
                   }
                 }
@@ -107,7 +137,7 @@ mod find_improving_edits_tests {
     let base_distance: u32 = distance(&aug_decode("kat"), &base_pronunciation);
     let mid: u32 = 1;
     
-    let edits = find_improving_edits(&transformed_spelling, &back_transformed_pronunciation, &base_pronunciation, &rules, base_distance, mid);
+    let edits = find_improving_edits(&transformed_spelling, &back_transformed_pronunciation, &rules, base_distance, mid, false, &|out| distance(&out.to_vec(), &base_pronunciation));
     
     println!("Improving edits:");
     for sw in &edits {
@@ -120,544 +150,462 @@ mod find_improving_edits_tests {
     assert!(edits.contains(&SubWithImprovement { sub: HLSubstitution::decode("{0}[a]→1→æ").unwrap(), improvement: 1 }));
     assert!(edits.contains(&SubWithImprovement { sub: HLSubstitution::decode("{0}[a]t→1→æ").unwrap(), improvement: 1 }));
   }
-}
-
-pub trait Table<Estimate: Clone, Estimator: Clone> {
-  fn introduce(
-    &self,
-    frequencies_in_introducing_order: &Vec<f64>,
-    introducing_i: usize,
-    change_at_introducing_i: i32,
-    edit: &HLSubstitution
-  ) -> Estimator;
   
-  fn estimate_introduce(
-    &self,
-    frequencies_in_introducing_order: &Vec<f64>,
-    introducing_i: usize
-  ) -> Estimate;
-  
-  fn update_edit(
-    &self,
-    frequencies_in_introducing_order: &Vec<f64>,
-    current_distances_in_introducing_order: &Vec<u32>,
-    introduced_i: usize,
-    updated_i: usize,
-    change_at_updated_i: i32,
-    prev_estimate: Estimator
-  ) -> Estimator;
-}
-
-pub trait System<T: Table<Estimate, Estimator>, Estimate: Clone, Estimator: Clone> {
-  fn build_table(
-    &self,
-    frequencies_in_introducing_order: &Vec<f64>,
-    current_distances_in_introducing_order: &Vec<u32>
-  ) -> T;
-  
-  fn calc_best_possible(&self, estimate: &Estimate) -> R64;
-  fn calc_worst_possible(&self, estimate: &Estimate) -> R64;
-  
-  fn calc_estimate(&self, estimator: &Estimator) -> Estimate;
+  #[test]
+  pub fn test_class_contexts() {
+    let transformed_spelling: Vec<AugGlyph> = aug_decode("cite");
+    let back_transformed_pronunciation: Vec<AugGlyph> = aug_decode("sait");
+    let base_pronunciation: Vec<AugGlyph> = aug_decode("sait");
+    let rules = HLSubstitutionList { substitutions: vec![] };
+    let base_distance: u32 = distance(&transformed_spelling, &base_pronunciation);
+    
+    let without = find_improving_edits(&transformed_spelling, &back_transformed_pronunciation, &rules, base_distance, 0, false, &|out| distance(&out.to_vec(), &base_pronunciation));
+    let with = find_improving_edits(&transformed_spelling, &back_transformed_pronunciation, &rules, base_distance, 0, true, &|out| distance(&out.to_vec(), &base_pronunciation));
+    
+    let class_rule = SubWithImprovement { sub: HLSubstitution::decode("[c]{V}→0→s").unwrap(), improvement: 1 };
+    assert!(!without.contains(&class_rule));
+    assert!(with.contains(&class_rule));
+    assert!(with.contains(&SubWithImprovement { sub: HLSubstitution::decode("[c]i→0→s").unwrap(), improvement: 1 }));
+  }
 }
 
 #[derive(Debug)]
 pub enum Outcome {
   FoundImprovement(HLSubstitution, f64),
-  FailedToFindImprovement(HLSubstitution, f64)
-}
-
-#[derive(Debug, Clone)]
-pub struct WorkingEntry<E> {
-  estimate: E,
-  best_possible: f64,
-  worst_possible: f64,
-  
-  // This is an index into the introducing vector,
-  // which is ordered by weighted badness.
-  introducing_index: usize,
-  
-  // This is an index into the exploring vector,
-  // which is ordered by frequency.
-  next_to_explore_index: usize
-}
-
-pub struct ReferenceData<'d, T> {
-  dictionary: &'d AugDictionary,
-  rules: &'d HLSubstitutionList,
-  mid: u32,
-  
-  n: usize,
-  current_distances: Vec<u32>,
-  
-  table: T,
-  
-  introducing_order: Vec<usize>,
-  introducing_order_rev: Vec<usize>,
-  
-  frequencies_in_introducing_order: Vec<f64>,
-  current_distances_in_introducing_order: Vec<u32>
+  FailedToFindImprovement(HLSubstitution, f64),
+  /// No word had any improving rules left to propose.
+  Exhausted,
+  /// Nothing was searched, because check_objective failed.
+  Refused(String)
 }
 
-fn edit_size_cost(edit: &HLSubstitution) -> f64 {
-  let size = edit.anterior.pre_key.len() + edit.anterior.at_key.len() + edit.anterior.post_key.len() + (if edit.anterior.at_start { 1 } else { 0 }) + (if edit.anterior.at_end { 1 } else { 0 });
-  (size as f64) * 0.001
+pub struct IterativeSystem {
+  pub dictionary: AugDictionary,
+  pub rules: HLSubstitutionList,
+  /// Whether to also propose rules with {V}/{C} contexts.
+  pub class_contexts: bool,
+  /// How many rules have been found so far.
+  pub steps: usize,
+  /// If set, a checkpoint is written here after every rule that's found.
+  pub checkpoint_path: Option<String>,
+  /// WeightedObjective::default() unless set otherwise. Checkpoints only keep a
+  /// fingerprint of it; see settings_fingerprint.
+  pub objective: Arc<dyn Objective>,
+  /// None by default. Fingerprinted in checkpoints, like the objective.
+  pub constraints: Arc<Constraints>,
+  /// If set, find_next_rule logs its events here, stamped with `steps`.
+  pub log: Option<SearchLog>,
+  /// What the most recent find_next_rule took.
+  pub last_search: SearchStats,
+  /// If set, find_next_rule scores its winner and this many runner-ups exactly
+  /// afterwards. Off by default, since it means scoring them on every word.
+  pub verify: Option<usize>,
+  /// The most recent verification, if verify is set.
+  pub last_verification: Option<genastarlike::Verification<HLSubstitution>>,
+  /// Which words each n-gram of the transformed spellings appears in. push_rule
+  /// keeps it up to date; anything else that changes the dictionary has to call
+  /// rebuild_index afterwards.
+  pub index: NgramIndex,
+  /// If set, candidates come from each word's alignment instead of every pair
+  /// of substrings. Fingerprinted in checkpoints, like the objective.
+  pub alignment: Option<Arc<AlignmentModel>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+  /// Calls to step.
+  pub steps: usize,
+  pub words_explored: usize
+}
+
+pub const CHECKPOINT_SCHEMA_VERSION: u32 = 2;
+
+// The search itself is deterministic, so this and the same settings are
+// everything needed to carry on exactly where it left off. The settings can't
+// all be saved (the objective is a trait object), so only their fingerprint is.
+#[derive(Serialize, Deserialize)]
+struct CheckpointWord {
+  transformed_spelling: Vec<AugGlyph>,
+  base_pronunciation: Vec<AugGlyph>,
+  back_transformed_pronunciation: Vec<AugGlyph>,
+  // Stored as bits so that the frequencies come back exactly.
+  frequency_bits: u64
+}
+
+#[derive(Serialize, Deserialize)]
+struct IterativeSystemCheckpoint {
+  schema_version: u32,
+  steps: usize,
+  class_contexts: bool,
+  settings: String,
+  rules: HLSubstitutionList,
+  words: Vec<CheckpointWord>
 }
 
-pub fn init_ref_data<'d, 'h, T: Table<Estimate, Estimator>, Estimate: Clone, Estimator: Clone, S: System<T, Estimate, Estimator>>(sys: &S, dictionary: &'d AugDictionary, rules: &'d HLSubstitutionList, mid: u32) -> ReferenceData<'d, T> {
-  let n = dictionary.words.len();
-
-  let current_distances: Vec<u32> = dictionary.words.iter().map(|w| {
-    distance(&rules.apply_posterior_copied(&w.transformed_spelling), &w.base_pronunciation)
-  }).collect();
-  
-  let mut introducing_order: Vec<usize> = (0 .. n).collect();
-  introducing_order.sort_by_key(|&i| FloatOrd(-dictionary.words[i].frequency * (current_distances[i] as f64)));
-  let introducing_order = introducing_order;
-  
-  let mut introducing_order_rev = vec![0; n];
-  for i in 0 .. n {
-    let j = introducing_order[i];
-    introducing_order_rev[j] = i;
+impl IterativeSystem {
+  pub fn setup(dictionary: &Dictionary, init_rules: HLSubstitutionList) -> IterativeSystem {
+    let dictionary = AugDictionary {
+        words: dictionary.words.iter().map(|w| {
+          let base_pronunciation: Vec<AugGlyph> = w.pronunciation.iter().map(|g| AugGlyph::Real(*g)).collect();
+          AugDictionaryWord {
+            transformed_spelling: w.spelling.iter().map(|g| AugGlyph::Real(*g)).collect(),
+            back_transformed_pronunciation: base_pronunciation.clone(),
+            base_pronunciation,
+            frequency: w.frequency
+          }
+        }).collect()
+    };
+    IterativeSystem {
+      index: NgramIndex::build(&dictionary, DEFAULT_MAX_N),
+      dictionary,
+      rules: init_rules,
+      class_contexts: false,
+      steps: 0,
+      checkpoint_path: None,
+      objective: Arc::new(WeightedObjective::default()),
+      constraints: Arc::new(Constraints::default()),
+      log: None,
+      last_search: SearchStats::default(),
+      verify: None,
+      last_verification: None,
+      alignment: None
+    }
   }
-
-  let frequencies_in_introducing_order = (0 .. n).map(|i| dictionary.words[introducing_order[i]].frequency).collect();
   
-  let current_distances_in_introducing_order = (0 .. n).map(|i| current_distances[introducing_order[i]]).collect();
-  
-  let table = sys.build_table(&frequencies_in_introducing_order, &current_distances_in_introducing_order);
-  
-  ReferenceData {
-    dictionary,
-    rules,
-    mid,
-    
-    n,
-    current_distances,
-    table,
-    
-    introducing_order,
-    introducing_order_rev,
-    
-    frequencies_in_introducing_order,
-    current_distances_in_introducing_order
+  fn to_checkpoint(&self) -> IterativeSystemCheckpoint {
+    IterativeSystemCheckpoint {
+      schema_version: CHECKPOINT_SCHEMA_VERSION,
+      steps: self.steps,
+      class_contexts: self.class_contexts,
+      settings: self.settings_fingerprint(),
+      rules: self.rules.clone(),
+      words: self.dictionary.words.iter().map(|w| CheckpointWord {
+        transformed_spelling: w.transformed_spelling.clone(),
+        base_pronunciation: w.base_pronunciation.clone(),
+        back_transformed_pronunciation: w.back_transformed_pronunciation.clone(),
+        frequency_bits: w.frequency.to_bits()
+      }).collect()
+    }
   }
-}
-
-pub struct WorkingData<Estimator> {
-  working_table: HashMap<HLSubstitution, WorkingEntry<Estimator>>,
-  best_possible: KeyedPriorityQueue<HLSubstitution, FloatOrd<f64>>,
-  best_possible_rev: KeyedPriorityQueue<HLSubstitution, FloatOrd<f64>>,
   
-  introducing_working_index: usize
-}
-
-fn improving_edits_at_i<'d, T>(r: &ReferenceData<'d, T>, i: usize) -> Vec<SubWithImprovement> {
-  let j = r.introducing_order[i];
-  let w = &r.dictionary.words[j];
-  find_improving_edits(&w.transformed_spelling, &w.back_transformed_pronunciation, &w.base_pronunciation, r.rules, r.current_distances_in_introducing_order[i], r.mid)
-}
-
-pub fn init_working_data<E>() -> WorkingData<E> {
-  let working_table: HashMap<HLSubstitution, WorkingEntry<E>> = HashMap::new();
-  let best_possible: KeyedPriorityQueue<HLSubstitution, FloatOrd<f64>> = KeyedPriorityQueue::new();
-    
-  // This stores working items in *reverse* order by best_possible. Items that cannot be as good
-  // as the worst_possible of the top of the best_possible queue should be deleted.
-  let best_possible_rev: KeyedPriorityQueue<HLSubstitution, FloatOrd<f64>> = KeyedPriorityQueue::new();
-  
-  WorkingData {
-    working_table,
-    best_possible,
-    best_possible_rev,
-    
-    introducing_working_index: 0
-  }
-}
-
-fn estimate_from_introducing_iterator<'d, T: Table<Estimate, Estimator>, Estimate: Clone, Estimator: Clone>(r: &ReferenceData<'d, T>, w: &mut WorkingData<Estimator>, _debug: bool) -> Option<Estimate> {
-  if w.introducing_working_index < r.n {
-    let i = w.introducing_working_index;
-    Some(r.table.estimate_introduce(&r.frequencies_in_introducing_order, i))
-  }
-  else {
-    None
+  fn from_checkpoint(checkpoint: IterativeSystemCheckpoint) -> IterativeSystem {
+    let dictionary = AugDictionary {
+      words: checkpoint.words.into_iter().map(|w| AugDictionaryWord {
+        transformed_spelling: w.transformed_spelling,
+        base_pronunciation: w.base_pronunciation,
+        back_transformed_pronunciation: w.back_transformed_pronunciation,
+        frequency: f64::from_bits(w.frequency_bits)
+      }).collect()
+    };
+    IterativeSystem {
+      index: NgramIndex::build(&dictionary, DEFAULT_MAX_N),
+      dictionary,
+      rules: checkpoint.rules,
+      class_contexts: checkpoint.class_contexts,
+      steps: checkpoint.steps,
+      checkpoint_path: None,
+      objective: Arc::new(WeightedObjective::default()),
+      constraints: Arc::new(Constraints::default()),
+      log: None,
+      last_search: SearchStats::default(),
+      verify: None,
+      last_verification: None,
+      alignment: None
+    }
   }
-}
-
-fn introduce<'d, T: Table<Estimate, Estimator>, Estimate: Clone, Estimator: Clone, S: System<T, Estimate, Estimator>>(sys: &S, r: &ReferenceData<'d, T>, w: &mut WorkingData<Estimator>, debug: bool) {
-  // This occurs if either: (1) there is nothing in the heap, or (2) the
-  // heap top's best_possible is not as good as best_possible_from_introducing_iterator.
-  
-  // Add it to the working table.
-  // Note that we need to consider the introducing_index in initializing
-  // best_possible and worst_possible.
   
-  if w.introducing_working_index >= r.n {
-    return;
+  /// Writes to a temporary file first, so that being interrupted halfway through
+  /// doesn't destroy the previous checkpoint.
+  pub fn save_checkpoint(&self, path: &str) -> Result<(), String> {
+    let temp_path = format!("{}.tmp", path);
+    let file = std::fs::File::create(&temp_path).map_err(|e| format!("{}: {}", temp_path, e))?;
+    serde_json::to_writer(std::io::BufWriter::new(file), &self.to_checkpoint()).map_err(|e| format!("{}: {}", temp_path, e))?;
+    std::fs::rename(&temp_path, path).map_err(|e| format!("{}: {}", path, e))
   }
   
-  let introducing_improving_edits = improving_edits_at_i(r, w.introducing_working_index);
-   
-  let i = w.introducing_working_index;
-  let j = r.introducing_order[i];
-  
-  for edit in introducing_improving_edits {
-    if !w.working_table.contains_key(&edit.sub) {
-      let improvement = edit.improvement;
-      let change = -(improvement as i32);
-      
-      if debug {
-        println!("introduce {:?} (word {})", edit.sub, aug_encode(&r.dictionary.words[j].transformed_spelling));
-        println!("  improvement: from {} to {} ({})", r.current_distances[j], r.current_distances[j]-edit.improvement, edit.improvement);
+  /// The loaded system keeps checkpointing to `path`. The objective, constraints
+  /// and alignment aren't part of the checkpoint, so `configure` has to set them
+  /// again; loading fails if they aren't what the checkpoint was made with.
+  pub fn load_checkpoint(path: &str, configure: &dyn Fn(&mut IterativeSystem) -> Result<(), String>) -> Result<IterativeSystem, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let checkpoint: IterativeSystemCheckpoint = serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))?;
+    if checkpoint.schema_version != CHECKPOINT_SCHEMA_VERSION {
+      return Err(format!("{}: schema version {} is not supported, expected {}", path, checkpoint.schema_version, CHECKPOINT_SCHEMA_VERSION));
+    }
+    checkpoint.rules.check_back_refs().map_err(|e| format!("{}: {}", path, e))?;
+    
+    let settings = checkpoint.settings.clone();
+    let mut res = IterativeSystem::from_checkpoint(checkpoint);
+    configure(&mut res)?;
+    if res.settings_fingerprint() != settings {
+      return Err(format!("{}: the objective, constraints or alignment differ from what the checkpoint was made with", path));
+    }
+    res.checkpoint_path = Some(path.to_owned());
+    Ok(res)
+  }
+  
+  /// A hash of the settings that change what the search finds, besides the
+  /// state itself. The objective can only be compared by what it does, so it's
+  /// probed on the current words and rules.
+  pub fn settings_fingerprint(&self) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+      for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
       }
-      
-      let init_estimate: Estimator = r.table.introduce(&r.frequencies_in_introducing_order, i, change, &edit.sub);
-      let e = sys.calc_estimate(&init_estimate);
-      let size_cost = edit_size_cost(&edit.sub);
-      let best_possible = sys.calc_best_possible(&e).raw() + size_cost;
-      let worst_possible = sys.calc_worst_possible(&e).raw() + size_cost;
-      
-      let entry = WorkingEntry {
-        estimate: init_estimate,
-        best_possible,
-        worst_possible,
-        introducing_index: w.introducing_working_index,
-        next_to_explore_index: 0,
-      };
-      
-      w.working_table.insert(edit.sub.clone(), entry);
-      w.best_possible.push(edit.sub.clone(), FloatOrd(-best_possible));
-      w.best_possible_rev.push(edit.sub.clone(), FloatOrd(best_possible));
+    };
+    
+    let objective = self.search_objective();
+    for w in &self.dictionary.words {
+      let output = self.rules.apply_posterior_copied(&w.transformed_spelling);
+      let spelling = self.rules.deapply_anterior_copied(&w.transformed_spelling);
+      feed(&objective.word_cost(&output, &w.base_pronunciation, &spelling).to_le_bytes());
     }
-    else {
-      if debug { println!("We've seen {:?} before, skipping it.", edit.improvement); }
+    let mut prior = HLSubstitutionList { substitutions: vec![] };
+    for sub in &self.rules.substitutions {
+      feed(&objective.rule_cost(&prior, sub).to_bits().to_le_bytes());
+      prior.substitutions.push(sub.clone());
     }
-  }
-  
-  w.introducing_working_index += 1;
-}
-
-fn cull_working_table<'d, 'h, E>(w: &mut WorkingData<E>, debug: bool) {
-  // Use best_possible_rev to delete working table entries where the best possible is not
-  // better than the worst_possible if the top of best_possible.
-  if debug { println!("cull_working_table"); }
-  
-  let Some(best_top) = w.best_possible.peek() else { return };
-  let best_worst = w.working_table.get(best_top.0).unwrap().worst_possible;
-  
-  if debug { println!("  best_worst = {} ({:?})", best_worst, best_top.0); }
-  
-  loop {
-    if w.best_possible_rev.len() <= 1 {
-      if debug { println!("  Only one edit, stopping cull."); }
-      return;
+    feed(&[objective.has_dictionary_cost() as u8]);
+    
+    let mut protected: Vec<String> = self.constraints.protected.iter().map(|(spelling, forms)| {
+      let mut forms: Vec<String> = forms.iter().map(aug_encode).collect();
+      forms.sort();
+      format!("{} {}", aug_encode(spelling), forms.join(" "))
+    }).collect();
+    protected.sort();
+    let mut must_hit: Vec<String> = self.constraints.must_hit.iter().map(aug_encode).collect();
+    must_hit.sort();
+    feed(format!("{:?} {:?} {}", protected, must_hit, self.constraints.miss_penalty).as_bytes());
+    
+    if let Some(model) = &self.alignment {
+      feed(format!("{} {} {}", model.max_graphemes, model.max_phonemes, model.min_prob).as_bytes());
+      for (graphemes, phonemes, prob) in model.chunks() {
+        feed(format!("{} {} {}", encode(&graphemes), encode(&phonemes), prob).as_bytes());
+      }
     }
     
-    let worst_best = w.best_possible_rev.peek().unwrap().1.0;
-    
-    if worst_best >= best_worst {
-      let sub = w.best_possible_rev.peek().unwrap().0;
-      if debug { println!("  Removing {:?} because {} is not better than {}", sub, worst_best, best_worst); }
-      w.best_possible.remove(sub);
-      w.working_table.remove(sub);
-      w.best_possible_rev.pop();
+    format!("{:016x}", hash)
+  }
+
+  /// The objective with the must-hit penalties added, which is what the searches
+  /// actually minimize.
+  pub fn search_objective(&self) -> Arc<dyn Objective> {
+    if self.constraints.must_hit.is_empty() {
+      self.objective.clone()
     }
     else {
-      if debug { println!("  Stopping cull because {} could beat {}", worst_best, best_worst); }
-      return;
+      Arc::new(ConstrainedObjective { inner: self.objective.clone(), constraints: self.constraints.clone() })
     }
   }
-}
-
-fn new_distance_if_new(transformed_spelling: &Vec<AugGlyph>, base_pronunciation: &Vec<AugGlyph>, sub: &HLSubstitution, rules: &HLSubstitutionList) -> Option<u32> {
-  if let Some(new_step_one) = sub.apply_copied(transformed_spelling) {
-    let new_transformed = rules.apply_posterior_copied(&new_step_one);
-    let new_distance = distance(&new_transformed, base_pronunciation);
-    Some(new_distance)
-  }
-  else {
-    None
-  }
-}
-
-fn change_with_new(transformed_spelling: &Vec<AugGlyph>, base_pronunciation: &Vec<AugGlyph>, sub: &HLSubstitution, rules: &HLSubstitutionList, orig_dist: u32) -> i32 {
-  match new_distance_if_new(transformed_spelling, base_pronunciation, sub, rules) {
-    Some(new_dist) => (new_dist as i32) - (orig_dist as i32),
-    None => 0
-  }
-}
-
-fn calc_change(transformed_spelling: &Vec<AugGlyph>, base_pronunciation: &Vec<AugGlyph>, sub: &HLSubstitution, rules: &HLSubstitutionList, orig_dist: u32) -> i32 {
-  change_with_new(transformed_spelling, base_pronunciation, sub, rules, orig_dist)
-}
-
-fn produce_outcome(best_possible_sub: HLSubstitution, best_possible: f64) -> Outcome {
-  if best_possible < 0.0 {
-    Outcome::FoundImprovement(best_possible_sub, best_possible)
-  }
-  else {
-    Outcome::FailedToFindImprovement(best_possible_sub, best_possible)
+  
+  /// The objective's word costs under the current rules, weighted by frequency
+  /// and divided by the total frequency, so that dictionaries of different sizes
+  /// can be compared.
+  pub fn mean_word_cost(&self) -> f64 {
+    let total: f64 = self.dictionary.words.iter().map(|w| w.frequency).sum();
+    let cost: f64 = self.dictionary.words.par_iter().map(|w| {
+      let output = self.rules.apply_posterior_copied(&w.transformed_spelling);
+      let spelling = self.rules.deapply_anterior_copied(&w.transformed_spelling);
+      w.frequency * (self.objective.word_cost(&output, &w.base_pronunciation, &spelling) as f64)
+    }).sum();
+    if total > 0.0 { cost / total } else { 0.0 }
+  }
+  
+  /// Every constraint the current rules break.
+  pub fn violations(&self) -> Vec<Violation> {
+    let (dictionary, _) = HLEditList::from_state(&self.dictionary, &self.rules);
+    let outputs: Vec<Vec<AugGlyph>> = self.dictionary.words.iter().map(|w| self.rules.apply_posterior_copied(&w.transformed_spelling)).collect();
+    self.constraints.violations(&dictionary, &outputs)
+  }
+  
+  /// Applies `sub` to the dictionary and adds it to the rules.
+  pub fn push_rule(&mut self, sub: HLSubstitution) {
+    for (i, w) in self.dictionary.words.iter_mut().enumerate() {
+      let old = w.transformed_spelling.clone();
+      if sub.apply_anterior(&mut w.transformed_spelling) {
+        self.index.update(i, &old, &w.transformed_spelling);
+      }
+      sub.deapply_posterior(&mut w.back_transformed_pronunciation);
+    }
+    self.rules.substitutions.push(sub);
   }
-}
-
-fn check_for_winner<'d, T: Table<Estimate, Estimator>, Estimate: Clone, Estimator: Clone>(r: &ReferenceData<'d, T>, w: &WorkingData<Estimator>, best_possible_from_introducing_iterator: Option<f64>, debug: bool) -> Option<Outcome> {
-  let best_possible_sub = w.best_possible.peek().unwrap().0.clone();
   
-  if debug { println!("advancing {:?}", best_possible_sub); }
+  /// push_rule for a rule a search settled on: it counts as a step, and is
+  /// checkpointed if there's a checkpoint path.
+  pub fn commit_rule(&mut self, sub: HLSubstitution) {
+    self.push_rule(sub);
+    self.steps += 1;
+    
+    // Losing a checkpoint shouldn't throw away the search that's running.
+    if let Some(path) = &self.checkpoint_path {
+      if let Err(e) = self.save_checkpoint(path) {
+        eprintln!("Couldn't save checkpoint: {}", e);
+      }
+    }
+  }
   
-  let working_table_len = w.working_table.len();
-  let working = w.working_table.get(&best_possible_sub).unwrap();
+  pub fn rebuild_index(&mut self) {
+    self.index = NgramIndex::build(&self.dictionary, DEFAULT_MAX_N);
+  }
   
-  if working_table_len == 1 && best_possible_from_introducing_iterator.map_or_else(|| true, |b| working.worst_possible <= b) {
-    if debug { println!("Found the winner by priority: {:?} {}/{} {} {} {:?}", best_possible_sub, working.next_to_explore_index, r.n, working.best_possible, working.worst_possible, best_possible_from_introducing_iterator); }
-    Some(produce_outcome(best_possible_sub, working.best_possible))
-  }
-  else {
-    let j = working.next_to_explore_index;
-    
-    if j >= r.n {
-      // This case can really only happen due to rounding error.
-      // At this point, we know:
-      // * Its best_possible is equal to its worst_possible
-      // * Its best_possible is at least as good as any other best_possible in the table
-      // * Its best_possible is at least as good as the best possible from introducing iterator
-      if debug { println!("Found the winner by default: {:?}", best_possible_sub); }
-      Some(produce_outcome(best_possible_sub, working.best_possible))
+  /// The searches estimate word by word, so they can't honour dictionary costs.
+  pub fn check_objective(&self) -> Result<(), String> {
+    if self.objective.has_dictionary_cost() {
+      Err("the objective has dictionary costs, which only beam search and annealing can score".to_owned())
     }
     else {
-      None
+      Ok(())
     }
   }
-}
-
-struct WorkIntermediate<E> {
-  estimate: E,
-  next_to_explore_index: usize
-}
-
-fn advance_many<'d, T: Table<Estimate, Estimator>, Estimate: Clone, Estimator: Clone>(r: &ReferenceData<'d, T>, sub: &HLSubstitution, working: &WorkingEntry<Estimator>, num_to_advance: usize, debug: bool) -> WorkIntermediate<Estimator> {
-  let mut j = working.next_to_explore_index;
-  let mut k = 0;
-  let mut estimate = working.estimate.clone();
   
-  while k < num_to_advance && j < r.n {
-    let i = r.introducing_order_rev[j];
-    
-    if debug { println!("  word = {}", aug_encode(&r.dictionary.words[j].transformed_spelling)); }
-    
-    if i == working.introducing_index {
-      if debug { println!("  {} {}/{} was seen before, moving on.", aug_encode(&r.dictionary.words[j].transformed_spelling), j, i); }
-      // Nothing to do, we've already seen this one
+  /// Candidates that would rewrite a protected word are never proposed.
+  pub fn find_next_rule<T: genastarlike::Table<Estimate, Estimator, HLSubstitution> + Send + Sync, Estimate: Clone + Send + Sync, Estimator: Clone + Send + Sync, S: genastarlike::EstimationSystem<T, Estimate, Estimator, HLSubstitution> + Send + Sync>(&mut self, sys: &S, debug: bool) -> Outcome {
+    if let Err(e) = self.check_objective() {
+      return Outcome::Refused(e);
     }
-    else {
-      let word = &r.dictionary.words[j];
+    let objective = self.search_objective();
+    let guard = self.constraints.guard(&self.rules);
+    let edit_sys = HLEditSystem {
+      mid: self.rules.next_open_mid(),
+      class_contexts: self.class_contexts,
+      objective: &*objective,
+      guard: &guard,
+      index: Some(&self.index),
+      alignment: self.alignment.as_deref()
+    };
+    let (dictionary, edit_list) = HLEditList::from_state(&self.dictionary, &self.rules);
+    let r = genastarlike::init_ref_data(sys, &edit_sys, &dictionary, &edit_list);
+    let mut w = genastarlike::init_working_data();
+    if let Some(mut log) = self.log.take() {
+      log.rule = self.steps;
+      w.set_log(Some(log));
+    }
+    if self.verify.is_some() {
+      w.keep_culled();
+    }
+    let mut steps = 0;
+    
+    loop {
+      steps += 1;
+      let outcome = match genastarlike::step(sys, &edit_sys, &r, &mut w, debug) {
+        None => continue,
+        Some(genastarlike::Outcome::FoundImprovement(sub, change)) => Outcome::FoundImprovement(sub, change),
+        Some(genastarlike::Outcome::FailedToFindImprovement(sub, change)) => Outcome::FailedToFindImprovement(sub, change),
+        Some(genastarlike::Outcome::Exhausted) => Outcome::Exhausted
+      };
       
-      let change = calc_change(&word.transformed_spelling, &word.base_pronunciation, sub, r.rules, r.current_distances[j]);
+      self.last_verification = match (&outcome, self.verify) {
+        (Outcome::FoundImprovement(sub, _) | Outcome::FailedToFindImprovement(sub, _), Some(runner_ups)) => Some(genastarlike::verify(&edit_sys, &r, &mut w, sub, runner_ups)),
+        _ => None
+      };
+      
+      self.log = w.set_log(None);
+      if let Some(log) = &mut self.log {
+        log.flush();
+      }
+      self.last_search = SearchStats { steps, words_explored: w.words_explored() };
       
-      if debug {
-        println!("  old distance = {}", r.current_distances[j]);
-        println!("  change = {}", change);
+      if let Outcome::FoundImprovement(sub, _) = &outcome {
+        self.commit_rule(sub.clone());
       }
       
-      estimate = r.table.update_edit(
-        &r.frequencies_in_introducing_order,
-        &r.current_distances_in_introducing_order,
-        working.introducing_index,
-        i,
-        change,
-        estimate
-      );
+      return outcome;
     }
-    
-    k += 1;
-    j += 1;
-  }
-  
-  WorkIntermediate {
-    estimate,
-    next_to_explore_index: j
   }
 }
 
-struct AdvancingWork<Estimator> {
-  edit: HLSubstitution,
-  working: WorkingEntry<Estimator>
-}
-
-struct AdvancingResult<Estimator> {
-  edit: HLSubstitution,
-  estimate: Estimator,
-  best_possible: f64,
-  worst_possible: f64,
-  next_to_explore_index: usize
-}
-
-fn do_work<'d, T: Table<Estimate, Estimator> + Send + Sync, Estimate: Clone + Send + Sync, Estimator: Clone + Send + Sync, S: System<T, Estimate, Estimator> + Send + Sync>(sys: &S, r: &ReferenceData<'d, T>, w: &mut WorkingData<Estimator>, debug: bool) {
-  // To take advantage of multiple CPU cores, we process edits in chunks
-  let edit_chunk_size = 256;
-  let steps_chunk_size = 256;
-  
-  // Go explore the next word and update best_possible and worst_possible accordingly. Note
-  // that in updating best_possible, we need to consider this entry's introducing_index,
-  // since that will tell us which words it could conceivably improve.
-  
-  let mut edit_chunk: Vec<AdvancingWork<Estimator>> = vec![];
-  for _ in 0 .. edit_chunk_size {
-    if w.best_possible.is_empty() {
-      break;
-    }
-      
-    let sub = w.best_possible.pop().unwrap().0;
-    let working = (*w.working_table.get(&sub).unwrap()).clone();
-    edit_chunk.push(AdvancingWork {
-      edit: sub,
-      working
-    });
-  }
-  
-  let result_chunk: Vec<AdvancingResult<Estimator>> = edit_chunk.into_par_iter().map(|work| {
-    let best_possible_sub = work.edit;
-    let working = work.working;
-    
-    if debug { println!("advancing {:?}", best_possible_sub); }
-    
-    let intermediate = advance_many(r, &best_possible_sub, &working, steps_chunk_size, debug);
-    
-    let e = sys.calc_estimate(&intermediate.estimate);
-    let size_cost = edit_size_cost(&best_possible_sub);
-    let best_possible = sys.calc_best_possible(&e).raw() + size_cost;
-    let worst_possible = sys.calc_worst_possible(&e).raw() + size_cost;
-    
-    AdvancingResult {
-      edit: best_possible_sub,
-      estimate: intermediate.estimate,
-      best_possible,
-      worst_possible,
-      next_to_explore_index: intermediate.next_to_explore_index
-    }
-  }).collect();
-  
-  for result in result_chunk {
-    w.best_possible_rev.set_priority(&result.edit, FloatOrd(result.best_possible)).unwrap();
-    w.best_possible.push(result.edit.clone(), FloatOrd(-result.best_possible));
+/// Proposes and scores high_level_substitutions2 edits for genastarlike.
+pub struct HLEditSystem<'o> {
+  /// The mid that proposed edits get.
+  pub mid: u32,
+  pub class_contexts: bool,
+  /// Its word costs are what's estimated, and its rule costs are the edits'
+  /// size costs. It mustn't have dictionary costs, since nothing here sees them.
+  pub objective: &'o dyn Objective,
+  /// Has to have been made for the same prior rules.
+  pub guard: &'o ProtectionGuard,
+  /// If set, the search and exact scores only score the words an edit's key
+  /// appears in; the rest are taken to be unchanged. Has to index the
+  /// dictionary the prior rules were recovered from.
+  pub index: Option<&'o NgramIndex>,
+  /// If set, only what each word's alignment suggests is proposed.
+  pub alignment: Option<&'o AlignmentModel>
+}
+
+/// The rules found so far, along with what they've already done to each spelling
+/// and pronunciation, so that scoring an edit doesn't mean replaying every rule.
+pub struct HLEditList {
+  pub rules: HLSubstitutionList,
+  transformed_spellings: HashMap<Vec<Glyph>, Vec<AugGlyph>>,
+  back_transformed_pronunciations: HashMap<Vec<Glyph>, Vec<AugGlyph>>
+}
+
+impl HLEditList {
+  /// genastarlike only deals in plain words, so this also recovers the dictionary
+  /// that `dictionary` was transformed from.
+  pub fn from_state(dictionary: &AugDictionary, rules: &HLSubstitutionList) -> (Dictionary, HLEditList) {
+    let mut transformed_spellings = HashMap::new();
+    let mut back_transformed_pronunciations = HashMap::new();
+    
+    let words = dictionary.words.iter().map(|w| {
+      let spelling = strip_aug(&rules.deapply_anterior_copied(&w.transformed_spelling));
+      let pronunciation = strip_aug(&w.base_pronunciation);
+      transformed_spellings.insert(spelling.clone(), w.transformed_spelling.clone());
+      back_transformed_pronunciations.insert(pronunciation.clone(), w.back_transformed_pronunciation.clone());
+      DictionaryWord {
+        spelling,
+        pronunciation,
+        frequency: w.frequency
+      }
+    }).collect();
     
-    let working = w.working_table.get_mut(&result.edit).unwrap();
-    working.estimate = result.estimate;
-    working.best_possible = result.best_possible;
-    working.worst_possible = result.worst_possible;
-    working.next_to_explore_index = result.next_to_explore_index;
+    (Dictionary { words }, HLEditList { rules: rules.clone(), transformed_spellings, back_transformed_pronunciations })
   }
 }
 
-fn dump_state<'d, T, E>(r: &ReferenceData<'d, T>, w: &mut WorkingData<E>) {
-  for j in 0 .. (r.dictionary.words.len()+1) {
-    for (edit, w) in w.working_table.iter() {
-      if w.next_to_explore_index == j {
-        println!("          {:>10} bp={:>4.1} wp={:>4.1}", format!("{:?}", edit), w.best_possible, w.worst_possible);
-      }
-    }
-    if j < r.dictionary.words.len() {
-      println!("  {:>4} f={:>4.2} d={:>3}", aug_encode(&r.dictionary.words[j].transformed_spelling), r.dictionary.words[j].frequency, r.current_distances[j]);
-    }
+impl std::fmt::Debug for HLEditList {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_list().entries(self.rules.substitutions.iter()).finish()
   }
 }
 
-pub fn step<'d, T: Table<Estimate, Estimator> + Send + Sync, Estimate: Clone + Send + Sync, Estimator: Clone + Send + Sync, S: System<T, Estimate, Estimator> + Send + Sync>(sys: &S, r: &ReferenceData<'d, T>, w: &mut WorkingData<Estimator>, debug: bool) -> Option<Outcome> {
-  if debug { println!("step"); }
+impl<'o> genastarlike::EditSystem<HLSubstitution, HLEditList> for HLEditSystem<'o> {
+  fn find_improving_edits(&self, spelling: &Vec<Glyph>, pronunciation: &Vec<Glyph>, prior_rules: &HLEditList) -> Vec<genastarlike::SubWithImprovement<HLSubstitution>> {
+    let base_cost = self.distance(prior_rules, spelling, pronunciation);
+    let (augmented, original) = (augment(pronunciation), augment(spelling));
+    let transformed_spelling = &prior_rules.transformed_spellings[spelling];
+    let back_transformed_pronunciation = &prior_rules.back_transformed_pronunciations[pronunciation];
+    let cost = |out: &[AugGlyph]| self.objective.word_cost(out, &augmented, &original);
+    let edits = match self.alignment {
+      Some(model) => model.find_improving_edits(spelling, pronunciation, transformed_spelling, back_transformed_pronunciation, &prior_rules.rules, base_cost, self.mid, self.class_contexts, &cost),
+      None => find_improving_edits(transformed_spelling, back_transformed_pronunciation, &prior_rules.rules, base_cost, self.mid, self.class_contexts, &cost)
+    };
+    edits.into_iter().filter(|s| self.guard.rejects(&prior_rules.rules, &s.sub).is_none()).map(|s| genastarlike::SubWithImprovement {
+      size_cost: self.size_cost(prior_rules, &s.sub),
+      sub: s.sub,
+      improvement: s.improvement
+    }).collect()
+  }
   
-  if debug { dump_state(r, w); }
+  fn distance(&self, rules: &HLEditList, spelling: &Vec<Glyph>, pronunciation: &Vec<Glyph>) -> u32 {
+    let output = rules.rules.apply_posterior_copied(&rules.transformed_spellings[spelling]);
+    self.objective.word_cost(&output, &augment(pronunciation), &augment(spelling))
+  }
   
-  if w.working_table.is_empty() {
-    if debug { println!("Working table is empty."); }
-    introduce(sys, r, w, debug);
-    None
-  }
-  else {
-    // Cull the working table by starting with the top of best_possible_rev.
-    // This means that once the working table is down to a single element, and that element's
-    // worst_possible is better than best_possible_from_introducing_iterator, the algorithm
-    // may terminate.
-    cull_working_table(w, debug);
-    
-    let estimate_from_introducing_iterator = estimate_from_introducing_iterator(r, w, debug);
-    let best_possible_from_introducing_iterator = estimate_from_introducing_iterator.map(|e| sys.calc_best_possible(&e).raw());
-    
-    let best_possible_top = w.best_possible.peek().unwrap();
-    let best_possible_from_working_table = -best_possible_top.1.0;
-    
-    if debug { println!("best_possible_from_working_table = {}", best_possible_from_working_table); }
-    
-    let intro_iter_is_better = best_possible_from_introducing_iterator.map_or_else(|| false, |b| b < best_possible_from_working_table);
-    
-    if debug { println!("Best possible:"); }
-    if debug { println!("  from new ones: {:?} (word {}/{}/{}) {}",
-      best_possible_from_introducing_iterator,
-      if w.introducing_working_index < r.n { aug_encode(&r.dictionary.words[r.introducing_order_rev[w.introducing_working_index]].transformed_spelling) } else { "NA".to_owned() },
-      w.introducing_working_index,
-      if w.introducing_working_index < r.n { r.introducing_order_rev[w.introducing_working_index] } else { r.n },
-      if intro_iter_is_better {"*"} else {""}
-    ); }
-    if debug { println!("  from old ones: {:.2} ({:?}) {}", best_possible_from_working_table, best_possible_top.0, if intro_iter_is_better {""} else {"*"}); }
-    
-    if intro_iter_is_better {
-      introduce(sys, r, w, debug);
-      None
-    }
-    else {
-      if let Some(res) = check_for_winner(r, w, best_possible_from_introducing_iterator, debug) {
-        Some(res)
-      }
-      else {
-        do_work(sys, r, w, debug);
-        None
-      }
-    }
+  fn new_distance(&self, prior_rules: &HLEditList, new_rule: &HLSubstitution, spelling: &Vec<Glyph>, pronunciation: &Vec<Glyph>) -> Option<u32> {
+    let new_step_one = new_rule.apply_copied(&prior_rules.transformed_spellings[spelling])?;
+    let output = prior_rules.rules.apply_posterior_copied(&new_step_one);
+    Some(self.objective.word_cost(&output, &augment(pronunciation), &augment(spelling)))
   }
-}
-
-pub struct IterativeSystem {
-  pub dictionary: AugDictionary,
-  pub rules: HLSubstitutionList,
-}
-
-impl IterativeSystem {
-  pub fn setup(dictionary: &Dictionary, init_rules: HLSubstitutionList) -> IterativeSystem {
-    IterativeSystem {
-      dictionary: AugDictionary {
-        words: dictionary.words.iter().map(|w| {
-          let base_pronunciation: Vec<AugGlyph> = w.pronunciation.iter().map(|g| AugGlyph::Real(*g)).collect();
-          AugDictionaryWord {
-            transformed_spelling: w.spelling.iter().map(|g| AugGlyph::Real(*g)).collect(),
-            back_transformed_pronunciation: base_pronunciation.clone(),
-            base_pronunciation,
-            frequency: w.frequency
-          }
-        }).collect()
-      },
-      rules: init_rules
-    }
+  
+  fn size_cost(&self, prior_rules: &HLEditList, new_rule: &HLSubstitution) -> f64 {
+    self.objective.rule_cost(&prior_rules.rules, new_rule)
   }
-
-  pub fn find_next_rule<'d, T: Table<Estimate, Estimator> + Send + Sync, Estimate: Clone + Send + Sync, Estimator: Clone + Send + Sync, S: System<T, Estimate, Estimator> + Send + Sync>(&mut self, sys: &S, debug: bool) -> Outcome {
-    let r = init_ref_data(sys, &self.dictionary, &self.rules, self.rules.next_open_mid());
-    let mut w = init_working_data();
-    
-    loop {
-      let outcome = step(sys, &r, &mut w, debug);
-      
-      if let Some(outcome) = outcome {
-        if let Outcome::FoundImprovement(sub, _) = &outcome {
-          self.rules.substitutions.push(sub.clone());
-          for w in self.dictionary.words.iter_mut() {
-            sub.apply_anterior(&mut w.transformed_spelling);
-            sub.deapply_posterior(&mut w.back_transformed_pronunciation);
-          }
-        }
-        
-        return outcome;
-      }
-    }
+  
+  fn affected_words(&self, _prior_rules: &HLEditList, edit: &HLSubstitution) -> Option<Vec<usize>> {
+    self.index.map(|index| index.affected_by(edit))
   }
 }
 
@@ -666,6 +614,7 @@ mod tests {
   use super::*;
   use std::collections::HashMap;
   use crate::glyphs::aug_decode;
+  use noisy_float::prelude::*;
   
   #[derive(Clone)]
   struct Test1Estimator {
@@ -689,7 +638,7 @@ mod tests {
     complete_table: Vec<Test1Word>
   }
   
-  impl Table<Test1Estimate, Test1Estimator> for Test1Table {
+  impl genastarlike::Table<Test1Estimate, Test1Estimator, HLSubstitution> for Test1Table {
     fn introduce(
       &self,
       _frequencies_in_introducing_order: &Vec<f64>,
@@ -736,7 +685,7 @@ mod tests {
     complete_table: Vec<Test1Word>
   }
   
-  impl System<Test1Table, Test1Estimate, Test1Estimator> for Test1System {
+  impl genastarlike::EstimationSystem<Test1Table, Test1Estimate, Test1Estimator, HLSubstitution> for Test1System {
     fn build_table(
       &self,
       _frequencies_in_introducing_order: &Vec<f64>,
@@ -803,20 +752,21 @@ mod tests {
     
     println!("Initializing ref data...");
     let rules = HLSubstitutionList { substitutions: vec![] };
-    let r = init_ref_data(&system, &dictionary, &rules, 0);
+    let objective = WeightedObjective::default();
+    let guard = Constraints::default().guard(&rules);
+    let edit_sys = HLEditSystem { mid: 0, class_contexts: false, objective: &objective, guard: &guard, index: None, alignment: None };
+    let (plain, edit_list) = HLEditList::from_state(&dictionary, &rules);
+    let r = genastarlike::init_ref_data(&system, &edit_sys, &plain, &edit_list);
     
     println!("Initializing working data...");
-    let mut w = init_working_data();
-    
-    println!("current_distances = {:?}", r.current_distances);
-    println!("introducing_order = {:?}", r.introducing_order);
+    let mut w = genastarlike::init_working_data();
     println!("");
     
     let mut the_winner: Option<HLSubstitution> = None;
     
     for _ in 0 .. 10 {
-      if let Some(outcome) = step(&system, &r, &mut w, true) {
-        if let Outcome::FoundImprovement(winner, _) = outcome {
+      if let Some(outcome) = genastarlike::step(&system, &edit_sys, &r, &mut w, true) {
+        if let genastarlike::Outcome::FoundImprovement(winner, _) = outcome {
           the_winner = Some(winner);
           break;
         }
@@ -832,12 +782,26 @@ mod tests {
   
   #[test]
   fn with_new_test_1() {
-    let spelling = aug_decode("something");
-    let pronunciation = aug_decode("sʌmθɪŋ");
+    use genastarlike::EditSystem;
+    
+    let dictionary = AugDictionary {
+      words: vec![AugDictionaryWord {
+        transformed_spelling: aug_decode("something"),
+        back_transformed_pronunciation: aug_decode("sʌmθɪŋ"),
+        base_pronunciation: aug_decode("sʌmθɪŋ"),
+        frequency: 1.0
+      }]
+    };
+    let rules = HLSubstitutionList { substitutions: vec![] };
+    let objective = WeightedObjective::default();
+    let guard = Constraints::default().guard(&rules);
+    let edit_sys = HLEditSystem { mid: 0, class_contexts: false, objective: &objective, guard: &guard, index: None, alignment: None };
+    let (plain, edit_list) = HLEditList::from_state(&dictionary, &rules);
+    let w = &plain.words[0];
     
     let sub = HLSubstitution::decode("[thi]→0→ϑɪs").unwrap();
     
-    assert!(new_distance_if_new(&spelling, &pronunciation, &sub, &HLSubstitutionList { substitutions: vec![] }).is_some());
+    assert!(edit_sys.new_distance(&edit_list, &sub, &w.spelling, &w.pronunciation).is_some());
   }
   
   #[test]
@@ -898,6 +862,13 @@ mod tests {
     assert_eq!(iter_system.dictionary.words[0].back_transformed_pronunciation, aug_decode("{0}t"));
     assert_eq!(iter_system.dictionary.words[1].transformed_spelling, aug_decode("{0}s"));
     assert_eq!(iter_system.dictionary.words[1].back_transformed_pronunciation, aug_decode("{0}z"));
+    
+    iter_system.objective = Arc::new(WeightedObjective {
+      weights: crate::objective::ObjectiveWeights { homographs: 1.0, ..Default::default() },
+      ..WeightedObjective::default()
+    });
+    assert!(matches!(iter_system.find_next_rule(&system, false), Outcome::Refused(_)));
+    assert_eq!(iter_system.rules.substitutions.len(), 1);
   }
 }
 
@@ -0,0 +1,332 @@
+
+/* Beam search over sequences of rules.
+ *
+ * IterativeSystem::find_next_rule commits to the single best next rule, which
+ * misses rules that only pay off together, like one that introduces a mid and
+ * another that consumes it. This keeps the best `width` partial rule lists
+ * instead, extends each of them with candidates from find_improving_edits, and
+ * after `depth` rules picks the list with the lowest cumulative score.
+ *
+ * Candidates are scored exactly against the whole dictionary, so this is only
+ * practical on a subset of it. With a width of 1 it's a plain greedy search over
+ * the same candidates, which is what the comparison report measures against.
+ * Wider beams keep greedy's state alongside their best ones, so they never end
+ * up worse than it.
+ */
+
+use crate::glyphs::{AugGlyph, augment};
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList};
+use crate::astarlike2::{AugDictionary, HLEditList, IterativeSystem, find_improving_edits};
+use crate::objective::{Objective, rules_cost, weighted_word_cost};
+use crate::dictionary::Dictionary;
+use crate::constraints::Constraints;
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use float_ord::FloatOrd;
+use rayon::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct BeamConfig {
+  /// How many partial rule lists to keep (K).
+  pub width: usize,
+  /// How many rules to look ahead (D).
+  pub depth: usize,
+  /// How many of the worst words each list proposes candidates from.
+  pub seed_words: usize,
+  /// How many of those candidates are scored exactly, per list.
+  pub candidates: usize,
+  pub class_contexts: bool
+}
+
+impl Default for BeamConfig {
+  fn default() -> BeamConfig {
+    BeamConfig {
+      width: 4,
+      depth: 3,
+      seed_words: 20,
+      candidates: 32,
+      class_contexts: false
+    }
+  }
+}
+
+#[derive(Clone)]
+struct BeamState {
+  dictionary: AugDictionary,
+  rules: HLSubstitutionList,
+  /// What the rules produce for each word, and what that costs.
+  outputs: Vec<Vec<AugGlyph>>,
+  word_costs: Vec<u32>,
+  rule_cost: f64,
+  added: Vec<HLSubstitution>,
+  score: f64,
+  /// The dictionary the rules started from, for the objective's spelling terms
+  /// and dictionary costs.
+  original: Arc<Dictionary>,
+  objective: Arc<dyn Objective>,
+  constraints: Arc<Constraints>
+}
+
+impl BeamState {
+  fn new(system: &IterativeSystem) -> BeamState {
+    let (original, _) = HLEditList::from_state(&system.dictionary, &system.rules);
+    let outputs: Vec<Vec<AugGlyph>> = system.dictionary.words.par_iter().map(|w| system.rules.apply_posterior_copied(&w.transformed_spelling)).collect();
+    let word_costs: Vec<u32> = outputs.par_iter().zip(original.words.par_iter()).map(|(output, w)| {
+      system.objective.word_cost(output, &augment(&w.pronunciation), &augment(&w.spelling))
+    }).collect();
+    let rule_cost = rules_cost(&*system.objective, &system.rules);
+
+    let mut res = BeamState {
+      dictionary: system.dictionary.clone(),
+      rules: system.rules.clone(),
+      outputs,
+      word_costs,
+      rule_cost,
+      added: vec![],
+      score: 0.0,
+      original: Arc::new(original),
+      objective: system.search_objective(),
+      constraints: system.constraints.clone()
+    };
+    res.score = res.total(&res.outputs, &res.word_costs, res.rule_cost);
+    res
+  }
+
+  fn total(&self, outputs: &[Vec<AugGlyph>], word_costs: &[u32], rule_cost: f64) -> f64 {
+    weighted_word_cost(word_costs, &self.original) + rule_cost + self.objective.dictionary_cost(outputs, &self.original)
+  }
+
+  /// Candidate edits, best first by how much they help the words they came from.
+  fn candidates(&self, config: &BeamConfig) -> Vec<HLSubstitution> {
+    let mid = self.rules.next_open_mid();
+
+    let mut worst: Vec<usize> = (0 .. self.dictionary.words.len()).filter(|&i| self.word_costs[i] > 0).collect();
+    worst.sort_by_key(|&i| FloatOrd(-self.dictionary.words[i].frequency * (self.word_costs[i] as f64)));
+    worst.truncate(config.seed_words);
+
+    let mut promise: HashMap<HLSubstitution, f64> = HashMap::new();
+    for i in worst {
+      let w = &self.dictionary.words[i];
+      let original = &self.original.words[i];
+      let (pronunciation, spelling) = (augment(&original.pronunciation), augment(&original.spelling));
+      let cost = |out: &[AugGlyph]| self.objective.word_cost(out, &pronunciation, &spelling);
+      for s in find_improving_edits(&w.transformed_spelling, &w.back_transformed_pronunciation, &self.rules, self.word_costs[i], mid, config.class_contexts, &cost) {
+        *promise.entry(s.sub).or_insert(0.0) += w.frequency * (s.improvement as f64);
+      }
+    }
+
+    let guard = self.constraints.guard(&self.rules);
+    let mut res: Vec<(HLSubstitution, f64)> = promise.into_iter().filter(|(s, _)| guard.rejects(&self.rules, s).is_none()).collect();
+    // Ties are broken by the encoding so that runs are reproducible.
+    res.sort_by_cached_key(|(s, p)| (FloatOrd(-p + self.objective.rule_cost(&self.rules, s)), s.encode()));
+    res.truncate(config.candidates);
+    res.into_iter().map(|(s, _)| s).collect()
+  }
+
+  /// The outputs and word costs with `sub` added. Only words that `sub` matches
+  /// can change.
+  fn outputs_with(&self, sub: &HLSubstitution, rules: &HLSubstitutionList) -> (Vec<Vec<AugGlyph>>, Vec<u32>) {
+    self.dictionary.words.par_iter().zip(self.original.words.par_iter()).enumerate().map(|(i, (w, original))| {
+      let mut working = w.transformed_spelling.clone();
+      if sub.apply_anterior(&mut working) {
+        let output = rules.apply_posterior_copied(&working);
+        let cost = self.objective.word_cost(&output, &augment(&original.pronunciation), &augment(&original.spelling));
+        (output, cost)
+      }
+      else {
+        (self.outputs[i].clone(), self.word_costs[i])
+      }
+    }).unzip()
+  }
+
+  /// The score of this list with `sub` added, without building the new state.
+  fn score_with(&self, sub: &HLSubstitution) -> f64 {
+    let rules = self.with_rule(sub);
+    let (outputs, word_costs) = self.outputs_with(sub, &rules);
+    self.total(&outputs, &word_costs, self.rule_cost + self.objective.rule_cost(&self.rules, sub))
+  }
+
+  fn with_rule(&self, sub: &HLSubstitution) -> HLSubstitutionList {
+    let mut rules = self.rules.clone();
+    rules.substitutions.push(sub.clone());
+    rules
+  }
+
+  fn extended(&self, sub: &HLSubstitution, score: f64) -> BeamState {
+    let rules = self.with_rule(sub);
+    let (outputs, word_costs) = self.outputs_with(sub, &rules);
+    let mut dictionary = self.dictionary.clone();
+    for w in dictionary.words.iter_mut() {
+      sub.apply_anterior(&mut w.transformed_spelling);
+      sub.deapply_posterior(&mut w.back_transformed_pronunciation);
+    }
+    let mut added = self.added.clone();
+    added.push(sub.clone());
+    BeamState {
+      dictionary,
+      outputs,
+      word_costs,
+      rule_cost: self.rule_cost + self.objective.rule_cost(&self.rules, sub),
+      rules,
+      added,
+      score,
+      original: self.original.clone(),
+      objective: self.objective.clone(),
+      constraints: self.constraints.clone()
+    }
+  }
+
+  // Two lists that reach the same words by different orderings are the same state.
+  fn key(&self) -> Vec<Vec<AugGlyph>> {
+    self.dictionary.words.iter().map(|w| w.transformed_spelling.clone()).collect()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct BeamResult {
+  /// The rules the search would add, in order. Can be shorter than the depth if
+  /// nothing improved the score any further.
+  pub rules: Vec<HLSubstitution>,
+  pub score_before: f64,
+  pub score_after: f64
+}
+
+/// Searches for the best `config.depth` rules to add to `system`'s rules, scored
+/// exactly by `system.search_objective()` and never rewriting protected words.
+pub fn beam_search(system: &IterativeSystem, config: &BeamConfig) -> BeamResult {
+  let start = BeamState::new(system);
+  let score_before = start.score;
+
+  let mut beam = vec![start];
+  let mut best = beam[0].clone();
+  // Where in the beam the state a greedy search would have reached is, if it
+  // hasn't stopped. It's always kept, so the beam can't end up worse than greedy.
+  let mut greedy = Some(0);
+  let order = |s: &BeamState| (FloatOrd(s.score), s.added.iter().map(|s| s.encode()).collect::<Vec<_>>());
+
+  for _ in 0 .. config.depth {
+    let mut next: Vec<BeamState> = vec![];
+    let mut greedy_next: Option<BeamState> = None;
+    for (k, state) in beam.iter().enumerate() {
+      let scored: Vec<(HLSubstitution, f64)> = state.candidates(config).into_iter().map(|s| {
+        let score = state.score_with(&s);
+        (s, score)
+      }).filter(|(_, score)| *score < state.score).collect();
+      let children: Vec<BeamState> = scored.iter().map(|(s, score)| state.extended(s, *score)).collect();
+      if greedy == Some(k) {
+        greedy_next = children.iter().min_by_key(|s| order(s)).cloned();
+      }
+      next.extend(children);
+    }
+
+    if next.is_empty() {
+      break;
+    }
+
+    next.sort_by_cached_key(order);
+    let mut seen: HashSet<Vec<Vec<AugGlyph>>> = HashSet::new();
+    next.retain(|s| seen.insert(s.key()));
+    next.truncate(config.width.max(1));
+
+    greedy = greedy_next.map(|g| match next.iter().position(|s| s.added == g.added) {
+      Some(k) => k,
+      None => {
+        next.push(g);
+        next.len() - 1
+      }
+    });
+
+    for state in &next {
+      if state.score < best.score {
+        best = state.clone();
+      }
+    }
+    beam = next;
+  }
+
+  BeamResult {
+    rules: best.added,
+    score_before,
+    score_after: best.score
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct BeamComparison {
+  pub beam: BeamResult,
+  pub greedy: BeamResult
+}
+
+impl BeamComparison {
+  /// How much lower the beam's score is than greedy's. Never negative, since the
+  /// beam always keeps greedy's path.
+  pub fn improvement(&self) -> f64 {
+    self.greedy.score_after - self.beam.score_after
+  }
+}
+
+/// Runs the search and a greedy search over the same candidates and depth.
+pub fn compare_with_greedy(system: &IterativeSystem, config: &BeamConfig) -> BeamComparison {
+  let greedy_config = BeamConfig {
+    width: 1,
+    ..config.clone()
+  };
+  BeamComparison {
+    beam: beam_search(system, config),
+    greedy: beam_search(system, &greedy_config)
+  }
+}
+
+impl IterativeSystem {
+  /// Adds the first rule of the best sequence that beam search finds, or returns
+  /// None if no sequence improves on the current rules.
+  pub fn find_next_rule_beam(&mut self, config: &BeamConfig) -> Option<HLSubstitution> {
+    let result = beam_search(self, config);
+    let sub = result.rules.into_iter().next()?;
+    self.commit_rule(sub.clone());
+    Some(sub)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dictionary::from_words as dictionary;
+
+  #[test]
+  fn beam_not_worse_than_greedy_on_subset() {
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(30);
+    let system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+
+    let config = BeamConfig {
+      width: 3,
+      depth: 2,
+      seed_words: 5,
+      candidates: 8,
+      class_contexts: false
+    };
+    let comparison = compare_with_greedy(&system, &config);
+
+    assert_eq!(comparison.greedy.rules.len(), 2);
+    assert!(comparison.beam.score_after < comparison.beam.score_before);
+    assert!(comparison.improvement() >= 0.0);
+  }
+
+  #[test]
+  fn finds_rules_and_commits_the_first() {
+    let dictionary = dictionary(&[("at", "æt", 1.0), ("as", "æz", 0.5), ("cat", "kæt", 0.25)]);
+    let mut system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+
+    let result = beam_search(&system, &BeamConfig::default());
+    assert!(!result.rules.is_empty());
+    assert!(result.score_after < result.score_before);
+
+    let first = system.find_next_rule_beam(&BeamConfig::default()).unwrap();
+    assert_eq!(first, result.rules[0]);
+    assert_eq!(system.steps, 1);
+    assert_eq!(system.rules.substitutions, vec![first]);
+    // It goes through push_rule, so the index follows the dictionary.
+    assert_eq!(system.index, crate::ngram_index::NgramIndex::build(&system.dictionary, crate::ngram_index::DEFAULT_MAX_N));
+  }
+}
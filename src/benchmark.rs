@@ -0,0 +1,359 @@
+
+/* Reproducible speed benchmarks for the astarlike searches.
+ *
+ * Each configuration is run on the top N words of the dictionary for a fixed
+ * number of rules, recording per rule the winner, how many times step was
+ * called, how many (edit, word) pairs were scored exactly and the wall time.
+ * Steps and words explored are deterministic, so they're what a change to the
+ * search should be judged by; wall time depends on the machine.
+ *
+ * Slices are fingerprinted so that a baseline recorded against a different
+ * dictionary isn't silently compared with a new one. The dictionary has about
+ * 5000 words; the benchmark binary won't take a slice bigger than that.
+ *
+ * res/benchmark-baseline.json is the default run, to pass as --baseline.
+ */
+
+use crate::dictionary::Dictionary;
+use crate::glyphs::encode;
+use crate::astarlike2::{self, IterativeSystem, HLEditSystem, HLEditList};
+use crate::objective::WeightedObjective;
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList};
+use crate::calibration::sample_edits;
+use crate::empirical_astarlike22::{ChangeHistograms, EmpiricalSystem};
+use crate::distance_metrics::Levenshtein;
+use crate::{astarlike, gaussian_astarlike2, gaussian_astarlike22, genastarlike, high_level_substitutions};
+use serde::{Serialize, Deserialize};
+use howlong::SteadyTimer;
+use std::sync::Arc;
+
+/// Used by every Gaussian configuration, as in astarlike2test.
+pub const SCALE: f64 = 4.0;
+
+/// Used by the empirical configuration.
+pub const RISK: f64 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchConfig {
+  /// astarlike on the original substitutions, with gaussian_astarlike2.
+  Astarlike,
+  /// genastarlike without the n-gram index, driven step by step, with
+  /// gaussian_astarlike22.
+  Astarlike2,
+  /// IterativeSystem, which runs genastarlike, with gaussian_astarlike22.
+  Iterative,
+  /// IterativeSystem with empirical_astarlike22, its histograms sampled from
+  /// the slice before timing starts.
+  Empirical
+}
+
+pub const CONFIGS: [SearchConfig; 4] = [SearchConfig::Astarlike, SearchConfig::Astarlike2, SearchConfig::Iterative, SearchConfig::Empirical];
+
+impl SearchConfig {
+  pub fn name(&self) -> &'static str {
+    match self {
+      SearchConfig::Astarlike => "astarlike",
+      SearchConfig::Astarlike2 => "astarlike2",
+      SearchConfig::Iterative => "iterative",
+      SearchConfig::Empirical => "empirical"
+    }
+  }
+
+  pub fn parse(s: &str) -> Result<SearchConfig, String> {
+    CONFIGS.iter().find(|c| c.name() == s).copied().ok_or_else(|| format!("unknown configuration {:?}", s))
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleTiming {
+  pub rule: String,
+  pub steps: usize,
+  pub words_explored: usize,
+  pub ms: f64
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchResult {
+  pub config: SearchConfig,
+  /// The requested slice size.
+  pub slice: usize,
+  /// How many words it actually had.
+  pub words: usize,
+  pub fingerprint: String,
+  /// Can be shorter than requested if the search ran out of improvements.
+  pub rules: Vec<RuleTiming>
+}
+
+impl BenchResult {
+  pub fn steps(&self) -> usize {
+    self.rules.iter().map(|r| r.steps).sum()
+  }
+
+  pub fn words_explored(&self) -> usize {
+    self.rules.iter().map(|r| r.words_explored).sum()
+  }
+
+  pub fn ms(&self) -> f64 {
+    self.rules.iter().map(|r| r.ms).sum()
+  }
+}
+
+/// The `size` most frequent words.
+pub fn dictionary_slice(dictionary: &Dictionary, size: usize) -> Dictionary {
+  let mut words = dictionary.words.clone();
+  // Stable, so ties keep dictionary order.
+  words.sort_by(|a, b| b.frequency.total_cmp(&a.frequency));
+  words.truncate(size);
+  Dictionary { words }
+}
+
+/// FNV-1a over the spellings, pronunciations and exact frequencies.
+pub fn fingerprint(dictionary: &Dictionary) -> String {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  let mut feed = |bytes: &[u8]| {
+    for b in bytes {
+      hash ^= *b as u64;
+      hash = hash.wrapping_mul(0x100000001b3);
+    }
+  };
+  for w in &dictionary.words {
+    feed(encode(&w.spelling).as_bytes());
+    feed(b" ");
+    feed(encode(&w.pronunciation).as_bytes());
+    feed(&w.frequency.to_bits().to_le_bytes());
+  }
+  format!("{:016x}", hash)
+}
+
+fn run_astarlike(dictionary: &Dictionary, rules: usize) -> Vec<RuleTiming> {
+  let system = gaussian_astarlike2::GaussianSystem { scale: SCALE };
+  let mut hl_slist = high_level_substitutions::HLSubstitutionList::from_debug_str("").unwrap();
+  let mut res = vec![];
+
+  for _ in 0 .. rules {
+    let timer = SteadyTimer::new();
+    let r = astarlike::init_ref_data(&system, dictionary, &hl_slist);
+    let mut w = astarlike::init_working_data(&r);
+    let mut steps = 0;
+    let winner = loop {
+      steps += 1;
+      match astarlike::step(&system, &r, &mut w, false) {
+        None => continue,
+        Some(astarlike::Outcome::FoundImprovement(winner, _)) => break Some(winner),
+        Some(astarlike::Outcome::FailedToFindImprovement(..)) => break None
+      }
+    };
+    let Some(winner) = winner else { break };
+
+    res.push(RuleTiming {
+      rule: format!("{:?}", winner),
+      steps,
+      words_explored: w.words_explored(),
+      ms: timer.elapsed().as_secs_f64() * 1000.0
+    });
+    // This version applies the most recent rule first.
+    hl_slist.substitutions.insert(0, winner);
+  }
+
+  res
+}
+
+fn run_astarlike2(dictionary: &Dictionary, rules: usize) -> Vec<RuleTiming> {
+  let system = gaussian_astarlike22::GaussianSystem { scale: SCALE };
+  let mut state = IterativeSystem::setup(dictionary, HLSubstitutionList { substitutions: vec![] });
+  let mut res = vec![];
+
+  for _ in 0 .. rules {
+    let timer = SteadyTimer::new();
+    let objective = WeightedObjective::default();
+    let guard = state.constraints.guard(&state.rules);
+    let edit_sys = HLEditSystem {
+      mid: state.rules.next_open_mid(),
+      class_contexts: false,
+      objective: &objective,
+      guard: &guard,
+      index: None,
+      alignment: None
+    };
+    let (plain, edit_list) = HLEditList::from_state(&state.dictionary, &state.rules);
+    let r = genastarlike::init_ref_data(&system, &edit_sys, &plain, &edit_list);
+    let mut w = genastarlike::init_working_data();
+    let mut steps = 0;
+    let winner = loop {
+      steps += 1;
+      match genastarlike::step(&system, &edit_sys, &r, &mut w, false) {
+        None => continue,
+        Some(genastarlike::Outcome::FoundImprovement(winner, _)) => break Some(winner),
+        Some(_) => break None
+      }
+    };
+    let Some(winner) = winner else { break };
+
+    res.push(RuleTiming {
+      rule: winner.encode(),
+      steps,
+      words_explored: w.words_explored(),
+      ms: timer.elapsed().as_secs_f64() * 1000.0
+    });
+    state.push_rule(winner);
+  }
+
+  res
+}
+
+fn run_iterative<T, Estimate, Estimator, S>(system: &S, dictionary: &Dictionary, rules: usize) -> Vec<RuleTiming> where
+  T: genastarlike::Table<Estimate, Estimator, HLSubstitution> + Send + Sync,
+  Estimate: Clone + Send + Sync,
+  Estimator: Clone + Send + Sync,
+  S: genastarlike::EstimationSystem<T, Estimate, Estimator, HLSubstitution> + Send + Sync
+{
+  let mut state = IterativeSystem::setup(dictionary, HLSubstitutionList { substitutions: vec![] });
+  let mut res = vec![];
+
+  for _ in 0 .. rules {
+    let timer = SteadyTimer::new();
+    let astarlike2::Outcome::FoundImprovement(winner, _) = state.find_next_rule(system, false) else { break };
+    res.push(RuleTiming {
+      rule: winner.encode(),
+      steps: state.last_search.steps,
+      words_explored: state.last_search.words_explored,
+      ms: timer.elapsed().as_secs_f64() * 1000.0
+    });
+  }
+
+  res
+}
+
+fn empirical_system(dictionary: &Dictionary) -> EmpiricalSystem {
+  let state = IterativeSystem::setup(dictionary, HLSubstitutionList { substitutions: vec![] });
+  let samples = sample_edits(&state.dictionary, &state.rules, &Levenshtein, 200, 500, 0);
+  EmpiricalSystem {
+    histograms: Arc::new(ChangeHistograms::from_samples(&samples, 6)),
+    risk: RISK
+  }
+}
+
+pub fn run(config: SearchConfig, dictionary: &Dictionary, slice: usize, rules: usize) -> BenchResult {
+  let words = dictionary_slice(dictionary, slice);
+  BenchResult {
+    config,
+    slice,
+    words: words.words.len(),
+    fingerprint: fingerprint(&words),
+    rules: match config {
+      SearchConfig::Astarlike => run_astarlike(&words, rules),
+      SearchConfig::Astarlike2 => run_astarlike2(&words, rules),
+      SearchConfig::Iterative => run_iterative(&gaussian_astarlike22::GaussianSystem { scale: SCALE }, &words, rules),
+      SearchConfig::Empirical => run_iterative(&empirical_system(&words), &words, rules)
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Baseline {
+  pub results: Vec<BenchResult>
+}
+
+impl Baseline {
+  pub fn load(path: &str) -> Result<Baseline, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))
+  }
+
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    std::fs::write(path, serde_json::to_string_pretty(self).unwrap()).map_err(|e| format!("{}: {}", path, e))
+  }
+
+  pub fn find(&self, config: SearchConfig, slice: usize) -> Option<&BenchResult> {
+    self.results.iter().find(|r| r.config == config && r.slice == slice)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Comparison {
+  /// Nothing to compare against.
+  New,
+  /// The slice's words aren't what the baseline ran on.
+  SliceChanged,
+  Compared {
+    /// Whether the same rules were found, in the same order.
+    same_rules: bool,
+    /// Current over baseline; below 1 is an improvement.
+    steps_ratio: f64,
+    explored_ratio: f64,
+    time_ratio: f64
+  }
+}
+
+pub fn compare(baseline: &Baseline, current: &BenchResult) -> Comparison {
+  let Some(old) = baseline.find(current.config, current.slice) else { return Comparison::New };
+  if old.fingerprint != current.fingerprint {
+    return Comparison::SliceChanged;
+  }
+  let ratio = |new: f64, old: f64| if old == 0.0 { 1.0 } else { new / old };
+  Comparison::Compared {
+    same_rules: old.rules.iter().map(|r| &r.rule).eq(current.rules.iter().map(|r| &r.rule)),
+    steps_ratio: ratio(current.steps() as f64, old.steps() as f64),
+    explored_ratio: ratio(current.words_explored() as f64, old.words_explored() as f64),
+    time_ratio: ratio(current.ms(), old.ms())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn runs_are_reproducible() {
+    let dictionary = crate::dictionary::load_dictionary().unwrap();
+    let slice = dictionary_slice(&dictionary, 100);
+    assert_eq!(slice.words.len(), 100);
+    assert_eq!(fingerprint(&slice), fingerprint(&dictionary_slice(&dictionary, 100)));
+    assert_ne!(fingerprint(&slice), fingerprint(&dictionary_slice(&dictionary, 99)));
+
+    for config in CONFIGS {
+      let first = run(config, &dictionary, 100, 2);
+      let second = run(config, &dictionary, 100, 2);
+      assert_eq!(first.rules.len(), 2);
+      assert!(first.words_explored() > 0);
+
+      let baseline = Baseline { results: vec![first] };
+      let Comparison::Compared { same_rules, steps_ratio, explored_ratio, .. } = compare(&baseline, &second) else { panic!("Expected a comparison") };
+      assert!(same_rules);
+      assert_eq!((steps_ratio, explored_ratio), (1.0, 1.0));
+    }
+  }
+
+  #[test]
+  fn index_skips_unaffected_words() {
+    let dictionary = crate::dictionary::load_dictionary().unwrap();
+    // The same search, with and without the n-gram index.
+    let without = run(SearchConfig::Astarlike2, &dictionary, 100, 3);
+    let with = run(SearchConfig::Iterative, &dictionary, 100, 3);
+    assert!(with.rules.iter().map(|r| &r.rule).eq(without.rules.iter().map(|r| &r.rule)));
+    assert_eq!(with.steps(), without.steps());
+    assert!(with.words_explored() < without.words_explored());
+  }
+
+  #[test]
+  fn compare_needs_a_matching_slice() {
+    let result = BenchResult {
+      config: SearchConfig::Iterative,
+      slice: 500,
+      words: 500,
+      fingerprint: "0".to_owned(),
+      rules: vec![RuleTiming { rule: "[th]→0→ϑ".to_owned(), steps: 10, words_explored: 100, ms: 2.0 }]
+    };
+    let baseline = Baseline { results: vec![result.clone()] };
+
+    assert_eq!(compare(&Baseline::default(), &result), Comparison::New);
+    assert_eq!(compare(&baseline, &BenchResult { fingerprint: "1".to_owned(), ..result.clone() }), Comparison::SliceChanged);
+
+    let faster = BenchResult {
+      rules: vec![RuleTiming { rule: "[th]→0→ϑ".to_owned(), steps: 5, words_explored: 50, ms: 1.0 }],
+      ..result.clone()
+    };
+    assert_eq!(compare(&baseline, &faster), Comparison::Compared { same_rules: true, steps_ratio: 0.5, explored_ratio: 0.5, time_ratio: 0.5 });
+  }
+}
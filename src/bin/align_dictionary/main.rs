@@ -0,0 +1,76 @@
+use feature_refining::alignment::AlignmentModel;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::glyphs::encode;
+use clap::Parser;
+
+/// Trains a letter-to-sound alignment model on the dictionary, for seeding
+/// candidate rules.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Only use this many of the most frequent words.
+    #[arg(long)]
+    size: Option<usize>,
+
+    /// Rounds of expectation maximization.
+    #[arg(long, default_value_t = 10)]
+    iterations: usize,
+
+    #[arg(long, default_value_t = 2)]
+    max_graphemes: usize,
+
+    /// Letters spoken as several sounds need 2, but that also lets schwas be
+    /// absorbed into neighbouring consonants.
+    #[arg(long, default_value_t = 1)]
+    max_phonemes: usize,
+
+    /// Chunks less likely than this given their letters don't seed candidates.
+    #[arg(long)]
+    min_prob: Option<f64>,
+
+    /// How many of the most likely chunks to print.
+    #[arg(long, default_value_t = 40)]
+    top: usize,
+
+    /// Write the model here, for astarlike2test --alignment.
+    #[arg(long)]
+    out: Option<String>,
+
+    /// Write every word's best alignment here as JSON lines.
+    #[arg(long)]
+    export: Option<String>,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  let mut dictionary = load_dictionary().unwrap();
+  if let Some(size) = args.size {
+    dictionary.words.truncate(size);
+  }
+
+  let mut model = AlignmentModel::initial(&dictionary, args.max_graphemes, args.max_phonemes);
+  for i in 0 .. args.iterations {
+    let (next, log_likelihood) = model.improve(&dictionary);
+    println!("Iteration {}: log likelihood {:.3}", i + 1, log_likelihood);
+    model = next;
+  }
+  if let Some(min_prob) = args.min_prob {
+    model.min_prob = min_prob;
+  }
+
+  println!("{:>8} {:>8} {:>10} {:>10}", "letters", "sounds", "joint", "given");
+  for (g, p, joint) in model.chunks().into_iter().take(args.top) {
+    println!("{:>8} {:>8} {:>10.6} {:>10.4}", encode(&g), encode(&p), joint, model.conditional(&g, &p));
+  }
+
+  let unaligned = dictionary.words.iter().filter(|w| model.align(&w.spelling, &w.pronunciation).is_none()).count();
+  println!("{} of {} words can't be aligned", unaligned, dictionary.words.len());
+
+  if let Some(path) = &args.out {
+    model.save(path).unwrap();
+  }
+  if let Some(path) = &args.export {
+    println!("Exported {} alignments to {}", model.export_alignments(&dictionary, path).unwrap(), path);
+  }
+}
@@ -0,0 +1,106 @@
+use feature_refining::annealing::{AnnealingConfig, anneal};
+use feature_refining::beam_search::{BeamConfig, beam_search};
+use feature_refining::astarlike2::IterativeSystem;
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::rule_files::RuleFile;
+use feature_refining::objective::load_objective;
+use clap::Parser;
+
+/// Anneals a rule list, starting from a rule file or from a greedy search.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Rule file to start from. Without one, a greedy search picks the start.
+    #[arg(long)]
+    rules: Option<String>,
+
+    /// How many rules the greedy search picks.
+    #[arg(long, default_value_t = 10)]
+    greedy_rules: usize,
+
+    /// Only use this many of the most frequent words.
+    #[arg(long, default_value_t = 200)]
+    words: usize,
+
+    #[arg(long, default_value_t = 4)]
+    chains: usize,
+
+    #[arg(long, default_value_t = 1.0)]
+    max_temperature: f64,
+
+    #[arg(long, default_value_t = 0.01)]
+    min_temperature: f64,
+
+    #[arg(long, default_value_t = 0.01)]
+    final_cooling: f64,
+
+    #[arg(long, default_value_t = 50)]
+    rounds: usize,
+
+    #[arg(long, default_value_t = 20)]
+    moves_per_round: usize,
+
+    #[arg(long)]
+    class_contexts: bool,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    #[arg(long)]
+    costs: Option<String>,
+
+    /// JSON file of objective weights. Defaults to distance plus rule size.
+    #[arg(long)]
+    weights: Option<String>,
+
+    /// Write the best list here as a rule file.
+    #[arg(long)]
+    output: Option<String>,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  let mut dictionary = load_dictionary().unwrap();
+  dictionary.words.truncate(args.words);
+  let objective = load_objective(args.costs.as_deref(), args.weights.as_deref()).unwrap();
+
+  let start = match &args.rules {
+    Some(path) => RuleFile::load(path).unwrap().to_list(),
+    None => {
+      let mut system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+      system.objective = objective.clone();
+      let greedy = beam_search(&system, &BeamConfig {
+        width: 1,
+        depth: args.greedy_rules,
+        class_contexts: args.class_contexts,
+        ..BeamConfig::default()
+      });
+      HLSubstitutionList { substitutions: greedy.rules }
+    }
+  };
+
+  let config = AnnealingConfig {
+    chains: args.chains,
+    max_temperature: args.max_temperature,
+    min_temperature: args.min_temperature,
+    final_cooling: args.final_cooling,
+    rounds: args.rounds,
+    moves_per_round: args.moves_per_round,
+    class_contexts: args.class_contexts,
+    seed: args.seed
+  };
+  let res = anneal(&start, &dictionary, &config, &*objective);
+
+  println!("score: {:.4} -> {:.4}", res.start_score, res.best_score);
+  println!("accepted {} of {} moves, {} swaps", res.accepted, res.proposed, res.swaps);
+  for sub in &res.best.substitutions {
+    println!("{}", sub.encode());
+  }
+
+  if let Some(path) = &args.output {
+    RuleFile::from_list(&res.best).save(path).unwrap();
+  }
+}
@@ -3,10 +3,80 @@ use feature_refining::astarlike2::*;
 use feature_refining::high_level_substitutions2::*;
 use feature_refining::dictionary;
 use feature_refining::gaussian_astarlike22::*;
+use feature_refining::constraints::Constraints;
+use feature_refining::search_log::SearchLog;
+use feature_refining::gaussian_expectation_table2::Calibration;
+use feature_refining::alignment::AlignmentModel;
+use feature_refining::holdout::{split, Holdout};
+use std::sync::Arc;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Save progress here after every rule, and resume from it if it exists.
+    #[arg(long)]
+    checkpoint: Option<String>,
+
+    /// Constraint file of protected and must-hit words.
+    #[arg(long)]
+    constraints: Option<String>,
+
+    /// Write search events here as JSON lines, for search_summary.
+    #[arg(long)]
+    log: Option<String>,
+
+    /// Per-word sigmas from calibrate_estimators. Defaults to 1 everywhere.
+    #[arg(long)]
+    calibration: Option<String>,
+
+    #[arg(long, default_value_t = 4.0)]
+    scale: f64,
+
+    /// After each rule, score the winner and this many runner-ups exactly, and
+    /// report when one of them would have been better.
+    #[arg(long)]
+    verify: Option<usize>,
+
+    /// Alignment model from align_dictionary. If given, candidates come from
+    /// each word's alignment instead of every pair of substrings.
+    #[arg(long)]
+    alignment: Option<String>,
+
+    /// Hold out one of this many folds of the dictionary, and report the mean
+    /// word cost of both the training and the held-out words after each rule.
+    #[arg(long)]
+    folds: Option<usize>,
+
+    /// Which fold to hold out.
+    #[arg(long, default_value_t = 0)]
+    fold: usize,
+
+    /// With --folds, stop once this many rules in a row haven't improved the
+    /// held-out cost.
+    #[arg(long)]
+    patience: Option<usize>,
+}
 
 fn main() {
+  let args = Args::parse();
+  
   let dictionary = dictionary::load_dictionary().unwrap();
   println!("Full size: {}", dictionary.words.len());
+  let (dictionary, held_out) = match args.folds {
+    Some(folds) => {
+      let (train, test) = match split(&dictionary, folds, args.fold) {
+        Ok(split) => split,
+        Err(e) => {
+          eprintln!("--folds: {}", e);
+          std::process::exit(1);
+        }
+      };
+      println!("Holding out {} words", test.words.len());
+      (train, Some(test))
+    },
+    None => (dictionary, None)
+  };
   
   let init_rules = HLSubstitutionList {
     substitutions: vec![]
@@ -30,13 +100,49 @@ fn main() {
   // [{2}]$→14→a      [{2}]$→14→a   
   // [{8}h]→15→ʧ      [{6}h]→15→ʧ   
 
-  let system = GaussianSystem {
-    scale: 4.0
+  let system = CalibratedGaussianSystem {
+    scale: args.scale,
+    calibration: Arc::new(match &args.calibration {
+      Some(path) => Calibration::load(path).unwrap(),
+      None => Calibration::default()
+    })
   };
   
-  let mut iter_system = feature_refining::astarlike2::IterativeSystem::setup(&dictionary, init_rules);
+  // These aren't saved in checkpoints, so they're set again on resuming.
+  let configure = |iter_system: &mut IterativeSystem| -> Result<(), String> {
+    if let Some(path) = &args.constraints {
+      iter_system.constraints = Arc::new(Constraints::load(path)?);
+    }
+    if let Some(path) = &args.alignment {
+      iter_system.alignment = Some(Arc::new(AlignmentModel::load(path)?));
+    }
+    Ok(())
+  };
   
-  'stepper_loop: for _i in 0 .. 400 {
+  let mut iter_system = match &args.checkpoint {
+    Some(path) if std::path::Path::new(path).exists() => {
+      let iter_system = IterativeSystem::load_checkpoint(path, &configure).unwrap();
+      println!("Resuming after {} rules", iter_system.steps);
+      for s in &iter_system.rules.substitutions {
+        println!("{}", s.encode());
+      }
+      iter_system
+    },
+    _ => {
+      let mut iter_system = IterativeSystem::setup(&dictionary, init_rules);
+      configure(&mut iter_system).unwrap();
+      iter_system.checkpoint_path = args.checkpoint.clone();
+      iter_system
+    }
+  };
+  if let Some(path) = &args.log {
+    iter_system.log = Some(SearchLog::create(path).unwrap());
+  }
+  iter_system.verify = args.verify;
+  let mut holdout = held_out.map(|test| Holdout::new(&test, &iter_system));
+  let (mut verified, mut wrong_picks) = (0, 0);
+  
+  'stepper_loop: while iter_system.steps < 400 {
     match iter_system.find_next_rule(&system, false) {
       Outcome::FoundImprovement(the_winner, _) => {
         println!("{}", the_winner.encode());
@@ -45,7 +151,44 @@ fn main() {
         println!("Failed to find improvement: {} {}", best.encode(), change);
         break 'stepper_loop;
       }
+      Outcome::Exhausted => {
+        println!("No rules left to propose");
+        break 'stepper_loop;
+      }
+      Outcome::Refused(e) => {
+        eprintln!("{}", e);
+        std::process::exit(1);
+      }
     };
+    
+    if let Some(v) = &iter_system.last_verification {
+      verified += 1;
+      if v.picked_wrongly() {
+        println!("  {} would have been better by {:.6}", v.best.encode(), v.regret());
+        wrong_picks += 1;
+      }
+    }
+    
+    if let Some(holdout) = &mut holdout {
+      let report = holdout.record(&iter_system);
+      println!("  train {:.6} held out {:.6}", report.train, report.test);
+      if args.patience.is_some_and(|p| holdout.stalled(p)) {
+        println!("No improvement on the held-out words for {} rules", args.patience.unwrap());
+        break 'stepper_loop;
+      }
+    }
+  }
+  
+  if let Some(holdout) = &holdout {
+    let best = holdout.best();
+    println!("Best on the held-out words after {} rules: {:.6}", best.rules, best.test);
+  }
+  
+  if args.verify.is_some() {
+    println!("{} of {} rules were not the best verified candidate", wrong_picks, verified);
+  }
+  
+  for v in iter_system.violations() {
+    println!("Violated: {}", v);
   }
 }
-
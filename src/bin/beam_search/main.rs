@@ -0,0 +1,81 @@
+use feature_refining::beam_search::{BeamConfig, compare_with_greedy};
+use feature_refining::astarlike2::IterativeSystem;
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::rule_files::RuleFile;
+use feature_refining::objective::load_objective;
+use clap::Parser;
+
+/// Compares a beam search for the next few rules against greedily picking them.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Rules to start from. Defaults to none.
+    #[arg(long)]
+    rules: Option<String>,
+
+    /// Only use this many of the most frequent words.
+    #[arg(long, default_value_t = 200)]
+    words: usize,
+
+    /// How many partial rule lists to keep.
+    #[arg(long, default_value_t = 4)]
+    width: usize,
+
+    /// How many rules to look ahead.
+    #[arg(long, default_value_t = 3)]
+    depth: usize,
+
+    #[arg(long, default_value_t = 20)]
+    seed_words: usize,
+
+    #[arg(long, default_value_t = 32)]
+    candidates: usize,
+
+    #[arg(long)]
+    class_contexts: bool,
+
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    #[arg(long)]
+    costs: Option<String>,
+
+    /// JSON file of objective weights. Defaults to distance plus rule size.
+    #[arg(long)]
+    weights: Option<String>,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  let mut dictionary = load_dictionary().unwrap();
+  dictionary.words.truncate(args.words);
+
+  let rules = match &args.rules {
+    Some(path) => RuleFile::load(path).unwrap().to_list(),
+    None => HLSubstitutionList { substitutions: vec![] }
+  };
+
+  // The dictionary has to be brought up to date with the starting rules.
+  let mut system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+  for sub in rules.substitutions {
+    system.push_rule(sub);
+  }
+  system.objective = load_objective(args.costs.as_deref(), args.weights.as_deref()).unwrap();
+
+  let config = BeamConfig {
+    width: args.width,
+    depth: args.depth,
+    seed_words: args.seed_words,
+    candidates: args.candidates,
+    class_contexts: args.class_contexts
+  };
+  let comparison = compare_with_greedy(&system, &config);
+
+  for (name, result) in [("greedy", &comparison.greedy), ("beam", &comparison.beam)] {
+    println!("{}: {:.4} -> {:.4}", name, result.score_before, result.score_after);
+    for sub in &result.rules {
+      println!("  {}", sub.encode());
+    }
+  }
+  println!("beam is better by {:.4}", comparison.improvement());
+}
@@ -0,0 +1,80 @@
+use feature_refining::benchmark::*;
+use feature_refining::dictionary;
+use clap::Parser;
+
+/// Times the astarlike searches on the most frequent words, optionally against
+/// a saved baseline.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Comma-separated configurations: astarlike, astarlike2, iterative, empirical.
+    #[arg(long, default_value = "astarlike,astarlike2,iterative,empirical")]
+    configs: String,
+
+    /// Comma-separated slice sizes, none bigger than the dictionary.
+    #[arg(long, default_value = "500,2000,5000")]
+    sizes: String,
+
+    /// How many rules to find in each run.
+    #[arg(long, default_value_t = 5)]
+    rules: usize,
+
+    /// Compare against the results saved here, e.g. res/benchmark-baseline.json.
+    #[arg(long)]
+    baseline: Option<String>,
+
+    /// Save this run's results here.
+    #[arg(long)]
+    save: Option<String>,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  let configs: Vec<SearchConfig> = args.configs.split(',').map(|c| SearchConfig::parse(c.trim()).unwrap()).collect();
+  let sizes: Vec<usize> = args.sizes.split(',').map(|s| s.trim().parse().unwrap()).collect();
+  let baseline = args.baseline.as_ref().map(|p| Baseline::load(p).unwrap()).unwrap_or_default();
+  let dictionary = dictionary::load_dictionary().unwrap();
+  if let Some(&size) = sizes.iter().find(|&&s| s > dictionary.words.len()) {
+    eprintln!("--sizes: {} is more than the {} words in the dictionary", size, dictionary.words.len());
+    std::process::exit(1);
+  }
+
+  let mut results = Baseline::default();
+  for &size in &sizes {
+    let mut first: Option<BenchResult> = None;
+    for &config in &configs {
+      let result = run(config, &dictionary, size, args.rules);
+      println!("{} on {} ({} words, {}):", config.name(), size, result.words, result.fingerprint);
+      for r in &result.rules {
+        println!("  {:<20} {:>8} steps {:>10} explored {:>10.1} ms", r.rule, r.steps, r.words_explored, r.ms);
+      }
+      println!("  {:<20} {:>8} steps {:>10} explored {:>10.1} ms", "total", result.steps(), result.words_explored(), result.ms());
+
+      match compare(&baseline, &result) {
+        Comparison::New => {},
+        Comparison::SliceChanged => println!("  The baseline ran on different words"),
+        Comparison::Compared { same_rules, steps_ratio, explored_ratio, time_ratio } => {
+          println!("  vs baseline: steps {:.3}x, explored {:.3}x, time {:.3}x{}",
+            steps_ratio, explored_ratio, time_ratio,
+            if same_rules { "" } else { ", DIFFERENT RULES" });
+        }
+      }
+      // astarlike's rules are in its own notation, so only the others are comparable.
+      if config != SearchConfig::Astarlike {
+        match &first {
+          None => first = Some(result.clone()),
+          Some(other) => {
+            let same = other.rules.iter().map(|r| &r.rule).eq(result.rules.iter().map(|r| &r.rule));
+            println!("  {} rules as {}", if same { "Same" } else { "DIFFERENT" }, other.config.name());
+          }
+        }
+      }
+      results.results.push(result);
+    }
+  }
+
+  if let Some(path) = &args.save {
+    results.save(path).unwrap();
+  }
+}
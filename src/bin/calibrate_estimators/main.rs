@@ -0,0 +1,95 @@
+use feature_refining::calibration::*;
+use feature_refining::empirical_astarlike22::ChangeHistograms;
+use feature_refining::astarlike2::IterativeSystem;
+use feature_refining::gaussian_expectation_table2::Calibration;
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::distance_metrics::load_metric;
+use feature_refining::rule_files::RuleFile;
+use clap::Parser;
+
+/// Fits the Gaussian estimators' per-word sigmas to real candidate edits, and
+/// reports how often their bounds are wrong.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Rules to start from. Defaults to none.
+    #[arg(long)]
+    rules: Option<String>,
+
+    /// Only use this many of the most frequent words.
+    #[arg(long)]
+    size: Option<usize>,
+
+    /// Take candidate edits from this many words, in introducing order.
+    #[arg(long, default_value_t = 200)]
+    words: usize,
+
+    /// How many of those candidates to sample.
+    #[arg(long, default_value_t = 500)]
+    edits: usize,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Distances above this share a sigma.
+    #[arg(long, default_value_t = 6)]
+    max_distance: u32,
+
+    /// The scale to check the bounds at.
+    #[arg(long, default_value_t = 4.0)]
+    scale: f64,
+
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    #[arg(long)]
+    costs: Option<String>,
+
+    /// Write the fitted calibration here, for astarlike2test --calibration.
+    #[arg(long)]
+    out: Option<String>,
+
+    /// Write the change histograms here, for the empirical estimator.
+    #[arg(long)]
+    histograms: Option<String>,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  let mut dictionary = load_dictionary().unwrap();
+  if let Some(size) = args.size {
+    dictionary.words.truncate(size);
+  }
+  let rules = match &args.rules {
+    Some(path) => RuleFile::load(path).unwrap().to_list(),
+    None => HLSubstitutionList { substitutions: vec![] }
+  };
+  let mut system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+  for sub in &rules.substitutions {
+    system.push_rule(sub.clone());
+  }
+  let metric = load_metric(args.costs.as_deref()).unwrap();
+
+  let samples = sample_edits(&system.dictionary, &rules, &*metric, args.words, args.edits, args.seed);
+  println!("Sampled {} edits over {} words", samples.edits.len(), samples.frequencies_in_introducing_order.len());
+
+  println!("{:>8} {:>10} {:>10} {:>8} {:>8}", "distance", "pairs", "changed", "worsen", "improve");
+  for f in fit_by_distance(&samples, args.max_distance) {
+    println!("{:>8} {:>10} {:>10} {:>8.4} {:>8.4}", f.distance, f.pairs, f.changed, f.worsen_sigma, f.improve_sigma);
+  }
+  let calibration = fit(&samples, args.max_distance);
+
+  for (name, c) in [("default", &Calibration::default()), ("calibrated", &calibration)] {
+    let check = check_bounds(&samples, c, args.scale);
+    println!("{}: at scale {}, {} below best and {} above worst ({:.2}%); scale for 5%: {:.3}, 1%: {:.3}, none: {:.3}",
+      name, args.scale, check.below_best, check.above_worst, 100.0 * check.violation_rate(),
+      check.scale_for(0.05), check.scale_for(0.01), check.scale_for(0.0));
+  }
+
+  if let Some(path) = &args.out {
+    calibration.save(path).unwrap();
+  }
+  if let Some(path) = &args.histograms {
+    ChangeHistograms::from_samples(&samples, args.max_distance).save(path).unwrap();
+  }
+}
@@ -0,0 +1,60 @@
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::equivalence_checking::{check_equivalence, dictionary_words, exhaustive_words};
+use feature_refining::lookup_minimization::random_words;
+use feature_refining::rule_files::RuleFile;
+use feature_refining::glyphs::aug_encode;
+use clap::Parser;
+
+/// Checks that a rule list and its low-level lowering agree.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Rule file to check. Defaults to set_1.
+    #[arg(long)]
+    rules: Option<String>,
+
+    /// Don't check the dictionary words.
+    #[arg(long)]
+    no_dictionary: bool,
+
+    /// How many random words to check.
+    #[arg(long, default_value_t = 0)]
+    random: usize,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Check every word up to this long over the glyphs the rules use.
+    #[arg(long, default_value_t = 0)]
+    exhaustive: usize,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  let rules = match &args.rules {
+    Some(path) => RuleFile::load(path).unwrap().to_list(),
+    None => HLSubstitutionList::set_1()
+  };
+
+  let mut words = vec![];
+  if !args.no_dictionary {
+    words.extend(dictionary_words(&load_dictionary().unwrap()));
+  }
+  words.extend(random_words(&rules, args.random, 8, args.seed));
+  words.extend(exhaustive_words(&rules, args.exhaustive));
+
+  let divergences = check_equivalence(&rules, &words);
+
+  for d in &divergences {
+    println!("{}: {} directly, {} by low level", aug_encode(&d.word), aug_encode(&d.directly), aug_encode(&d.by_low_level));
+    println!("  first diverges after rule {}: {}", d.prefix_len - 1, rules.substitutions[d.prefix_len - 1].encode());
+  }
+
+  println!("{} of {} words diverge", divergences.len(), words.len());
+
+  if !divergences.is_empty() {
+    std::process::exit(1);
+  }
+}
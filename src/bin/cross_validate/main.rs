@@ -0,0 +1,90 @@
+use feature_refining::holdout::*;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::gaussian_astarlike22::CalibratedGaussianSystem;
+use feature_refining::objective::load_objective;
+use clap::Parser;
+
+/// Finds rules on each fold's training words, and reports how the mean word
+/// cost of the held-out words changes with each rule.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Only use this many of the most frequent words.
+    #[arg(long)]
+    size: Option<usize>,
+
+    #[arg(long, default_value_t = 5)]
+    folds: usize,
+
+    /// The most rules to find on each fold.
+    #[arg(long, default_value_t = 50)]
+    rules: usize,
+
+    /// Stop a fold once this many rules in a row haven't improved its held-out cost.
+    #[arg(long)]
+    patience: Option<usize>,
+
+    #[arg(long, default_value_t = 4.0)]
+    scale: f64,
+
+    /// Also propose rules with {V}/{C} contexts.
+    #[arg(long)]
+    class_contexts: bool,
+
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    #[arg(long)]
+    costs: Option<String>,
+
+    /// Objective weights file.
+    #[arg(long)]
+    weights: Option<String>,
+
+    /// Write every fold's reports here as JSON.
+    #[arg(long)]
+    out: Option<String>,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  let mut dictionary = load_dictionary().unwrap();
+  if let Some(size) = args.size {
+    dictionary.words.truncate(size);
+  }
+  let objective = load_objective(args.costs.as_deref(), args.weights.as_deref()).unwrap();
+  if objective.has_dictionary_cost() {
+    eprintln!("--weights: homographs can't be searched for greedily; use beam_search or anneal");
+    std::process::exit(1);
+  }
+  let system = CalibratedGaussianSystem::for_objective(args.scale, &*objective);
+
+  let results = match cross_validate(&system, &dictionary, args.folds, args.rules, args.patience, &|s| {
+    s.objective = objective.clone();
+    s.class_contexts = args.class_contexts;
+  }) {
+    Ok(results) => results,
+    Err(e) => {
+      eprintln!("--folds: {}", e);
+      std::process::exit(1);
+    }
+  };
+
+  for result in &results {
+    let best = best(&result.reports);
+    println!("Fold {}: {} rules, best held out {:.6} after {}", result.fold, result.reports.len() - 1, best.test, best.rules);
+  }
+
+  println!("{:>6} {:>10} {:>10} {:>6}", "rules", "train", "held out", "folds");
+  let means = mean_by_rule_count(&results);
+  for &(rules, train, test, folds) in &means {
+    println!("{:>6} {:>10.6} {:>10.6} {:>6}", rules, train, test, folds);
+  }
+  // Only counts every fold reached are comparable.
+  if let Some(&(rules, _, test, _)) = means.iter().filter(|m| m.3 == args.folds).reduce(|best, m| if m.2 < best.2 { m } else { best }) {
+    println!("Best mean held-out cost {:.6} after {} rules", test, rules);
+  }
+
+  if let Some(path) = &args.out {
+    std::fs::write(path, serde_json::to_string_pretty(&results).unwrap()).map_err(|e| format!("{}: {}", path, e)).unwrap();
+  }
+}
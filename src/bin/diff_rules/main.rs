@@ -0,0 +1,80 @@
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::rule_diff::{diff_rule_sets, RuleChange};
+use feature_refining::rule_files::RuleFile;
+use feature_refining::distance_metrics::load_metric;
+use feature_refining::glyphs::encode;
+use clap::Parser;
+
+/// Compares two rule lists and shows which words they treat differently.
+/// Each list is a .json or .toml file, a rule file, or "set_1".
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    #[arg()]
+    old: String,
+
+    #[arg()]
+    new: String,
+
+    /// Show at most this many words.
+    #[arg(long, default_value_t = 50)]
+    limit: usize,
+
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    #[arg(long)]
+    costs: Option<String>,
+}
+
+fn load(path: &str) -> HLSubstitutionList {
+  if path == "set_1" {
+    HLSubstitutionList::set_1()
+  }
+  else if path.ends_with(".json") {
+    HLSubstitutionList::load_json(path).unwrap()
+  }
+  else if path.ends_with(".toml") {
+    HLSubstitutionList::load_toml(path).unwrap()
+  }
+  else {
+    RuleFile::load(path).unwrap().to_list()
+  }
+}
+
+fn main() {
+  let args = Args::parse();
+
+  let old = load(&args.old);
+  let new = load(&args.new);
+  let dictionary = load_dictionary().unwrap();
+  let metric = load_metric(args.costs.as_deref()).unwrap();
+
+  let diff = diff_rule_sets(&old, &new, &dictionary, &*metric);
+
+  println!("Rules:");
+  for c in &diff.changes {
+    match c {
+      RuleChange::Added { sub, index } => println!("  + {:>4} {}", index, sub.encode()),
+      RuleChange::Removed { sub, index } => println!("  - {:>4} {}", index, sub.encode()),
+      RuleChange::Moved { sub, from, to } => println!("  ~ {:>4} {} (was {})", to, sub.encode(), from),
+    }
+  }
+  println!("");
+
+  println!("Score: {:.4} -> {:.4}", diff.score_before, diff.score_after);
+  println!("{} words changed", diff.impacts.len());
+  println!("");
+
+  for i in diff.impacts.iter().take(args.limit) {
+    println!("{:+10.4}  {:<16} {:<16} -> {:<16} ({} -> {}, should be {}, weight {:.4})",
+      i.weighted_change(),
+      encode(&i.spelling),
+      encode(&i.before),
+      encode(&i.after),
+      i.distance_before,
+      i.distance_after,
+      encode(&i.pronunciation),
+      i.frequency
+    );
+  }
+}
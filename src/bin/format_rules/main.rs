@@ -0,0 +1,51 @@
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::rule_files::RuleFile;
+use clap::Parser;
+
+/// Rewrites a rule file in canonical form.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    #[arg()]
+    path: String,
+
+    /// Don't write anything, just fail if the file isn't canonical.
+    #[arg(long)]
+    check: bool,
+
+    /// Overwrite the file with HLSubstitutionList::set_1.
+    #[arg(long)]
+    from_set_1: bool,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  if args.from_set_1 {
+    RuleFile::from_list(&HLSubstitutionList::set_1()).save(&args.path).unwrap();
+    return;
+  }
+
+  let file = match RuleFile::load(&args.path) {
+    Ok(file) => file,
+    Err(e) => {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+  };
+
+  let canonical = file.encode();
+  let current = std::fs::read_to_string(&args.path).unwrap();
+
+  if canonical == current {
+    println!("{}: {} rules, already canonical", args.path, file.entries.len());
+  }
+  else if args.check {
+    eprintln!("{}: not in canonical form", args.path);
+    std::process::exit(1);
+  }
+  else {
+    file.save(&args.path).unwrap();
+    println!("{}: {} rules, reformatted", args.path, file.entries.len());
+  }
+}
@@ -0,0 +1,27 @@
+
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::lookup_minimization::{minimize_lookups, equivalence_words};
+
+fn main() {
+  let hlist = HLSubstitutionList::set_1();
+  
+  let dictionary = load_dictionary().unwrap();
+  
+  let words = equivalence_words(&hlist, &dictionary, 100000, 0);
+  println!("Checking equivalence on {} words.", words.len());
+  
+  let result = minimize_lookups(&hlist, &words, true);
+  
+  println!("");
+  println!("Lookups: {} -> {}", result.initial_lookups, result.final_lookups);
+  println!("");
+  
+  for s in &result.rules.substitutions {
+    println!("{}", s.encode());
+  }
+  if !result.posterior_order.iter().eq((0 .. result.rules.substitutions.len()).rev().collect::<Vec<_>>().iter()) {
+    println!("");
+    println!("Posteriors applied in the order {:?}", result.posterior_order);
+  }
+}
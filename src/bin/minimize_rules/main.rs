@@ -0,0 +1,44 @@
+
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::rule_minimization::{minimize, Change};
+use feature_refining::distance_metrics::load_metric;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    #[arg(long)]
+    costs: Option<String>,
+}
+
+fn main() {
+  let args = Args::parse();
+  let metric = load_metric(args.costs.as_deref()).unwrap();
+  
+  let hlist = HLSubstitutionList::set_1();
+  
+  let dictionary = load_dictionary().unwrap();
+  
+  let result = minimize(&hlist, &dictionary, &*metric, false);
+  
+  println!("Changes:");
+  for c in &result.log {
+    let description = match &c.change {
+      Change::Deleted { sub } => format!("deleted {}", sub.encode()),
+      Change::Merged { kept, removed, result } => format!("merged {} and {} into {}", kept.encode(), removed.encode(), result.encode()),
+      Change::Moved { sub, from, to } => format!("moved {} from {} to {}", sub.encode(), from, to),
+    };
+    println!("  {:.4} -> {:.4} {}", c.score_before, c.score_after, description);
+  }
+  println!("");
+  
+  println!("Score: {:.4} -> {:.4}", result.initial_score, result.final_score);
+  println!("Rules: {} -> {}", hlist.substitutions.len(), result.rules.substitutions.len());
+  println!("");
+  
+  for s in &result.rules.substitutions {
+    println!("{}", s.encode());
+  }
+}
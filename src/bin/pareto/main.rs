@@ -0,0 +1,123 @@
+use feature_refining::pareto::*;
+use feature_refining::astarlike2::{IterativeSystem, Outcome};
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::gaussian_astarlike22::CalibratedGaussianSystem;
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::hbshape::compiled_gsub_size;
+use feature_refining::objective::load_objective;
+use feature_refining::rule_files::RuleFile;
+use feature_refining::substitutions2 as s2;
+use std::path::Path;
+use clap::Parser;
+
+/// Records what each prefix of a rule list costs and buys, and prints the
+/// Pareto front of mean word cost against one of those costs.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Replay this rule file.
+    #[arg(long)]
+    rules: Option<String>,
+
+    /// Otherwise search for this many rules.
+    #[arg(long, default_value_t = 50)]
+    search: usize,
+
+    #[arg(long, default_value_t = 4.0)]
+    scale: f64,
+
+    /// Only use this many of the most frequent words.
+    #[arg(long)]
+    size: Option<usize>,
+
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    #[arg(long)]
+    costs: Option<String>,
+
+    /// Objective weights file.
+    #[arg(long)]
+    weights: Option<String>,
+
+    /// What to trade the cost off against: rules, lookups, synthetics or gsub_bytes.
+    #[arg(long, default_value = "rules")]
+    axis: String,
+
+    /// Pick the best prefix with at most this much of the axis.
+    #[arg(long)]
+    budget: Option<usize>,
+
+    /// Compile every prefix into this font with fonttools to size the GSUB
+    /// table. Otherwise the size is estimated.
+    #[arg(long)]
+    font: Option<String>,
+
+    /// Write every point here as JSON.
+    #[arg(long)]
+    points: Option<String>,
+
+    /// Write the prefix picked for the budget here.
+    #[arg(long)]
+    out: Option<String>,
+}
+
+fn main() {
+  let args = Args::parse();
+  let axis = Axis::parse(&args.axis).unwrap();
+
+  let mut dictionary = load_dictionary().unwrap();
+  if let Some(size) = args.size {
+    dictionary.words.truncate(size);
+  }
+  let objective = load_objective(args.costs.as_deref(), args.weights.as_deref()).unwrap();
+  let gsub_size = |slist: &s2::SubstitutionList| match &args.font {
+    Some(font) => compiled_gsub_size(slist, Path::new(font)).map_err(|e| format!("{}: {}", font, e)).unwrap(),
+    None => estimate_gsub_size(slist)
+  };
+
+  let (rule_file, points) = match &args.rules {
+    Some(path) => {
+      let rule_file = RuleFile::load(path).unwrap();
+      let points = replay(&dictionary, &rule_file.to_list(), objective, &gsub_size);
+      (rule_file, points)
+    },
+    None => {
+      if objective.has_dictionary_cost() {
+        eprintln!("--weights: homographs can't be searched for greedily; pass --rules from beam_search or anneal");
+        std::process::exit(1);
+      }
+      let system = CalibratedGaussianSystem::for_objective(args.scale, &*objective);
+      let mut iter_system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+      iter_system.objective = objective;
+      let mut points = vec![measure(&iter_system, &gsub_size)];
+      while iter_system.steps < args.search {
+        let Outcome::FoundImprovement(..) = iter_system.find_next_rule(&system, false) else { break };
+        let point = measure(&iter_system, &gsub_size);
+        println!("{} {:.6}", point.rule.as_deref().unwrap(), point.cost);
+        points.push(point);
+      }
+      (RuleFile::from_list(&iter_system.rules), points)
+    }
+  };
+
+  println!("{:>6} {:>8} {:>10} {:>10} {:>10}  rule", "rules", "lookups", "synthetics", "gsub_bytes", "cost");
+  for p in front(&points, axis) {
+    println!("{:>6} {:>8} {:>10} {:>10} {:>10.6}  {}", p.rules, p.lookups, p.synthetics, p.gsub_bytes, p.cost, p.rule.as_deref().unwrap_or(""));
+  }
+
+  if let Some(path) = &args.points {
+    std::fs::write(path, serde_json::to_string_pretty(&points).unwrap()).map_err(|e| format!("{}: {}", path, e)).unwrap();
+  }
+
+  if let Some(budget) = args.budget {
+    let Some(best) = best_within(&points, axis, budget) else {
+      println!("Nothing fits within {} {}", budget, axis.name());
+      return;
+    };
+    println!("Best within {} {}: {} rules, cost {:.6}", budget, axis.name(), best.rules, best.cost);
+    if let Some(path) = &args.out {
+      let mut prefix = rule_file.clone();
+      prefix.entries.truncate(best.rules);
+      prefix.save(path).unwrap();
+    }
+  }
+}
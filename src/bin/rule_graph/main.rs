@@ -0,0 +1,44 @@
+use feature_refining::high_level_substitutions2::HLSubstitutionList;
+use feature_refining::dictionary::load_dictionary;
+use feature_refining::rule_files::RuleFile;
+use feature_refining::rule_graph::RuleGraph;
+use clap::Parser;
+
+/// Prints the mid dependency graph of a rule list as Graphviz DOT or JSON.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// A .json or .toml file, or a rule file. Defaults to set_1.
+    #[arg(long)]
+    rules: Option<String>,
+
+    #[arg(long)]
+    json: bool,
+
+    /// Add fire counts and this many example words from the dictionary to each rule.
+    #[arg(long)]
+    examples: Option<usize>,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  let rules = match &args.rules {
+    None => HLSubstitutionList::set_1(),
+    Some(path) if path.ends_with(".json") => HLSubstitutionList::load_json(path).unwrap(),
+    Some(path) if path.ends_with(".toml") => HLSubstitutionList::load_toml(path).unwrap(),
+    Some(path) => RuleFile::load(path).unwrap().to_list()
+  };
+
+  let mut graph = RuleGraph::build(&rules);
+  if let Some(max_examples) = args.examples {
+    graph.add_usage(&rules, &load_dictionary().unwrap(), max_examples);
+  }
+
+  if args.json {
+    println!("{}", graph.to_json());
+  }
+  else {
+    print!("{}", graph.to_dot());
+  }
+}
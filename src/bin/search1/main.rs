@@ -5,7 +5,7 @@ use std::{vec::Vec, fs, path::Path};
 use float_ord::FloatOrd;
 use rand::{Rng, distributions::{Uniform, Bernoulli, WeightedIndex}, prelude::Distribution, thread_rng, rngs::ThreadRng};
 use lazy_static::lazy_static;
-use levenshtein_diff as levenshtein;
+use clap::Parser;
 
 use feature_refining::glyphs::*;
 use feature_refining::dictionary::*;
@@ -13,36 +13,47 @@ use serde::{Serialize, Deserialize};
 use feature_refining::sub_generation::SubGenerator;
 use feature_refining::substitutions::*;
 use feature_refining::frequencies;
+use feature_refining::distance_metrics::{DistanceMetric, load_metric};
+use std::sync::Arc;
 
-fn score_difference(p1: &Vec<Glyph>, p2: &Vec<Glyph>) -> f64 {
-  levenshtein::distance(p1, p2).0 as f64
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    /// The population cache keeps the scores it was saved with.
+    #[arg(long)]
+    costs: Option<String>,
 }
 
-fn score_dictionary_transformer<F: Fn(&Vec<Glyph>) -> Vec<Glyph>>(dictionary: &Dictionary, transformer: F) -> f64 {
+fn score_difference(p1: &Vec<Glyph>, p2: &Vec<Glyph>, metric: &dyn DistanceMetric) -> f64 {
+  metric.distanceg(p1, p2) as f64
+}
+
+fn score_dictionary_transformer<F: Fn(&Vec<Glyph>) -> Vec<Glyph>>(dictionary: &Dictionary, metric: &dyn DistanceMetric, transformer: F) -> f64 {
   let mut total: f64 = 0.0;
   
   for word in &dictionary.words {
     let transformed = transformer(&word.spelling);
-    let score = score_difference(&word.pronunciation, &transformed) as f64;
+    let score = score_difference(&word.pronunciation, &transformed, metric) as f64;
     total = total + score * (word.frequency as f64);
   }
   
   total
 }
 
-fn score_substitution_list(dictionary: &Dictionary, substitution_list: &SubstitutionList) -> f64 {
-  score_dictionary_transformer(dictionary, |word| {
+fn score_substitution_list(dictionary: &Dictionary, substitution_list: &SubstitutionList, metric: &dyn DistanceMetric) -> f64 {
+  score_dictionary_transformer(dictionary, metric, |word| {
     let mut word = word.clone();
     substitution_list.apply_all_pos(&mut word);
     word
   })
 }
 
-fn base_score(dictionary: &Dictionary) -> f64 {
+fn base_score(dictionary: &Dictionary, metric: &dyn DistanceMetric) -> f64 {
   let mut total: f64 = 0.0;
   
   for word in &dictionary.words {
-    let score = score_difference(&word.pronunciation, &word.spelling) as f64;
+    let score = score_difference(&word.pronunciation, &word.spelling, metric) as f64;
     total = total + score * (word.frequency as f64);
   }
   
@@ -56,6 +67,7 @@ mod scoring_sanity_tests {
   #[test]
   fn test_1() {
     use Glyph::*;
+    use feature_refining::distance_metrics::Levenshtein;
     let dictionary = Dictionary {
       words: vec![
         DictionaryWord {
@@ -84,8 +96,8 @@ mod scoring_sanity_tests {
         }),
       ]
     };
-    let score1 = base_score(&dictionary);
-    let score2 = score_substitution_list(&dictionary, &substitution_list);
+    let score1 = base_score(&dictionary, &Levenshtein);
+    let score2 = score_substitution_list(&dictionary, &substitution_list, &Levenshtein);
     assert!(score2 < score1);
   }
 }
@@ -261,6 +273,7 @@ impl Population {
 struct ScoringSetup {
   sub_gen: SubGenerator,
   dictionary: Dictionary,
+  metric: Arc<dyn DistanceMetric>,
   length_penalty_a: f64,
   length_penalty_b: f64
 }
@@ -268,7 +281,7 @@ struct ScoringSetup {
 impl ScoringSetup {
   fn score(&self, s: &SubstitutionList) -> f64 {
     let length_score = self.length_penalty_a * ((s.substitutions.len() as f64)*self.length_penalty_b).exp();
-    score_substitution_list(&self.dictionary, s) + length_score
+    score_substitution_list(&self.dictionary, s, &*self.metric) + length_score
   }
   
   fn make_sub(&self, rng: &mut ThreadRng) -> Substitution {
@@ -311,11 +324,11 @@ fn refine(setup: &ScoringSetup, population: &mut Population, num_iters: usize) {
   }
 }
 
-fn run() {
+fn run(metric: Arc<dyn DistanceMetric>) {
   let freq_table = frequencies::load();
   let sub_gen = SubGenerator::new(freq_table);
   let dictionary = load_dictionary().unwrap();
-  let base_score = base_score(&dictionary);
+  let base_score = base_score(&dictionary, &*metric);
   
   let length_penalty_b = 1.0/500.0;
   let length_penalty_a = base_score / (1.0 as f64).exp();
@@ -323,6 +336,7 @@ fn run() {
   let setup = ScoringSetup {
     sub_gen,
     dictionary,
+    metric,
     length_penalty_a,
     length_penalty_b
   };
@@ -354,6 +368,7 @@ fn run() {
 }
 
 fn main() {
-  run();
+  let args = Args::parse();
+  run(load_metric(args.costs.as_deref()).unwrap());
 }
 
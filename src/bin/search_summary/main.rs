@@ -0,0 +1,70 @@
+use feature_refining::search_log::{read_log, summarize};
+use clap::Parser;
+use std::io::Write;
+
+/// Summarizes a search event log: how the score converged and where each rule's
+/// search spent its time.
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// The JSON-lines log a search wrote.
+    log: String,
+
+    /// Write rule, winner, score, ms and cumulative ms per rule here as CSV, for
+    /// charting convergence.
+    #[arg(long)]
+    convergence: Option<String>,
+
+    /// Write per-rule work counts and milliseconds by event kind here as CSV.
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+const EVENTS: [&str; 8] = ["started", "introduced", "advanced", "culled", "winner", "failed", "exhausted", "verified"];
+
+fn main() {
+  let args = Args::parse();
+
+  let summaries = summarize(&read_log(&args.log).unwrap());
+
+  println!("{:>5} {:>10} {:>12} {:>8} {:>10} {:>8}  winner", "rule", "ms", "score", "words", "advanced", "culled");
+  for s in &summaries {
+    println!("{:>5} {:>10.1} {:>12} {:>8} {:>10} {:>8}  {}",
+      s.rule,
+      s.ms,
+      s.score.map_or("-".to_owned(), |x| format!("{:.4}", x)),
+      s.words_introduced,
+      s.words_advanced,
+      s.edits_culled,
+      s.winner.as_deref().unwrap_or("-")
+    );
+  }
+  let total: f64 = summaries.iter().map(|s| s.ms).sum();
+  println!("{} rules in {:.1}s", summaries.len(), total / 1000.0);
+
+  let verified: Vec<_> = summaries.iter().filter_map(|s| s.regret.map(|r| (s.rule, r))).collect();
+  if !verified.is_empty() {
+    let wrong: Vec<_> = verified.iter().filter(|(_, r)| *r > 1e-9).collect();
+    println!("{} of {} verified rules were not the best candidate", wrong.len(), verified.len());
+    for (rule, regret) in wrong {
+      println!("  rule {}: regret {:.6}", rule, regret);
+    }
+  }
+
+  if let Some(path) = &args.convergence {
+    let mut out = std::fs::File::create(path).unwrap();
+    writeln!(out, "rule,winner,score,ms,cumulative_ms").unwrap();
+    for s in &summaries {
+      writeln!(out, "{},\"{}\",{},{:.3},{:.3}", s.rule, s.winner.as_deref().unwrap_or(""), s.score.map_or(String::new(), |x| x.to_string()), s.ms, s.cumulative_ms).unwrap();
+    }
+  }
+
+  if let Some(path) = &args.profile {
+    let mut out = std::fs::File::create(path).unwrap();
+    writeln!(out, "rule,words_introduced,edits_introduced,edits_advanced,words_advanced,edits_culled,{}", EVENTS.map(|e| format!("{}_ms", e)).join(",")).unwrap();
+    for s in &summaries {
+      let times: Vec<String> = EVENTS.iter().map(|e| format!("{:.3}", s.ms_by_event.get(*e).unwrap_or(&0.0))).collect();
+      writeln!(out, "{},{},{},{},{},{},{}", s.rule, s.words_introduced, s.edits_introduced, s.edits_advanced, s.words_advanced, s.edits_culled, times.join(",")).unwrap();
+    }
+  }
+}
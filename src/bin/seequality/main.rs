@@ -1,9 +1,18 @@
 
 use feature_refining::high_level_substitutions2::HLSubstitutionList;
 use feature_refining::dictionary::{load_dictionary, DictionaryWord};
-use feature_refining::astarlike2::distanceg;
+use feature_refining::distance_metrics::load_metric;
 use feature_refining::glyphs::{strip_aug, Glyph, encode, augment};
 use float_ord::FloatOrd;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    #[arg(long)]
+    costs: Option<String>,
+}
 
 struct ProError<'d> {
   word: &'d DictionaryWord,
@@ -12,13 +21,16 @@ struct ProError<'d> {
 }
   
 fn main() {
+  let args = Args::parse();
+  let metric = load_metric(args.costs.as_deref()).unwrap();
+  
   let hlist = HLSubstitutionList::set_1();
     
   let dictionary = load_dictionary().unwrap();
   
   let mut errors: Vec<ProError> = dictionary.words.iter().map(|word| {
     let transformed_spelling = strip_aug(&hlist.apply_copied_always(&augment(&word.spelling)));
-    let error = distanceg(&transformed_spelling, &word.pronunciation);
+    let error = metric.distanceg(&transformed_spelling, &word.pronunciation);
     let weighted_error = word.frequency * (error as f64);
     
     ProError {
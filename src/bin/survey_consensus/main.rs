@@ -1,10 +1,29 @@
-
+use clap::Parser;
+use feature_refining::alignment::AlignmentModel;
 use feature_refining::dictionary::load_dictionary;
 use feature_refining::consensus_finding::build_justification_table;
 use feature_refining::high_level_substitutions::HLSubstitution;
+use feature_refining::distance_metrics::load_metric;
 use float_ord::FloatOrd;
 
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    /// Alignment model from align_dictionary. If given, each word only
+    /// justifies what its alignment suggests.
+    #[arg(long)]
+    alignment: Option<String>,
+
+    /// Confusion-cost file for a weighted distance. Defaults to Levenshtein.
+    #[arg(long)]
+    costs: Option<String>,
+}
+
 fn main() {
+  let args = Args::parse();
+  let alignment = args.alignment.as_ref().map(|path| AlignmentModel::load(path).unwrap());
+  let metric = load_metric(args.costs.as_deref()).unwrap();
+  
   let mut dictioary = load_dictionary().unwrap();
   
   println!("Loaded dictionary with {} words.", dictioary.words.len());
@@ -14,7 +33,7 @@ fn main() {
   println!("Truncated to {} words.", dictioary.words.len());
   
   println!("Building justification table...");
-  let jt = build_justification_table(&dictioary);
+  let jt = build_justification_table(&dictioary, alignment.as_ref(), &*metric);
   println!("Done.");
   
   let mut edits: Vec<(HLSubstitution, f64)> = jt.by_sub.into_iter().collect();
@@ -25,4 +44,3 @@ fn main() {
     println!("{:?}", edit);
   }
 }
-
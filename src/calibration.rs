@@ -0,0 +1,257 @@
+
+/* Calibrating the Gaussian estimators against real edits.
+ *
+ * gaussian_expectation_table2 assumes every word it hasn't looked at yet could
+ * move by about its frequency in either direction, except that words already
+ * at distance 0 can't improve. Real edits mostly leave most words alone, and
+ * how far a word can improve depends on how far off it is.
+ *
+ * This samples candidate edits the way the search would propose them, applies
+ * each to every word, and measures the changes by current distance. The
+ * root-mean-square worsening and improvement at each distance become a
+ * Calibration. check_bounds then replays the sampled edits through a table to
+ * see how often the best and worst possible bounds at introduction were wrong,
+ * and what scale would have kept them right.
+ */
+
+use crate::astarlike2::{AugDictionary, AugDictionaryWord, find_improving_edits};
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList};
+use crate::distance_metrics::DistanceMetric;
+use crate::gaussian_expectation_table2::{self as get2, Calibration};
+use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
+use rayon::prelude::*;
+use float_ord::FloatOrd;
+use std::collections::HashSet;
+
+pub struct SampledEdit {
+  pub edit: HLSubstitution,
+  /// The first word in introducing order that proposes it, which is where the
+  /// search would introduce it.
+  pub introducing_i: usize,
+  /// How much it changes each word's distance, in introducing order.
+  pub changes: Vec<i32>
+}
+
+impl SampledEdit {
+  pub fn weighted_change(&self, frequencies_in_introducing_order: &[f64]) -> f64 {
+    self.changes.iter().zip(frequencies_in_introducing_order).map(|(&c, f)| (c as f64) * f).sum()
+  }
+}
+
+pub struct Samples {
+  pub frequencies_in_introducing_order: Vec<f64>,
+  pub current_distances_in_introducing_order: Vec<u32>,
+  pub edits: Vec<SampledEdit>
+}
+
+/// Takes every edit the first `words` words in introducing order propose, and
+/// keeps a random `edits` of them.
+pub fn sample_edits(dictionary: &AugDictionary, rules: &HLSubstitutionList, metric: &dyn DistanceMetric, words: usize, edits: usize, seed: u64) -> Samples {
+  let n = dictionary.words.len();
+  let current_distances: Vec<u32> = dictionary.words.iter().map(|w| {
+    metric.distance(&rules.apply_posterior_copied(&w.transformed_spelling), &w.base_pronunciation)
+  }).collect();
+
+  // The same order as genastarlike::init_ref_data.
+  let mut introducing_order: Vec<usize> = (0 .. n).collect();
+  introducing_order.sort_by_key(|&i| FloatOrd(-dictionary.words[i].frequency * (current_distances[i] as f64)));
+  let ordered: Vec<&AugDictionaryWord> = introducing_order.iter().map(|&j| &dictionary.words[j]).collect();
+  let current_distances_in_introducing_order: Vec<u32> = introducing_order.iter().map(|&j| current_distances[j]).collect();
+
+  let mid = rules.next_open_mid();
+  let mut seen: HashSet<HLSubstitution> = HashSet::new();
+  let mut candidates: Vec<(HLSubstitution, usize)> = vec![];
+  for (i, w) in ordered.iter().enumerate().take(words) {
+    let base_distance = current_distances_in_introducing_order[i];
+    for e in find_improving_edits(&w.transformed_spelling, &w.back_transformed_pronunciation, rules, base_distance, mid, false, &|out| metric.distance(out, &w.base_pronunciation)) {
+      if seen.insert(e.sub.clone()) {
+        candidates.push((e.sub, i));
+      }
+    }
+  }
+  candidates.shuffle(&mut SmallRng::seed_from_u64(seed));
+  candidates.truncate(edits);
+
+  let edits = candidates.into_par_iter().map(|(edit, introducing_i)| {
+    let changes = ordered.iter().zip(&current_distances_in_introducing_order).map(|(w, &d)| {
+      match edit.apply_copied(&w.transformed_spelling) {
+        Some(new_step_one) => (metric.distance(&rules.apply_posterior_copied(&new_step_one), &w.base_pronunciation) as i32) - (d as i32),
+        None => 0
+      }
+    }).collect();
+    SampledEdit { edit, introducing_i, changes }
+  }).collect();
+
+  Samples {
+    frequencies_in_introducing_order: ordered.iter().map(|w| w.frequency).collect(),
+    current_distances_in_introducing_order,
+    edits
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceFit {
+  pub distance: u32,
+  /// (edit, word) pairs at this distance, not counting introducing words.
+  pub pairs: usize,
+  /// Of those, how many the edit changed at all.
+  pub changed: usize,
+  pub worsen_sigma: f64,
+  pub improve_sigma: f64
+}
+
+/// The fit at each distance up to `max_distance`, which also takes in everything
+/// further off. A distance with no pairs gets the fit of the one before it.
+pub fn fit_by_distance(samples: &Samples, max_distance: u32) -> Vec<DistanceFit> {
+  let buckets = max_distance as usize + 1;
+  let mut pairs = vec![0; buckets];
+  let mut changed = vec![0; buckets];
+  let mut worsen_ss = vec![0.0; buckets];
+  let mut improve_ss = vec![0.0; buckets];
+
+  for e in &samples.edits {
+    for (i, &c) in e.changes.iter().enumerate() {
+      if i == e.introducing_i {
+        continue;
+      }
+      let b = (samples.current_distances_in_introducing_order[i] as usize).min(buckets - 1);
+      pairs[b] += 1;
+      if c != 0 {
+        changed[b] += 1;
+      }
+      let cc = (c as f64) * (c as f64);
+      if c > 0 {
+        worsen_ss[b] += cc;
+      }
+      else {
+        improve_ss[b] += cc;
+      }
+    }
+  }
+
+  let default = Calibration::default();
+  let mut res: Vec<DistanceFit> = vec![];
+  for b in 0 .. buckets {
+    let (worsen_sigma, improve_sigma) = if pairs[b] > 0 {
+      ((worsen_ss[b] / pairs[b] as f64).sqrt(), (improve_ss[b] / pairs[b] as f64).sqrt())
+    }
+    else {
+      match res.last() {
+        Some(prev) => (prev.worsen_sigma, prev.improve_sigma),
+        None => (default.worsen(0), default.improve(0))
+      }
+    };
+    res.push(DistanceFit { distance: b as u32, pairs: pairs[b], changed: changed[b], worsen_sigma, improve_sigma });
+  }
+  res
+}
+
+pub fn fit(samples: &Samples, max_distance: u32) -> Calibration {
+  let fits = fit_by_distance(samples, max_distance);
+  Calibration {
+    worsen_sigma: fits.iter().map(|f| f.worsen_sigma).collect(),
+    improve_sigma: fits.iter().map(|f| f.improve_sigma).collect()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundCheck {
+  pub edits: usize,
+  /// Edits that did better than their best possible at introduction.
+  pub below_best: usize,
+  /// Edits that did worse than their worst possible.
+  pub above_worst: usize,
+  /// For each edit, the smallest scale whose bounds would have held, ascending.
+  needed_scales: Vec<f64>
+}
+
+impl BoundCheck {
+  pub fn violation_rate(&self) -> f64 {
+    if self.edits == 0 { 0.0 } else { (self.below_best + self.above_worst) as f64 / self.edits as f64 }
+  }
+
+  /// The smallest scale at which no more than `rate` of the edits would have
+  /// broken a bound. Infinite if some of those edits moved where the table
+  /// expects no movement at all.
+  pub fn scale_for(&self, rate: f64) -> f64 {
+    if self.needed_scales.is_empty() {
+      return 0.0;
+    }
+    let allowed = ((rate * self.edits as f64).floor() as usize).min(self.edits - 1);
+    self.needed_scales[self.edits - 1 - allowed]
+  }
+}
+
+/// Replays every sampled edit through a table built with `calibration`.
+pub fn check_bounds(samples: &Samples, calibration: &Calibration, scale: f64) -> BoundCheck {
+  let freqs = &samples.frequencies_in_introducing_order;
+  let table = get2::build_calibrated_expectation_table(freqs, &samples.current_distances_in_introducing_order, calibration);
+
+  let mut res = BoundCheck { edits: samples.edits.len(), below_best: 0, above_worst: 0, needed_scales: vec![] };
+  for e in &samples.edits {
+    let estimate = get2::introduce_edit(freqs, &table, e.introducing_i, e.changes[e.introducing_i]);
+    let actual = e.weighted_change(freqs);
+    if actual < get2::calc_best_possible(&estimate, scale) {
+      res.below_best += 1;
+    }
+    if actual > get2::calc_worst_possible(&estimate, scale) {
+      res.above_worst += 1;
+    }
+
+    let deviation = actual - estimate.actual_weighted_change;
+    let sigma = if deviation < 0.0 {
+      estimate.after_nonzero_ss_freq.max(0.0).sqrt()
+    }
+    else {
+      (estimate.before_ss_freq + estimate.after_ss_freq).max(0.0).sqrt()
+    };
+    res.needed_scales.push(if deviation == 0.0 { 0.0 } else { deviation.abs() / sigma });
+  }
+  res.needed_scales.sort_by_key(|&s| FloatOrd(s));
+  res
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::astarlike2::IterativeSystem;
+  use crate::distance_metrics::Levenshtein;
+
+  fn sample() -> Samples {
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(200);
+    let system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+    sample_edits(&system.dictionary, &system.rules, &Levenshtein, 20, 100, 0)
+  }
+
+  #[test]
+  fn fits_real_edits() {
+    let samples = sample();
+    assert_eq!(samples.edits.len(), 100);
+    assert!(samples.edits.iter().all(|e| e.changes[e.introducing_i] < 0));
+    let again = sample();
+    assert_eq!(samples.edits.iter().map(|e| e.edit.encode()).collect::<Vec<_>>(), again.edits.iter().map(|e| e.edit.encode()).collect::<Vec<_>>());
+
+    let fits = fit_by_distance(&samples, 4);
+    assert_eq!(fits.len(), 5);
+    // A word that's already right can only get worse.
+    assert_eq!(fits[0].improve_sigma, 0.0);
+    // Most edits leave most words alone, so the default is far too wide.
+    assert!(fits.iter().all(|f| f.worsen_sigma < 1.0 && f.changed <= f.pairs));
+    assert_eq!(fit(&samples, 4).worsen_sigma.len(), 5);
+  }
+
+  #[test]
+  fn bounds_hold_at_the_needed_scale() {
+    let samples = sample();
+    let calibration = fit(&samples, 4);
+    let check = check_bounds(&samples, &calibration, 4.0);
+    assert_eq!(check.edits, 100);
+
+    let scale = check.scale_for(0.05);
+    assert!(scale.is_finite());
+    assert!(check_bounds(&samples, &calibration, scale).violation_rate() <= 0.05);
+    assert_eq!(check_bounds(&samples, &calibration, check.scale_for(0.0)).violation_rate(), 0.0);
+    assert!(check.scale_for(0.0) >= scale);
+  }
+}
@@ -2,16 +2,13 @@
 use crate::glyphs::{Glyph, AugGlyph};
 use AugGlyph::*;
 use crate::high_level_substitutions::{HLSubstitution, HLSubstitutionList, hl_to_ll};
-use levenshtein_diff as levenshtein;
 use crate::substitutions2::apply_all;
 use crate::dictionary::Dictionary;
-use std::collections::HashMap;
+use crate::alignment::AlignmentModel;
+use crate::distance_metrics::DistanceMetric;
+use std::collections::{HashMap, HashSet};
 use float_ord::FloatOrd;
 
-fn distance(p1: &Vec<Glyph>, p2: &Vec<Glyph>) -> u32 {
-  levenshtein::distance(p1, p2).0 as u32
-}
-
 fn apply_copied(v: &Vec<Glyph>, hl_slist: &HLSubstitutionList) -> Vec<Glyph> {
   let slist = hl_to_ll(hl_slist).unwrap();
   let mut working = v.iter().map(|g| Real(g.clone())).collect();
@@ -22,11 +19,11 @@ fn apply_copied(v: &Vec<Glyph>, hl_slist: &HLSubstitutionList) -> Vec<Glyph> {
   }).collect()
 }
 
-pub fn find_improving_edits(word: &Vec<Glyph>, pronunciation: &Vec<Glyph>, init_hl_slist: &HLSubstitutionList) -> Vec<(HLSubstitution, u32)> {
+pub fn find_improving_edits(word: &Vec<Glyph>, pronunciation: &Vec<Glyph>, init_hl_slist: &HLSubstitutionList, metric: &dyn DistanceMetric) -> Vec<(HLSubstitution, u32)> {
   let mut res = vec![];
   
   let base_transformed = apply_copied(word, init_hl_slist);
-  let base_distance = distance(&base_transformed, pronunciation);
+  let base_distance = metric.distanceg(&base_transformed, pronunciation);
   
   let mut working_hl_slist = init_hl_slist.clone();
   working_hl_slist.substitutions.insert(0, HLSubstitution {
@@ -57,7 +54,7 @@ pub fn find_improving_edits(word: &Vec<Glyph>, pronunciation: &Vec<Glyph>, init_
                   };
                   working_hl_slist.substitutions[0] = hlsub;
                   let new_transformed = apply_copied(word, &working_hl_slist);
-                  let new_distance = distance(&new_transformed, pronunciation);
+                  let new_distance = metric.distanceg(&new_transformed, pronunciation);
                   if new_distance < base_distance {
                     res.push((working_hl_slist.substitutions[0].clone(), base_distance - new_distance));
                   }
@@ -73,6 +70,54 @@ pub fn find_improving_edits(word: &Vec<Glyph>, pronunciation: &Vec<Glyph>, init_
   res
 }
 
+/// The edits find_improving_edits would find that the word's alignment suggests:
+/// each chunk or pair of neighbouring chunks that AlignmentModel proposes, with
+/// up to one letter of context either side. Words the model can't align get
+/// every edit.
+pub fn find_aligned_edits(word: &Vec<Glyph>, pronunciation: &Vec<Glyph>, init_hl_slist: &HLSubstitutionList, model: &AlignmentModel, metric: &dyn DistanceMetric) -> Vec<(HLSubstitution, u32)> {
+  let Some(alignment) = model.align(word, pronunciation) else {
+    return find_improving_edits(word, pronunciation, init_hl_slist, metric);
+  };
+  let mut res = vec![];
+  
+  let base_transformed = apply_copied(word, init_hl_slist);
+  let base_distance = metric.distanceg(&base_transformed, pronunciation);
+  
+  let mut working_hl_slist = init_hl_slist.clone();
+  let mut seen = HashSet::new();
+  for (i, i2, j, j2) in alignment.spans(model.min_prob) {
+    for pre in 0 ..= i.min(1) {
+      for post in 0 ..= (word.len() - i2).min(1) {
+        let (k1, k2) = (i - pre, i2 + post);
+        for &at_start in if k1 == 0 {[true, false].iter()} else {[false].iter()} {
+          for &at_end in if k2 == word.len() {[true, false].iter()} else {[false].iter()} {
+            let hlsub = HLSubstitution {
+              key: word[k1 .. k2].to_vec(),
+              sub_start: pre,
+              sub_end: pre + i2 - i,
+              at_start,
+              at_end,
+              sub_content: pronunciation[j .. j2].to_vec()
+            };
+            if !seen.insert(hlsub.clone()) {
+              continue;
+            }
+            working_hl_slist.substitutions.insert(0, hlsub);
+            let new_transformed = apply_copied(word, &working_hl_slist);
+            let hlsub = working_hl_slist.substitutions.remove(0);
+            let new_distance = metric.distanceg(&new_transformed, pronunciation);
+            if new_distance < base_distance {
+              res.push((hlsub, base_distance - new_distance));
+            }
+          }
+        }
+      }
+    }
+  }
+  
+  res
+}
+
 pub struct WordEntry {
   pub substitutions: Vec<(HLSubstitution, u32)>,
   pub frequency: f64
@@ -87,19 +132,19 @@ fn sub_size(sub: &HLSubstitution) -> usize {
   sub.key.len() + (if sub.at_start { 1 } else { 0 }) + (if sub.at_end { 1 } else { 0 })
 }
 
-pub fn build_justification_table(dictionary: &Dictionary) -> JustificationTable {
+/// With `alignment`, each word only justifies what its alignment suggests.
+pub fn build_justification_table(dictionary: &Dictionary, alignment: Option<&AlignmentModel>, metric: &dyn DistanceMetric) -> JustificationTable {
   //use rayon::prelude::*;
   
   let by_word_entries: Vec<(Vec<Glyph>, WordEntry)> = dictionary.words.iter().map(|dictionary_word| {
+    let empty = HLSubstitutionList { substitutions: vec![] };
     (
       dictionary_word.spelling.clone(),
       WordEntry {
-        substitutions:
-          find_improving_edits(
-            &dictionary_word.spelling,
-            &dictionary_word.pronunciation, 
-            &HLSubstitutionList { substitutions: vec![] }
-          ),
+        substitutions: match alignment {
+          Some(model) => find_aligned_edits(&dictionary_word.spelling, &dictionary_word.pronunciation, &empty, model, metric),
+          None => find_improving_edits(&dictionary_word.spelling, &dictionary_word.pronunciation, &empty, metric)
+        },
         frequency: dictionary_word.frequency
       }
     )
@@ -130,23 +175,23 @@ pub fn build_justification_table(dictionary: &Dictionary) -> JustificationTable
   }
 }
 
-pub fn dictionary_base_score(dictionary: &Dictionary) -> f64 {
+pub fn dictionary_base_score(dictionary: &Dictionary, metric: &dyn DistanceMetric) -> f64 {
   let mut total: f64 = 0.0;
   
   for word in &dictionary.words {
-    let score = distance(&word.spelling, &word.pronunciation) as f64;
+    let score = metric.distanceg(&word.spelling, &word.pronunciation) as f64;
     total = total + score * (word.frequency as f64);
   }
   
   total
 }
 
-pub fn score_dictionary_transformer<F: Fn(&Vec<Glyph>) -> Vec<Glyph>>(dictionary: &Dictionary, transformer: F) -> f64 {
+pub fn score_dictionary_transformer<F: Fn(&Vec<Glyph>) -> Vec<Glyph>>(dictionary: &Dictionary, metric: &dyn DistanceMetric, transformer: F) -> f64 {
   let mut total: f64 = 0.0;
   
   for word in &dictionary.words {
     let transformed = transformer(&word.spelling);
-    let d = distance(&transformed, &word.pronunciation);
+    let d = metric.distanceg(&transformed, &word.pronunciation);
     let score = d as f64;
     total = total + score * (word.frequency as f64);
   }
@@ -154,19 +199,26 @@ pub fn score_dictionary_transformer<F: Fn(&Vec<Glyph>) -> Vec<Glyph>>(dictionary
   total
 }
 
-pub fn score_hl_slist(hl_slist: &HLSubstitutionList, dictionary: &Dictionary) -> f64 {
-  score_dictionary_transformer(dictionary, |word| {
+pub fn score_hl_slist(hl_slist: &HLSubstitutionList, dictionary: &Dictionary, metric: &dyn DistanceMetric) -> f64 {
+  score_dictionary_transformer(dictionary, metric, |word| {
     apply_copied(word, &hl_slist)
   })
 }
 
-pub fn rate_edit(edit: &HLSubstitution, starting_hl_slist: &HLSubstitutionList, dictionary: &Dictionary) -> f64 {
+pub fn score_hl2_slist(hl_slist: &crate::high_level_substitutions2::HLSubstitutionList, dictionary: &Dictionary, metric: &dyn DistanceMetric) -> f64 {
+  use crate::glyphs::{augment, strip_aug};
+  score_dictionary_transformer(dictionary, metric, |word| {
+    strip_aug(&hl_slist.apply_copied_always(&augment(word)))
+  })
+}
+
+pub fn rate_edit(edit: &HLSubstitution, starting_hl_slist: &HLSubstitutionList, dictionary: &Dictionary, metric: &dyn DistanceMetric) -> f64 {
   let mut hl_slist = starting_hl_slist.clone();
   hl_slist.substitutions.insert(0, edit.clone());
-  score_hl_slist(&hl_slist, dictionary)
+  score_hl_slist(&hl_slist, dictionary, metric)
 }
 
-pub fn rate_top_edits<'a>(jt: &'a JustificationTable, max_to_rate: usize, starting_hl_slist: &HLSubstitutionList, dictionary: &Dictionary) -> Vec<(&'a HLSubstitution, f64)> {
+pub fn rate_top_edits<'a>(jt: &'a JustificationTable, max_to_rate: usize, starting_hl_slist: &HLSubstitutionList, dictionary: &Dictionary, metric: &dyn DistanceMetric) -> Vec<(&'a HLSubstitution, f64)> {
   //use rayon::prelude::*;
   
   let mut best: Vec<(&'a HLSubstitution, &f64)> = jt.by_sub.iter().collect();
@@ -174,7 +226,7 @@ pub fn rate_top_edits<'a>(jt: &'a JustificationTable, max_to_rate: usize, starti
   best.truncate(max_to_rate);
   
   let mut rated: Vec<(&'a HLSubstitution, f64)> = best.into_iter().map(|(edit, _)| {
-    (edit, rate_edit(edit, starting_hl_slist, dictionary))
+    (edit, rate_edit(edit, starting_hl_slist, dictionary, metric))
   }).collect();
   rated.sort_by_key(|(_, f)| FloatOrd(*f));
   
@@ -186,6 +238,7 @@ mod tests {
   use super::*;
   use Glyph::*;
   use crate::dictionary::DictionaryWord;
+  use crate::distance_metrics::{Levenshtein, WeightedDistance};
   use float_ord::FloatOrd;
   
   #[test]
@@ -195,7 +248,7 @@ mod tests {
     let init_hl_slist = HLSubstitutionList {
       substitutions: vec![]
     };
-    let found = find_improving_edits(&word, &pronunciation, &init_hl_slist);
+    let found = find_improving_edits(&word, &pronunciation, &init_hl_slist, &Levenshtein);
     let found: Vec<HLSubstitution> = found.into_iter().map(|(s, _)| s).collect();
     assert!(found.contains(& HLSubstitution { key: vec![E, I], sub_start: 0, sub_end: 1, at_start: false, at_end: false, sub_content: vec![Ei] }));
   }
@@ -207,7 +260,7 @@ mod tests {
     let init_hl_slist = HLSubstitutionList {
       substitutions: vec![]
     };
-    let found = find_improving_edits(&word, &pronunciation, &init_hl_slist);
+    let found = find_improving_edits(&word, &pronunciation, &init_hl_slist, &Levenshtein);
     let found: Vec<HLSubstitution> = found.into_iter().map(|(s, _)| s).collect();
     assert!(found.contains(& HLSubstitution { key: vec![T, H, E], sub_start: 0, sub_end: 3, at_start: true, at_end: true, sub_content: vec![Dh, Eh] }));
   }
@@ -228,7 +281,7 @@ mod tests {
         }
       ]
     };
-    let table = build_justification_table(&dictionary);
+    let table = build_justification_table(&dictionary, None, &Levenshtein);
     
     assert_eq!((*table.by_sub.get(&HLSubstitution { key: vec![G, H], sub_start: 0, sub_end: 2, at_start: false, at_end: false, sub_content: vec![T] }).unwrap()*10.0) as u32, 13);
     
@@ -249,13 +302,50 @@ mod tests {
     }
   }
   
+  #[test]
+  fn aligned_edits_test_1() {
+    use crate::glyphs::decode;
+    let dictionary = Dictionary {
+      words: [("the", "ϑʌ"), ("this", "ϑɪs"), ("that", "ϑæt"), ("thin", "ϑɪn"), ("sat", "sæt"), ("hat", "hæt"), ("ax", "æks")].iter().map(|(s, p)| DictionaryWord {
+        spelling: decode(s),
+        pronunciation: decode(p),
+        frequency: 1.0
+      }).collect()
+    };
+    let model = AlignmentModel::train(&dictionary, 2, 1, 10);
+    let empty = HLSubstitutionList { substitutions: vec![] };
+
+    let (word, pronunciation) = (decode("that"), decode("ϑæt"));
+    let aligned = find_aligned_edits(&word, &pronunciation, &empty, &model, &Levenshtein);
+    let all = find_improving_edits(&word, &pronunciation, &empty, &Levenshtein);
+    assert!(aligned.contains(&(HLSubstitution { key: vec![T, H], sub_start: 0, sub_end: 2, at_start: true, at_end: false, sub_content: vec![Dh] }, 2)));
+    assert!(aligned.iter().all(|e| all.contains(e)));
+    assert!(aligned.len() < all.len());
+
+    // One letter can't be two sounds, so "ax" can't be aligned and gets everything.
+    let (word, pronunciation) = (decode("ax"), decode("æks"));
+    assert!(model.align(&word, &pronunciation).is_none());
+    assert_eq!(find_aligned_edits(&word, &pronunciation, &empty, &model, &Levenshtein), find_improving_edits(&word, &pronunciation, &empty, &Levenshtein));
+
+    let table = build_justification_table(&dictionary, Some(&model), &Levenshtein);
+    assert!(table.by_word[&decode("ax")].substitutions.len() == find_improving_edits(&word, &pronunciation, &empty, &Levenshtein).len());
+  }
+
   #[test]
   fn distance_test_1() {
     let a = vec![E, I, G, H, T];
     let b = vec![Ei, T];
-    let d = distance(&a, &b);
+    let d = Levenshtein.distanceg(&a, &b);
     println!("{:?} to {:?} = {}", a, b, d);
     assert_eq!(d, 4);
+
+    // Improvements are in the metric's units.
+    let metric = WeightedDistance::decode("substitute 2\nindel 2\nvowels 1").unwrap();
+    let empty = HLSubstitutionList { substitutions: vec![] };
+    let edit = HLSubstitution { key: vec![G, H, T], sub_start: 0, sub_end: 3, at_start: false, at_end: true, sub_content: vec![T] };
+    assert!(find_improving_edits(&a, &b, &empty, &Levenshtein).contains(&(edit.clone(), 2)));
+    assert!(find_improving_edits(&a, &b, &empty, &metric).contains(&(edit, 4)));
+    assert_eq!(dictionary_base_score(&Dictionary { words: vec![DictionaryWord { spelling: a, pronunciation: b, frequency: 0.5 }] }, &metric), 3.5);
   }
   
   #[test]
@@ -279,7 +369,7 @@ mod tests {
       substitutions: vec![]
     };
     
-    let base_score = score_hl_slist(&init_hl_slist, &dictionary);
+    let base_score = score_hl_slist(&init_hl_slist, &dictionary, &Levenshtein);
     println!("base_score = {}", base_score);
     
     let edit1 = HLSubstitution {
@@ -300,8 +390,8 @@ mod tests {
       sub_content: vec![T],
     };
     
-    let score1 = rate_edit(&edit1, &init_hl_slist, &dictionary);
-    let score2 = rate_edit(&edit2, &init_hl_slist, &dictionary);
+    let score1 = rate_edit(&edit1, &init_hl_slist, &dictionary, &Levenshtein);
+    let score2 = rate_edit(&edit2, &init_hl_slist, &dictionary, &Levenshtein);
     
     println!("score1 = {}", score1);
     println!("score2 = {}", score2);
@@ -339,7 +429,7 @@ mod tests {
       ]
     };
     
-    let base_score = score_hl_slist(&init_hl_slist, &dictionary);
+    let base_score = score_hl_slist(&init_hl_slist, &dictionary, &Levenshtein);
     println!("base_score = {}", base_score);
     
     let edit1 = HLSubstitution {
@@ -360,8 +450,8 @@ mod tests {
       sub_content: vec![T],
     };
     
-    let score1 = rate_edit(&edit1, &init_hl_slist, &dictionary);
-    let score2 = rate_edit(&edit2, &init_hl_slist, &dictionary);
+    let score1 = rate_edit(&edit1, &init_hl_slist, &dictionary, &Levenshtein);
+    let score2 = rate_edit(&edit2, &init_hl_slist, &dictionary, &Levenshtein);
     
     println!("score1 = {}", score1);
     println!("score2 = {}", score2);
@@ -385,7 +475,7 @@ mod tests {
         },
       ]
     };
-    let table = build_justification_table(&dictionary);
+    let table = build_justification_table(&dictionary, None, &Levenshtein);
     
     let mut best: Vec<(HLSubstitution, f64)> = table.by_sub.iter().map(|(s,f)|(s.clone(), *f)).collect();
     best.sort_by_key(|(_, f)| FloatOrd(-*f));
@@ -408,7 +498,7 @@ mod tests {
       ]
     };
     
-    let top_edits = rate_top_edits(&table, 10, &init_hl_slist, &dictionary);
+    let top_edits = rate_top_edits(&table, 10, &init_hl_slist, &dictionary, &Levenshtein);
     for (sub, f) in top_edits.iter().take(10) {
       println!("{:.3} {:?}", f, sub);
     }
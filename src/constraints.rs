@@ -0,0 +1,258 @@
+
+/* Hard constraints on what the rule searches may do to particular words.
+ *
+ * A protected word may only ever read as one of its allowed forms (by default
+ * just its own spelling), so a candidate rule that would rewrite it into anything
+ * else is rejected outright. A must-hit word has to end up reading as its
+ * pronunciation; until it does, the search objective is charged a penalty for it,
+ * so rules that get it there look that much better.
+ *
+ * Constraint files are plain text, one constraint per line, with # starting a
+ * comment:
+ *
+ *   protect the        # never rewritten
+ *   protect of ov əv   # may become ov or əv, but nothing else
+ *   hit cat            # has to read as its pronunciation
+ *   penalty 20         # per missed word, before frequency; defaults to 10
+ */
+
+use crate::glyphs::{AugGlyph, aug_decode, aug_encode, augment};
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList};
+use crate::objective::Objective;
+use crate::dictionary::Dictionary;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraints {
+  /// Spellings, and the forms each may read as.
+  pub protected: HashMap<Vec<AugGlyph>, Vec<Vec<AugGlyph>>>,
+  pub must_hit: HashSet<Vec<AugGlyph>>,
+  pub miss_penalty: u32
+}
+
+impl Default for Constraints {
+  fn default() -> Constraints {
+    Constraints {
+      protected: HashMap::new(),
+      must_hit: HashSet::new(),
+      miss_penalty: 10
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+  /// A protected word reads as something it isn't allowed to.
+  Rewritten { spelling: Vec<AugGlyph>, output: Vec<AugGlyph> },
+  /// A must-hit word doesn't read as its pronunciation yet.
+  Missed { spelling: Vec<AugGlyph>, output: Vec<AugGlyph>, pronunciation: Vec<AugGlyph> }
+}
+
+impl std::fmt::Display for Violation {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Violation::Rewritten { spelling, output } => write!(f, "protected {} reads as {}", aug_encode(spelling), aug_encode(output)),
+      Violation::Missed { spelling, output, pronunciation } => write!(f, "{} reads as {}, not {}", aug_encode(spelling), aug_encode(output), aug_encode(pronunciation))
+    }
+  }
+}
+
+impl Constraints {
+  pub fn decode(text: &str) -> Result<Constraints, String> {
+    let mut res = Constraints::default();
+
+    for (i, line) in text.lines().enumerate() {
+      let err = |msg: String| format!("line {}: {}", i + 1, msg);
+      let line = line.split('#').next().unwrap().trim();
+      if line.is_empty() {
+        continue;
+      }
+
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      match fields.as_slice() {
+        ["protect", word, allowed @ ..] => {
+          let word = aug_decode(word);
+          let mut forms = vec![word.clone()];
+          forms.extend(allowed.iter().map(|a| aug_decode(a)));
+          res.protected.insert(word, forms);
+        },
+        ["hit", word] => {
+          res.must_hit.insert(aug_decode(word));
+        },
+        ["penalty", p] => res.miss_penalty = p.parse().map_err(|_| err(format!("bad penalty {:?}", p)))?,
+        _ => return Err(err(format!("can't parse {:?}", line)))
+      }
+    }
+
+    Ok(res)
+  }
+
+  pub fn load(path: &str) -> Result<Constraints, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Constraints::decode(&text).map_err(|e| format!("{}: {}", path, e))
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.protected.is_empty() && self.must_hit.is_empty()
+  }
+
+  pub fn allows(&self, spelling: &[AugGlyph], output: &[AugGlyph]) -> bool {
+    match self.protected.get(spelling) {
+      Some(forms) => forms.iter().any(|f| f == output),
+      None => true
+    }
+  }
+
+  /// What a word costs on top of the objective's own word cost.
+  pub fn penalty(&self, output: &[AugGlyph], pronunciation: &[AugGlyph], spelling: &[AugGlyph]) -> u32 {
+    if output != pronunciation && self.must_hit.contains(spelling) {
+      self.miss_penalty
+    }
+    else {
+      0
+    }
+  }
+
+  /// Every constraint that `outputs`, in dictionary order, break.
+  pub fn violations(&self, dictionary: &Dictionary, outputs: &[Vec<AugGlyph>]) -> Vec<Violation> {
+    let mut res = vec![];
+    for (w, output) in dictionary.words.iter().zip(outputs) {
+      let (spelling, pronunciation) = (augment(&w.spelling), augment(&w.pronunciation));
+      if !self.allows(&spelling, output) {
+        res.push(Violation::Rewritten { spelling: spelling.clone(), output: output.clone() });
+      }
+      if self.penalty(output, &pronunciation, &spelling) > 0 {
+        res.push(Violation::Missed { spelling, output: output.clone(), pronunciation });
+      }
+    }
+    res
+  }
+
+  /// The protected words as `rules` leave them, ready for checking candidates.
+  pub fn guard(&self, rules: &HLSubstitutionList) -> ProtectionGuard {
+    ProtectionGuard {
+      words: self.protected.iter().map(|(spelling, forms)| {
+        let mut transformed = spelling.clone();
+        for s in &rules.substitutions {
+          s.apply_anterior(&mut transformed);
+        }
+        GuardedWord {
+          current: rules.apply_posterior_copied(&transformed),
+          transformed,
+          forms: forms.clone(),
+          spelling: spelling.clone()
+        }
+      }).collect()
+    }
+  }
+}
+
+struct GuardedWord {
+  spelling: Vec<AugGlyph>,
+  transformed: Vec<AugGlyph>,
+  forms: Vec<Vec<AugGlyph>>,
+  current: Vec<AugGlyph>
+}
+
+/// Checks candidate rules against the protected words, for one set of prior rules.
+pub struct ProtectionGuard {
+  words: Vec<GuardedWord>
+}
+
+impl ProtectionGuard {
+  /// The violation adding `sub` after `rules` would cause, if any. A word that
+  /// already reads as something not allowed only counts if `sub` changes it.
+  pub fn rejects(&self, rules: &HLSubstitutionList, sub: &HLSubstitution) -> Option<Violation> {
+    self.words.iter().find_map(|w| {
+      let output = rules.apply_posterior_copied(&sub.apply_copied(&w.transformed)?);
+      if output != w.current && !w.forms.contains(&output) {
+        Some(Violation::Rewritten { spelling: w.spelling.clone(), output })
+      }
+      else {
+        None
+      }
+    })
+  }
+}
+
+/// Adds the must-hit penalties to another objective's word costs.
+pub struct ConstrainedObjective {
+  pub inner: Arc<dyn Objective>,
+  pub constraints: Arc<Constraints>
+}
+
+impl Objective for ConstrainedObjective {
+  fn word_cost(&self, output: &[AugGlyph], pronunciation: &[AugGlyph], spelling: &[AugGlyph]) -> u32 {
+    self.inner.word_cost(output, pronunciation, spelling) + self.constraints.penalty(output, pronunciation, spelling)
+  }
+
+  fn rule_cost(&self, rules: &HLSubstitutionList, sub: &HLSubstitution) -> f64 {
+    self.inner.rule_cost(rules, sub)
+  }
+
+  fn dictionary_cost(&self, outputs: &[Vec<AugGlyph>], dictionary: &Dictionary) -> f64 {
+    self.inner.dictionary_cost(outputs, dictionary)
+  }
+
+  fn has_dictionary_cost(&self) -> bool {
+    self.inner.has_dictionary_cost()
+  }
+
+  fn word_unit(&self) -> f64 {
+    self.inner.word_unit()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::astarlike2::{IterativeSystem, Outcome};
+  use crate::dictionary::from_words as dictionary;
+  use crate::gaussian_astarlike22::GaussianSystem;
+
+  #[test]
+  fn decode_and_check() {
+    assert!(Constraints::load("res/constraints/function_words.constraints").is_ok());
+    let constraints = Constraints::decode("
+      protect the
+      protect of ov əv  # either is fine
+      hit cat
+      penalty 3
+    ").unwrap();
+    assert!(constraints.allows(&aug_decode("the"), &aug_decode("the")));
+    assert!(!constraints.allows(&aug_decode("the"), &aug_decode("ϑe")));
+    assert!(constraints.allows(&aug_decode("of"), &aug_decode("əv")));
+    assert!(constraints.allows(&aug_decode("this"), &aug_decode("ϑis")));
+    assert_eq!(constraints.penalty(&aug_decode("cat"), &aug_decode("kæt"), &aug_decode("cat")), 3);
+    assert_eq!(constraints.penalty(&aug_decode("kæt"), &aug_decode("kæt"), &aug_decode("cat")), 0);
+
+    let rules = HLSubstitutionList::decode("[th]→0→ϑ").unwrap();
+    let guard = constraints.guard(&HLSubstitutionList { substitutions: vec![] });
+    assert_eq!(guard.rejects(&HLSubstitutionList { substitutions: vec![] }, &rules.substitutions[0]), Some(Violation::Rewritten {
+      spelling: aug_decode("the"),
+      output: aug_decode("ϑe")
+    }));
+    assert_eq!(guard.rejects(&HLSubstitutionList { substitutions: vec![] }, &HLSubstitution::decode("o[f]→0→v").unwrap()), None);
+
+    assert_eq!(Constraints::decode("hit").unwrap_err(), "line 1: can't parse \"hit\"");
+  }
+
+  #[test]
+  fn search_respects_constraints() {
+    let dictionary = dictionary(&[("the", "ϑʌ", 10.0), ("this", "ϑɪs", 1.0), ("that", "ϑæt", 1.0), ("thin", "ϑɪn", 1.0)]);
+    let system = GaussianSystem { scale: 4.0 };
+
+    let mut iter_system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+    iter_system.constraints = Arc::new(Constraints::decode("protect the\nhit thin").unwrap());
+    assert_eq!(iter_system.violations().len(), 1);
+
+    // Without the constraint the first rule would be [the]→0→ϑʌ.
+    while let Outcome::FoundImprovement(_, _) = iter_system.find_next_rule(&system, false) {}
+    assert!(iter_system.steps > 0);
+    let outputs: Vec<String> = dictionary.words.iter().map(|w| aug_encode(&iter_system.rules.apply_copied_always(&augment(&w.spelling)))).collect();
+    assert_eq!(outputs[0], "the");
+    assert_eq!(outputs[3], "ϑɪn");
+    assert_eq!(iter_system.violations(), vec![]);
+  }
+}
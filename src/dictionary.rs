@@ -181,3 +181,15 @@ pub fn load_old_dictionary() -> io::Result<Dictionary> {
   })
 }
 
+
+/// A small dictionary for tests, from (spelling, pronunciation, frequency).
+#[cfg(test)]
+pub fn from_words(words: &[(&str, &str, f64)]) -> Dictionary {
+  Dictionary {
+    words: words.iter().map(|(s, p, f)| DictionaryWord {
+      spelling: decode(s),
+      pronunciation: decode(p),
+      frequency: *f
+    }).collect()
+  }
+}
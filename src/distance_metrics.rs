@@ -0,0 +1,209 @@
+
+/* Ways of measuring how far a transformed spelling is from a pronunciation.
+ *
+ * Plain Levenshtein charges the same for getting æ instead of ɑ as for getting k
+ * instead of ɑ. WeightedDistance is an edit distance driven by a confusion-cost
+ * matrix instead, so near misses can be made cheaper than outright errors.
+ *
+ * Costs are whole numbers because everything downstream (improvements, the
+ * expectation tables) counts distance in whole steps. Plain Levenshtein is the
+ * special case where every cost is 1; a weighted matrix typically charges 2 for a
+ * full error so that it has room to charge 1 for a near miss.
+ *
+ * Cost files are plain text, one entry per line, with # starting a comment:
+ *
+ *   substitute 2   # any substitution not listed below
+ *   indel 2        # inserting or deleting any glyph
+ *   vowels 1       # one vowel for another, unless listed below
+ *   æ ɑ 1          # these two, either way round
+ */
+
+use crate::glyphs::{AugGlyph, Glyph, decode};
+use levenshtein_diff as levenshtein;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub trait DistanceMetric: Send + Sync {
+  fn distance(&self, p1: &[AugGlyph], p2: &[AugGlyph]) -> u32;
+
+  fn distanceg(&self, p1: &[Glyph], p2: &[Glyph]) -> u32 {
+    let p1: Vec<AugGlyph> = p1.iter().map(|g| AugGlyph::Real(*g)).collect();
+    let p2: Vec<AugGlyph> = p2.iter().map(|g| AugGlyph::Real(*g)).collect();
+    self.distance(&p1, &p2)
+  }
+
+  /// What getting one glyph outright wrong costs. The Gaussian estimators size
+  /// their sigmas in these units.
+  fn unit(&self) -> u32 {
+    1
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Levenshtein;
+
+impl DistanceMetric for Levenshtein {
+  fn distance(&self, p1: &[AugGlyph], p2: &[AugGlyph]) -> u32 {
+    levenshtein::distance(p1, p2).0 as u32
+  }
+
+  fn distanceg(&self, p1: &[Glyph], p2: &[Glyph]) -> u32 {
+    levenshtein::distance(p1, p2).0 as u32
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedDistance {
+  pub substitute: u32,
+  pub indel: u32,
+  /// Replaces `substitute` when both glyphs are vowels.
+  pub vowels: Option<u32>,
+  /// Symmetric, and takes precedence over everything else.
+  pub pairs: HashMap<(Glyph, Glyph), u32>
+}
+
+impl WeightedDistance {
+  /// Every cost is 1, so this agrees with Levenshtein.
+  pub fn uniform() -> WeightedDistance {
+    WeightedDistance {
+      substitute: 1,
+      indel: 1,
+      vowels: None,
+      pairs: HashMap::new()
+    }
+  }
+
+  pub fn substitution_cost(&self, a: &AugGlyph, b: &AugGlyph) -> u32 {
+    if a == b {
+      return 0;
+    }
+    match (a, b) {
+      (AugGlyph::Real(a), AugGlyph::Real(b)) => {
+        if let Some(&cost) = self.pairs.get(&(*a, *b)) {
+          cost
+        }
+        else if a.is_vowel() && b.is_vowel() {
+          self.vowels.unwrap_or(self.substitute)
+        }
+        else {
+          self.substitute
+        }
+      },
+      _ => self.substitute
+    }
+  }
+
+  pub fn decode(text: &str) -> Result<WeightedDistance, String> {
+    let mut res = WeightedDistance::uniform();
+
+    for (i, line) in text.lines().enumerate() {
+      let err = |msg: String| format!("line {}: {}", i + 1, msg);
+      let line = line.split('#').next().unwrap().trim();
+      if line.is_empty() {
+        continue;
+      }
+
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      let cost = |s: &str| s.parse::<u32>().map_err(|_| err(format!("bad cost {:?}", s)));
+      let glyph = |s: &str| match decode(s).as_slice() {
+        [g] => Ok(*g),
+        _ => Err(err(format!("{:?} is not a single glyph", s)))
+      };
+
+      match fields.as_slice() {
+        ["substitute", c] => res.substitute = cost(c)?,
+        ["indel", c] => res.indel = cost(c)?,
+        ["vowels", c] => res.vowels = Some(cost(c)?),
+        [a, b, c] => {
+          let (a, b, c) = (glyph(a)?, glyph(b)?, cost(c)?);
+          res.pairs.insert((a, b), c);
+          res.pairs.insert((b, a), c);
+        },
+        _ => return Err(err(format!("can't parse {:?}", line)))
+      }
+    }
+
+    Ok(res)
+  }
+
+  pub fn load(path: &str) -> Result<WeightedDistance, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    WeightedDistance::decode(&text).map_err(|e| format!("{}: {}", path, e))
+  }
+}
+
+impl DistanceMetric for WeightedDistance {
+  fn distance(&self, p1: &[AugGlyph], p2: &[AugGlyph]) -> u32 {
+    let mut prev: Vec<u32> = (0 ..= p2.len() as u32).map(|j| j * self.indel).collect();
+    let mut cur = vec![0; p2.len() + 1];
+
+    for (i, a) in p1.iter().enumerate() {
+      cur[0] = (i as u32 + 1) * self.indel;
+      for (j, b) in p2.iter().enumerate() {
+        cur[j+1] = (prev[j] + self.substitution_cost(a, b))
+          .min(prev[j+1] + self.indel)
+          .min(cur[j] + self.indel);
+      }
+      std::mem::swap(&mut prev, &mut cur);
+    }
+
+    prev[p2.len()]
+  }
+
+  fn unit(&self) -> u32 {
+    self.substitute.max(1)
+  }
+}
+
+/// Levenshtein if `path` is None, otherwise the cost file at `path`.
+pub fn load_metric(path: Option<&str>) -> Result<Arc<dyn DistanceMetric>, String> {
+  Ok(match path {
+    None => Arc::new(Levenshtein),
+    Some(path) => Arc::new(WeightedDistance::load(path)?)
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::glyphs::aug_decode;
+
+  #[test]
+  fn uniform_matches_levenshtein() {
+    let words = ["", "kæt", "cat", "ʧæt", "eight", "ϵt", "{0}t", "{0}{1}"];
+    for a in words {
+      for b in words {
+        let (a, b) = (aug_decode(a), aug_decode(b));
+        assert_eq!(WeightedDistance::uniform().distance(&a, &b), Levenshtein.distance(&a, &b));
+      }
+    }
+  }
+
+  #[test]
+  fn costs_from_file() {
+    let metric = WeightedDistance::decode("
+      # Full errors cost 2 so that near misses can cost 1.
+      substitute 2
+      indel 2
+      vowels 1
+      ʌ ə 0   # not worth distinguishing
+    ").unwrap();
+
+    let d = |a: &str, b: &str| metric.distance(&aug_decode(a), &aug_decode(b));
+    assert_eq!(d("kæt", "kɑt"), 1);
+    assert_eq!(d("kæt", "kkt"), 2);
+    assert_eq!(d("kæt", "kt"), 2);
+    assert_eq!(d("ʌbaut", "əbaut"), 0);
+    assert_eq!(d("əbaut", "ʌbaut"), 0);
+    assert_eq!(metric.unit(), 2);
+
+    assert_eq!(WeightedDistance::decode("substitute 2\nvowels x").unwrap_err(), "line 2: bad cost \"x\"");
+    assert_eq!(WeightedDistance::decode("ab c 1").unwrap_err(), "line 1: \"ab\" is not a single glyph");
+  }
+
+  #[test]
+  fn shipped_costs_load() {
+    let metric = WeightedDistance::load("res/costs/phonetic.costs").unwrap();
+    assert_eq!(metric.distance(&aug_decode("kæt"), &aug_decode("gæd")), 2);
+  }
+}
@@ -0,0 +1,360 @@
+
+/* An estimator for genastarlike built from measured changes
+ * instead of a Gaussian.
+ *
+ * ChangeHistograms records, for each current distance, how often a sampled
+ * edit changed a word at that distance by each amount. A word the search
+ * hasn't looked at yet is then a draw from its distance's histogram, scaled by
+ * its frequency; a word before the introducing word is a draw from the
+ * histogram's worsenings only, since an edit that improved it would have been
+ * introduced there.
+ *
+ * The bounds come in two layers. No word can get closer than distance 0, so
+ * no unknown word can improve by more than its distance, which gives a hard
+ * floor. Bernstein's inequality, using the histograms' means, variances and the
+ * largest deviation seen in them, gives bounds that hold with probability at
+ * least 1 - risk as long as the histograms are representative; the best
+ * possible is never put below the hard floor. The histograms only show what
+ * was sampled, so their extremes aren't used as limits, and nothing limits how
+ * much a word can get worse. Once every word is known both bounds are the
+ * exact change.
+ */
+
+use crate::calibration::Samples;
+use crate::high_level_substitutions2::HLSubstitution;
+use crate::genastarlike::{Table, EstimationSystem};
+use noisy_float::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Counts of each change in distance, by current distance. The last histogram
+/// also covers every larger distance.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeHistograms {
+  pub by_distance: Vec<BTreeMap<i32, usize>>
+}
+
+impl ChangeHistograms {
+  /// Every (edit, word) pair in `samples` but the introducing ones. A distance
+  /// with no pairs gets the histogram of the one before it.
+  pub fn from_samples(samples: &Samples, max_distance: u32) -> ChangeHistograms {
+    let buckets = max_distance as usize + 1;
+    let mut by_distance: Vec<BTreeMap<i32, usize>> = vec![BTreeMap::new(); buckets];
+    for e in &samples.edits {
+      for (i, &c) in e.changes.iter().enumerate() {
+        if i != e.introducing_i {
+          let b = (samples.current_distances_in_introducing_order[i] as usize).min(buckets - 1);
+          *by_distance[b].entry(c).or_insert(0) += 1;
+        }
+      }
+    }
+    for b in 0 .. buckets {
+      if by_distance[b].is_empty() {
+        by_distance[b] = if b == 0 { BTreeMap::from([(0, 1)]) } else { by_distance[b - 1].clone() };
+      }
+    }
+    ChangeHistograms { by_distance }
+  }
+
+  pub fn load(path: &str) -> Result<ChangeHistograms, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let res: ChangeHistograms = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    if res.by_distance.is_empty() || res.by_distance.iter().any(|h| h.values().sum::<usize>() == 0) {
+      return Err(format!("{}: every histogram needs at least one count", path));
+    }
+    Ok(res)
+  }
+
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    std::fs::write(path, serde_json::to_string_pretty(self).unwrap()).map_err(|e| format!("{}: {}", path, e))
+  }
+}
+
+/// What's known about one word's change before looking at it.
+#[derive(Debug, Clone, Copy, Default)]
+struct Moments {
+  mean: f64,
+  var: f64,
+  /// The least and most the histogram has seen.
+  floor: f64,
+  ceiling: f64,
+  /// The least the change can really be. Set by EmpiricalTable::build, which
+  /// knows the distance.
+  limit: f64
+}
+
+impl Moments {
+  fn of(histogram: &BTreeMap<i32, usize>, worsen_only: bool) -> Moments {
+    let change = |c: i32| if worsen_only { c.max(0) as f64 } else { c as f64 };
+    let total = histogram.values().sum::<usize>() as f64;
+    let mean = histogram.iter().map(|(&c, &k)| change(c) * k as f64).sum::<f64>() / total;
+    let var = histogram.iter().map(|(&c, &k)| (change(c) - mean).powi(2) * k as f64).sum::<f64>() / total;
+    Moments {
+      mean,
+      var,
+      floor: change(*histogram.keys().next().unwrap()),
+      ceiling: change(*histogram.keys().next_back().unwrap()),
+      limit: 0.0
+    }
+  }
+
+  fn scale(&self, f: f64) -> Moments {
+    Moments { mean: self.mean * f, var: self.var * f * f, floor: self.floor * f, ceiling: self.ceiling * f, limit: self.limit * f }
+  }
+
+  fn add(&self, other: &Moments) -> Moments {
+    Moments { mean: self.mean + other.mean, var: self.var + other.var, floor: self.floor + other.floor, ceiling: self.ceiling + other.ceiling, limit: self.limit + other.limit }
+  }
+
+  fn sub(&self, other: &Moments) -> Moments {
+    Moments { mean: self.mean - other.mean, var: self.var - other.var, floor: self.floor - other.floor, ceiling: self.ceiling - other.ceiling, limit: self.limit - other.limit }
+  }
+
+  fn down(&self) -> f64 {
+    self.mean - self.floor
+  }
+
+  fn up(&self) -> f64 {
+    self.ceiling - self.mean
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Estimator {
+  pub actual_weighted_change: f64,
+  unknown: Moments,
+  unknown_words: usize,
+  /// The furthest any one unknown word could fall below or rise above its
+  /// mean. Not reduced as words become known, which only loosens the bounds.
+  max_down: f64,
+  max_up: f64
+}
+
+pub struct EmpiricalTable {
+  /// Per word, in introducing order, scaled by frequency.
+  before_moments: Vec<Moments>,
+  after_moments: Vec<Moments>,
+  /// Sums and maxima of before_moments up to i, and of after_moments from i.
+  before_totals: Vec<Moments>,
+  after_totals: Vec<Moments>,
+  before_max: Vec<(f64, f64)>,
+  after_max: Vec<(f64, f64)>,
+  /// The most the word at i can improve by, scaled by frequency.
+  max_improvements: Vec<f64>
+}
+
+impl EmpiricalTable {
+  fn build(frequencies_in_introducing_order: &[f64], current_distances_in_introducing_order: &[u32], histograms: &ChangeHistograms) -> EmpiricalTable {
+    let n = frequencies_in_introducing_order.len();
+    let per_distance: Vec<(Moments, Moments)> = histograms.by_distance.iter().map(|h| (Moments::of(h, true), Moments::of(h, false))).collect();
+    let moments = |i: usize| {
+      let d = current_distances_in_introducing_order[i];
+      let (before, after) = per_distance[(d as usize).min(per_distance.len() - 1)];
+      // No word can get closer than distance 0. That holds before the
+      // introducing word too: the edit may just not have been proposed there.
+      let limit = -(d as f64);
+      let (before, after) = (Moments { limit, ..before }, Moments { floor: after.floor.max(limit), limit, ..after });
+      let f = frequencies_in_introducing_order[i];
+      (before.scale(f), after.scale(f))
+    };
+    let (before_moments, after_moments): (Vec<Moments>, Vec<Moments>) = (0 .. n).map(moments).unzip();
+
+    let mut before_totals = vec![Moments::default(); n];
+    let mut before_max = vec![(0.0, 0.0); n];
+    let (mut sum, mut max) = (Moments::default(), (0.0f64, 0.0f64));
+    for i in 0 .. n {
+      sum = sum.add(&before_moments[i]);
+      max = (max.0.max(before_moments[i].down()), max.1.max(before_moments[i].up()));
+      before_totals[i] = sum;
+      before_max[i] = max;
+    }
+
+    let mut after_totals = vec![Moments::default(); n];
+    let mut after_max = vec![(0.0, 0.0); n];
+    let (mut sum, mut max) = (Moments::default(), (0.0f64, 0.0f64));
+    for i in (0 .. n).rev() {
+      sum = sum.add(&after_moments[i]);
+      max = (max.0.max(after_moments[i].down()), max.1.max(after_moments[i].up()));
+      after_totals[i] = sum;
+      after_max[i] = max;
+    }
+
+    EmpiricalTable {
+      before_moments,
+      after_moments,
+      before_totals,
+      after_totals,
+      before_max,
+      after_max,
+      max_improvements: (0 .. n).map(|i| (current_distances_in_introducing_order[i] as f64) * frequencies_in_introducing_order[i]).collect()
+    }
+  }
+
+  /// Everything but the word at i unknown.
+  fn around(&self, i: usize, actual_weighted_change: f64) -> Estimator {
+    let n = self.after_totals.len();
+    let (before, before_max) = if i > 0 { (self.before_totals[i - 1], self.before_max[i - 1]) } else { (Moments::default(), (0.0, 0.0)) };
+    let (after, after_max) = if i + 1 < n { (self.after_totals[i + 1], self.after_max[i + 1]) } else { (Moments::default(), (0.0, 0.0)) };
+    Estimator {
+      actual_weighted_change,
+      unknown: before.add(&after),
+      unknown_words: n - 1,
+      max_down: before_max.0.max(after_max.0),
+      max_up: before_max.1.max(after_max.1)
+    }
+  }
+}
+
+impl Table<Estimator, Estimator, HLSubstitution> for EmpiricalTable {
+  fn introduce(
+    &self,
+    frequencies_in_introducing_order: &Vec<f64>,
+    introducing_i: usize,
+    change_at_introducing_i: i32,
+    _edit: &HLSubstitution
+  ) -> Estimator {
+    self.around(introducing_i, (change_at_introducing_i as f64) * frequencies_in_introducing_order[introducing_i])
+  }
+
+  // Introducing order is by frequency times distance, so no edit introduced at
+  // i or later can do better at its own word than fixing the word at i.
+  fn estimate_introduce(
+    &self,
+    _frequencies_in_introducing_order: &Vec<f64>,
+    introducing_i: usize
+  ) -> Estimator {
+    self.around(introducing_i, -self.max_improvements[introducing_i])
+  }
+
+  fn update_edit(
+    &self,
+    frequencies_in_introducing_order: &Vec<f64>,
+    _current_distances_in_introducing_order: &Vec<u32>,
+    introduced_i: usize,
+    updated_i: usize,
+    change_at_updated_i: i32,
+    prev_estimate: Estimator
+  ) -> Estimator {
+    if updated_i == introduced_i {
+      return prev_estimate;
+    }
+    let known = if updated_i < introduced_i { &self.before_moments[updated_i] } else { &self.after_moments[updated_i] };
+    Estimator {
+      actual_weighted_change: prev_estimate.actual_weighted_change + (change_at_updated_i as f64) * frequencies_in_introducing_order[updated_i],
+      unknown: prev_estimate.unknown.sub(known),
+      unknown_words: prev_estimate.unknown_words - 1,
+      ..prev_estimate
+    }
+  }
+}
+
+/// How far below or above its mean a sum can be with probability at most
+/// `risk`, given its variance and the largest deviation of any one term.
+pub fn bernstein_deviation(var: f64, max_deviation: f64, risk: f64) -> f64 {
+  let l = (1.0 / risk).ln();
+  let b = max_deviation * l / 3.0;
+  b + (b * b + 2.0 * var.max(0.0) * l).sqrt()
+}
+
+pub struct EmpiricalSystem {
+  pub histograms: Arc<ChangeHistograms>,
+  /// The chance each bound is allowed to be wrong.
+  pub risk: f64
+}
+
+impl EstimationSystem<EmpiricalTable, Estimator, Estimator, HLSubstitution> for EmpiricalSystem {
+  fn build_table(
+    &self,
+    frequencies_in_introducing_order: &Vec<f64>,
+    current_distances_in_introducing_order: &Vec<u32>
+  ) -> EmpiricalTable {
+    EmpiricalTable::build(frequencies_in_introducing_order, current_distances_in_introducing_order, &self.histograms)
+  }
+
+  fn calc_estimate(&self, estimator: &Estimator) -> Estimator {
+    estimator.clone()
+  }
+
+  fn calc_best_possible(&self, e: &Estimator) -> R64 {
+    if e.unknown_words == 0 {
+      return r64(e.actual_weighted_change);
+    }
+    let mean = e.actual_weighted_change + e.unknown.mean;
+    let likely = mean - bernstein_deviation(e.unknown.var, e.max_down, self.risk);
+    r64(likely.max(e.actual_weighted_change + e.unknown.limit))
+  }
+
+  fn calc_worst_possible(&self, e: &Estimator) -> R64 {
+    if e.unknown_words == 0 {
+      return r64(e.actual_weighted_change);
+    }
+    let mean = e.actual_weighted_change + e.unknown.mean;
+    r64(mean + bernstein_deviation(e.unknown.var, e.max_up, self.risk))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::astarlike2::{IterativeSystem, Outcome};
+  use crate::calibration::sample_edits;
+  use crate::distance_metrics::Levenshtein;
+  use crate::high_level_substitutions2::HLSubstitutionList;
+
+  #[test]
+  fn bounds_close_on_the_exact_change() {
+    let histograms = ChangeHistograms {
+      by_distance: vec![BTreeMap::from([(0, 8), (1, 2)]), BTreeMap::from([(-1, 1), (0, 6), (2, 1)])]
+    };
+    let system = EmpiricalSystem { histograms: Arc::new(histograms), risk: 0.01 };
+    let frequencies = vec![1.0, 0.5, 0.25, 0.125];
+    let distances = vec![3, 1, 0, 2];
+    let table = EstimationSystem::build_table(&system, &frequencies, &distances);
+    let changes = [-2, 1, 0, -1];
+
+    for introduced_i in 0 .. 4 {
+      let mut e = Table::introduce(&table, &frequencies, introduced_i, changes[introduced_i], &HLSubstitution::decode("[a]→0→æ").unwrap());
+      let mut prev = (system.calc_best_possible(&e).raw(), system.calc_worst_possible(&e).raw());
+      for i in (0 .. 4).rev() {
+        e = Table::update_edit(&table, &frequencies, &distances, introduced_i, i, changes[i], e);
+        prev = (system.calc_best_possible(&e).raw(), system.calc_worst_possible(&e).raw());
+        assert!(prev.0 <= prev.1 + 1e-9);
+      }
+      let exact = -2.0 + 0.5 + -0.125;
+      assert!((prev.0 - exact).abs() < 1e-9 && (prev.1 - exact).abs() < 1e-9);
+    }
+  }
+
+  #[test]
+  fn floor_is_the_real_limit() {
+    // Nothing sampled improved by more than 1, but a word at distance 3 could.
+    let histograms = ChangeHistograms { by_distance: vec![BTreeMap::from([(-1, 1), (0, 1)])] };
+    let system = EmpiricalSystem { histograms: Arc::new(histograms), risk: 1e-9 };
+    let frequencies = vec![1.0; 4];
+    let distances = vec![3; 4];
+    let table = EstimationSystem::build_table(&system, &frequencies, &distances);
+
+    let e = Table::introduce(&table, &frequencies, 0, -3, &HLSubstitution::decode("[a]→0→æ").unwrap());
+    assert!((system.calc_best_possible(&e).raw() - (-3.0 - 3.0 * 3.0)).abs() < 1e-9);
+    // Nor does getting worse stop at what was sampled.
+    assert!(system.calc_worst_possible(&e).raw() > -3.0);
+  }
+
+  #[test]
+  fn finds_the_same_first_rules() {
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(100);
+
+    let mut iter_system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+    let samples = sample_edits(&iter_system.dictionary, &iter_system.rules, &Levenshtein, 50, 300, 0);
+    let system = EmpiricalSystem { histograms: Arc::new(ChangeHistograms::from_samples(&samples, 4)), risk: 1e-4 };
+    let mut gaussian = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+
+    for _ in 0 .. 3 {
+      let Outcome::FoundImprovement(winner, _) = iter_system.find_next_rule(&system, false) else { panic!("Failed to find improvement") };
+      let Outcome::FoundImprovement(expected, _) = gaussian.find_next_rule(&crate::gaussian_astarlike22::GaussianSystem { scale: 4.0 }, false) else { panic!("Failed to find improvement") };
+      assert_eq!(winner.encode(), expected.encode());
+    }
+  }
+}
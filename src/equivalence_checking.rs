@@ -0,0 +1,153 @@
+
+/* Checks that a HLSubstitutionList does the same thing as what it lowers to.
+ *
+ * HLSubstitutionList::apply is what the search optimizes against, but what ships
+ * is low_level() run through the shaper, so any word where
+ * substitutions2::apply_all(low_level()) disagrees with apply is a lowering bug.
+ * For every such word this also finds the shortest prefix of the rule list that
+ * already disagrees on it, since the last rule of that prefix is usually the one
+ * to look at.
+ */
+
+use crate::glyphs::{AugGlyph, Glyph, augment};
+use crate::high_level_substitutions2::HLSubstitutionList;
+use crate::substitutions2 as s2;
+use crate::dictionary::Dictionary;
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+  pub word: Vec<AugGlyph>,
+  pub directly: Vec<AugGlyph>,
+  pub by_low_level: Vec<AugGlyph>,
+  // The first this-many rules already disagree on the word, but one fewer don't.
+  pub prefix_len: usize
+}
+
+pub fn dictionary_words(dictionary: &Dictionary) -> Vec<Vec<AugGlyph>> {
+  dictionary.words.iter().map(|w| augment(&w.spelling)).collect()
+}
+
+/// The real glyphs the rules' anteriors mention.
+pub fn rule_alphabet(rules: &HLSubstitutionList) -> BTreeSet<Glyph> {
+  rules.substitutions.iter().flat_map(|s| s.anterior.glyphs()).filter_map(|g| match g {
+    AugGlyph::Real(g) => Some(g),
+    AugGlyph::Synthetic(_) => None
+  }).collect()
+}
+
+/// Every word up to `max_len` long over the real glyphs the rules mention, plus `-`
+/// so that word boundaries inside a string get exercised too.
+pub fn exhaustive_words(rules: &HLSubstitutionList, max_len: usize) -> Vec<Vec<AugGlyph>> {
+  let mut alphabet = rule_alphabet(rules);
+  alphabet.extend(Glyph::from_char("-").map(|(g, _)| g));
+  let alphabet: Vec<AugGlyph> = alphabet.into_iter().map(AugGlyph::Real).collect();
+
+  let mut res: Vec<Vec<AugGlyph>> = vec![];
+  let mut working: Vec<Vec<AugGlyph>> = vec![vec![]];
+  for _ in 0 .. max_len {
+    working = working.iter().flat_map(|w| {
+      alphabet.iter().map(|g| {
+        let mut w = w.clone();
+        w.push(*g);
+        w
+      })
+    }).collect();
+    res.extend(working.iter().cloned());
+  }
+  res
+}
+
+fn apply_both(rules: &HLSubstitutionList, low_level: &s2::SubstitutionList, word: &[AugGlyph]) -> (Vec<AugGlyph>, Vec<AugGlyph>) {
+  let mut directly = word.to_vec();
+  rules.apply(&mut directly);
+  let mut by_low_level = word.to_vec();
+  s2::apply_all(&mut by_low_level, low_level);
+  (directly, by_low_level)
+}
+
+fn prefix(rules: &HLSubstitutionList, len: usize) -> HLSubstitutionList {
+  HLSubstitutionList {
+    substitutions: rules.substitutions[.. len].to_vec()
+  }
+}
+
+/// Returns every word in `words` where the rules and their lowering disagree.
+pub fn check_equivalence(rules: &HLSubstitutionList, words: &[Vec<AugGlyph>]) -> Vec<Divergence> {
+  check_equivalence_with(rules, words, HLSubstitutionList::low_level)
+}
+
+/// Like `check_equivalence`, but with some other way of lowering the rules.
+pub fn check_equivalence_with<F: Fn(&HLSubstitutionList) -> s2::SubstitutionList>(rules: &HLSubstitutionList, words: &[Vec<AugGlyph>], lower: F) -> Vec<Divergence> {
+  let low_level = lower(rules);
+
+  let diverging: Vec<(Vec<AugGlyph>, Vec<AugGlyph>, Vec<AugGlyph>)> = words.par_iter().filter_map(|word| {
+    let (directly, by_low_level) = apply_both(rules, &low_level, word);
+    if directly != by_low_level { Some((word.clone(), directly, by_low_level)) } else { None }
+  }).collect();
+
+  if diverging.is_empty() {
+    return vec![];
+  }
+
+  // Prefixes are compiled only when first needed, and shared between words.
+  let mut prefixes: Vec<Option<(HLSubstitutionList, s2::SubstitutionList)>> = vec![None; rules.substitutions.len() + 1];
+
+  diverging.into_iter().map(|(word, directly, by_low_level)| {
+    let prefix_len = (1 ..= rules.substitutions.len()).find(|&k| {
+      let (prefix_rules, prefix_low_level) = prefixes[k].get_or_insert_with(|| {
+        let prefix_rules = prefix(rules, k);
+        let prefix_low_level = lower(&prefix_rules);
+        (prefix_rules, prefix_low_level)
+      });
+      let (a, b) = apply_both(prefix_rules, prefix_low_level, &word);
+      a != b
+    }).unwrap_or(rules.substitutions.len());
+
+    Divergence { word, directly, by_low_level, prefix_len }
+  }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::glyphs::aug_decode;
+
+  #[test]
+  fn set_1_prefix_agrees_on_short_words() {
+    let rules = prefix(&HLSubstitutionList::set_1(), 40);
+    let words = exhaustive_words(&HLSubstitutionList::decode("[ab]→0→x").unwrap(), 4);
+    assert_eq!(words.len(), 3 + 9 + 27 + 81);
+    assert!(check_equivalence(&rules, &words).is_empty());
+  }
+
+  #[test]
+  fn finds_divergence_and_prefix() {
+    let rules = HLSubstitutionList::decode("
+      [a]→0→x
+      ^[b]→1→y
+      [c]→2→z
+    ").unwrap();
+    let words: Vec<Vec<AugGlyph>> = ["cb", "ba", "a"].iter().map(|w| aug_decode(w)).collect();
+
+    assert!(check_equivalence(&rules, &words).is_empty());
+
+    // A lowering that forgets about ignores, so ^ and $ stop working.
+    let without_ignores = |rules: &HLSubstitutionList| {
+      let mut low_level = rules.low_level();
+      for lookup in low_level.lookups.iter_mut() {
+        lookup.substitutions.retain(|s| s.sub_content != s2::SubContent::Ignore);
+      }
+      low_level
+    };
+
+    let divergences = check_equivalence_with(&rules, &words, without_ignores);
+    assert_eq!(divergences, vec![Divergence {
+      word: aug_decode("cb"),
+      directly: aug_decode("zb"),
+      by_low_level: aug_decode("zy"),
+      prefix_len: 2
+    }]);
+  }
+}
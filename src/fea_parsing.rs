@@ -87,6 +87,20 @@ mod fea_parser_tests {
     );
   }
   
+  #[test]
+  fn fea_parser_test_class_1() {
+    use Glyph::*;
+    let text = "lookup l0 {\n  sub c' [e i y] by s;\n} l0;\n";
+    let parsed = parse_fea_feature_body(text).unwrap();
+    assert_eq!(parsed.lookups[0].substitutions[0], s2::Substitution {
+      pre_key: vec![],
+      at_key: rr(&[C]),
+      post_key: vec![KeyElem::Class(rr(&[E, I, Y]))],
+      sub_content: SubContent::Sub(rr(&[S]))
+    });
+    assert_eq!(render_fea_feature_body(&parsed), text);
+  }
+  
   #[test]
   fn fea_parser_test_4() {
     use crate::glyphs::Glyph::*;
@@ -115,6 +129,7 @@ mod fea_parser_tests {
 fn key_elem_name(k: &KeyElem) -> String {
   match k {
     KeyElem::Glyph(g) => g.name(),
+    KeyElem::Class(gs) => format!("[{}]", gs.iter().map(|g| g.name()).collect::<Vec<_>>().join(" ")),
     KeyElem::AnyLetter => "@lc".to_owned()
   }
 }
@@ -163,6 +178,12 @@ fn parse_key_elem(name: &str) -> Result<KeyElem, String> {
   if name == "@lc" {
     Ok(KeyElem::AnyLetter)
   }
+  else if let Some(inner) = name.strip_prefix("[").and_then(|n| n.strip_suffix("]")) {
+    let glyphs: Result<Vec<AugGlyph>, String> = inner.split_whitespace().map(|n| {
+      AugGlyph::from_name(n).ok_or(format!("Can't parse glyph name {}", n))
+    }).collect();
+    Ok(KeyElem::Class(glyphs?))
+  }
   else {
     Ok(KeyElem::Glyph(AugGlyph::from_name(name).ok_or(format!("Can't parse glyph name {}", name))?))
   }
@@ -309,7 +330,7 @@ pub fn parse_fea_feature_body_1(text: &str) -> Result<mid::Feature, String> {
                                 let mut prime = false;
                                 for n in g.into_inner() {
                                   match n.as_rule() {
-                                    Rule::name => {
+                                    Rule::name | Rule::class => {
                                       name = Some(n.as_str().to_owned());
                                     },
                                     Rule::prime => {
@@ -378,7 +399,7 @@ pub fn parse_fea_feature_body_1(text: &str) -> Result<mid::Feature, String> {
                         let mut prime = false;
                         for n in child.into_inner() {
                           match n.as_rule() {
-                            Rule::name => {
+                            Rule::name | Rule::class => {
                               name = Some(n.as_str().to_owned());
                             },
                             Rule::prime => {
@@ -1,15 +1,25 @@
 
-use crate::astarlike2::{Table, System};
+use crate::genastarlike::{Table, EstimationSystem};
 use crate::gaussian_expectation_table2::*;
 use crate::gaussian_expectation_table2 as get2;
 use crate::high_level_substitutions2::HLSubstitution;
+use crate::objective::Objective;
 use noisy_float::prelude::*;
+use std::sync::Arc;
 
 pub struct GaussianTable {
   table: ExpectationTable
 }
 
-impl Table<Estimator, Estimator> for GaussianTable {
+impl GaussianTable {
+  fn build(frequencies_in_introducing_order: &Vec<f64>, current_distances_in_introducing_order: &Vec<u32>, calibration: &Calibration) -> GaussianTable {
+    GaussianTable {
+      table: build_calibrated_expectation_table(frequencies_in_introducing_order, current_distances_in_introducing_order, calibration)
+    }
+  }
+}
+
+impl Table<Estimator, Estimator, HLSubstitution> for GaussianTable {
   fn introduce(
     &self,
     frequencies_in_introducing_order: &Vec<f64>,
@@ -31,33 +41,70 @@ impl Table<Estimator, Estimator> for GaussianTable {
   fn update_edit(
     &self,
     frequencies_in_introducing_order: &Vec<f64>,
-    current_distances_in_introducing_order: &Vec<u32>,
+    _current_distances_in_introducing_order: &Vec<u32>,
     introduced_i: usize,
     updated_i: usize,
     change_at_updated_i: i32,
     prev_estimate: Estimator
   ) -> Estimator {
-    update_edit(frequencies_in_introducing_order, current_distances_in_introducing_order, introduced_i, updated_i, change_at_updated_i, &prev_estimate)
+    update_calibrated_edit(frequencies_in_introducing_order, &self.table, introduced_i, updated_i, change_at_updated_i, &prev_estimate)
   }
 }
 
+/// Every sigma is 1, which suits Levenshtein distance. Weighted objectives want
+/// CalibratedGaussianSystem::for_objective.
 pub struct GaussianSystem {
   pub scale: f64
 }
 
-impl System<GaussianTable, Estimator, Estimator> for GaussianSystem {
+impl EstimationSystem<GaussianTable, Estimator, Estimator, HLSubstitution> for GaussianSystem {
   fn build_table(
     &self,
     frequencies_in_introducing_order: &Vec<f64>,
     current_distances_in_introducing_order: &Vec<u32>
   ) -> GaussianTable {
-    GaussianTable {
-      table: build_expectation_table(
-        frequencies_in_introducing_order,
-        current_distances_in_introducing_order
-      )
+    GaussianTable::build(frequencies_in_introducing_order, current_distances_in_introducing_order, &Calibration::default())
+  }
+  
+  fn calc_estimate(&self, estimator: &Estimator) -> Estimator {
+    estimator.clone()
+  }
+  
+  fn calc_best_possible(&self, g: &Estimator) -> R64 {
+    r64(get2::calc_best_possible(g, self.scale))
+  }
+
+  fn calc_worst_possible(&self, g: &Estimator) -> R64 {
+    r64(get2::calc_worst_possible(g, self.scale))
+  }
+}
+
+/// A GaussianSystem whose per-word sigmas come from a calibration run instead
+/// of all being 1.
+pub struct CalibratedGaussianSystem {
+  pub scale: f64,
+  pub calibration: Arc<Calibration>
+}
+
+impl CalibratedGaussianSystem {
+  /// A GaussianSystem with its sigmas in the objective's units, e.g. 2 per
+  /// wrong glyph with a cost file that charges 2 for a substitution.
+  pub fn for_objective(scale: f64, objective: &dyn Objective) -> CalibratedGaussianSystem {
+    CalibratedGaussianSystem {
+      scale,
+      calibration: Arc::new(Calibration::default().scaled(objective.word_unit()))
     }
   }
+}
+
+impl EstimationSystem<GaussianTable, Estimator, Estimator, HLSubstitution> for CalibratedGaussianSystem {
+  fn build_table(
+    &self,
+    frequencies_in_introducing_order: &Vec<f64>,
+    current_distances_in_introducing_order: &Vec<u32>
+  ) -> GaussianTable {
+    GaussianTable::build(frequencies_in_introducing_order, current_distances_in_introducing_order, &self.calibration)
+  }
   
   fn calc_estimate(&self, estimator: &Estimator) -> Estimator {
     estimator.clone()
@@ -107,8 +154,6 @@ mod tests {
   
   #[test]
   fn goes_to_zero_test_3() {
-    use crate::astarlike2::{System, Table};
-    
     let ExampleTable1 { num_words, frequencies_in_introducing_order, introducing_order_rev, current_distances_in_introducing_order, .. } = example_table_1();
     
     let system = GaussianSystem {
@@ -135,53 +180,24 @@ mod tests {
   
   #[test]
   fn gaussian_astarlike_test_1() {
-    use crate::glyphs::AugGlyph::{self, *};
     use crate::astarlike2::*;
     
     let mut dictionary = crate::dictionary::load_dictionary().unwrap();
     dictionary.words.truncate(5);
-    let dictionary = AugDictionary {
-      words: dictionary.words.iter().map(|w| {
-        let base_pronunciation: Vec<AugGlyph> = w.pronunciation.iter().map(|g| Real(*g)).collect();
-        AugDictionaryWord {
-          transformed_spelling: w.spelling.iter().map(|g| Real(*g)).collect(),
-          back_transformed_pronunciation: base_pronunciation.clone(),
-          base_pronunciation,
-          frequency: w.frequency
-        }
-      }).collect()
-    };
     
     let system = GaussianSystem {
       scale: 1.0
     };
     
-    let rules = crate::high_level_substitutions2::HLSubstitutionList {
-      substitutions: vec![]
-    };
-    let r = init_ref_data(&system, &dictionary, &rules, 0);
-    
-    let mut w = init_working_data();
-    
-    let mut the_winner: Option<HLSubstitution> = None;
+    let mut iter_system = IterativeSystem::setup(&dictionary, crate::high_level_substitutions2::HLSubstitutionList { substitutions: vec![] });
     
-    for _ in 0 .. 1000 {
-      if let Some(outcome) = step(&system, &r, &mut w, true) {
-        if let Outcome::FoundImprovement(winner, _) = outcome {
-          the_winner = Some(winner);
-          break;
-        }
-        else {
-          panic!("Failed to find improvement");
-        }
-      }
-      println!("");
-    }
+    let the_winner = match iter_system.find_next_rule(&system, true) {
+      Outcome::FoundImprovement(winner, _) => winner,
+      _ => panic!("Failed to find improvement")
+    };
     
     println!("");
     println!("Winner: {:?}", the_winner);
-     
-    let the_winner = the_winner.unwrap();
     
     let should = "[the]→0→ϑʌ";
     
@@ -190,53 +206,24 @@ mod tests {
   
   #[test]
   fn gaussian_astarlike_test_2() {
-    use crate::glyphs::AugGlyph::{self, *};
     use crate::astarlike2::*;
     
     let mut dictionary = crate::dictionary::load_dictionary().unwrap();
     dictionary.words.truncate(20);
-    let dictionary = AugDictionary {
-      words: dictionary.words.iter().map(|w| {
-        let base_pronunciation: Vec<AugGlyph> = w.pronunciation.iter().map(|g| Real(*g)).collect();
-        AugDictionaryWord {
-          transformed_spelling: w.spelling.iter().map(|g| Real(*g)).collect(),
-          back_transformed_pronunciation: base_pronunciation.clone(),
-          base_pronunciation,
-          frequency: w.frequency
-        }
-      }).collect()
-    };
     
     let system = GaussianSystem {
       scale: 1.0
     };
     
-    let rules = crate::high_level_substitutions2::HLSubstitutionList {
-      substitutions: vec![]
-    };
-    let r = init_ref_data(&system, &dictionary, &rules, 0);
-    
-    let mut w = init_working_data();
-    
-    let mut the_winner: Option<HLSubstitution> = None;
+    let mut iter_system = IterativeSystem::setup(&dictionary, crate::high_level_substitutions2::HLSubstitutionList { substitutions: vec![] });
     
-    for _ in 0 .. 1000 {
-      if let Some(outcome) = step(&system, &r, &mut w, true) {
-        if let Outcome::FoundImprovement(winner, _) = outcome {
-          the_winner = Some(winner);
-          break;
-        }
-        else {
-          panic!("Failed to find improvement");
-        }
-      }
-      println!("");
-    }
+    let the_winner = match iter_system.find_next_rule(&system, true) {
+      Outcome::FoundImprovement(winner, _) => winner,
+      _ => panic!("Failed to find improvement")
+    };
     
     println!("");
     println!("Winner: {:?}", the_winner);
-     
-    let the_winner = the_winner.unwrap();
     
     let should = "[the]→0→ϑʌ";
     
@@ -278,5 +265,110 @@ mod tests {
      
     assert_eq!(winner2.encode(), "[of]→1→ʌv");
   }
+  
+  #[test]
+  fn checkpoint_resume_test_1() {
+    use crate::astarlike2::*;
+    
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(20);
+    
+    let system = GaussianSystem {
+      scale: 1.0
+    };
+    
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("checkpoint.json").to_str().unwrap().to_owned();
+    
+    let mut uninterrupted = IterativeSystem::setup(&dictionary, crate::high_level_substitutions2::HLSubstitutionList { substitutions: vec![] });
+    uninterrupted.checkpoint_path = Some(path.clone());
+    for _ in 0 .. 2 {
+      assert!(matches!(uninterrupted.find_next_rule(&system, false), Outcome::FoundImprovement(_, _)));
+    }
+    
+    let mut resumed = IterativeSystem::load_checkpoint(&path, &|_| Ok(())).unwrap();
+    assert_eq!(resumed.steps, 2);
+    
+    // Resuming with a different objective would quietly carry on a different search.
+    let heavier = crate::objective::WeightedObjective {
+      weights: crate::objective::ObjectiveWeights { complexity: 0.01, ..Default::default() },
+      ..Default::default()
+    };
+    assert!(IterativeSystem::load_checkpoint(&path, &|s| {
+      s.objective = Arc::new(heavier.clone());
+      Ok(())
+    }).is_err());
+    assert_eq!(resumed.checkpoint_path, Some(path.clone()));
+    
+    for _ in 0 .. 2 {
+      uninterrupted.find_next_rule(&system, false);
+      resumed.find_next_rule(&system, false);
+    }
+    
+    assert_eq!(resumed.steps, uninterrupted.steps);
+    assert_eq!(
+      resumed.rules.substitutions.iter().map(|s| s.encode()).collect::<Vec<_>>(),
+      uninterrupted.rules.substitutions.iter().map(|s| s.encode()).collect::<Vec<_>>()
+    );
+    for (a, b) in resumed.dictionary.words.iter().zip(uninterrupted.dictionary.words.iter()) {
+      assert_eq!(a.transformed_spelling, b.transformed_spelling);
+      assert_eq!(a.back_transformed_pronunciation, b.back_transformed_pronunciation);
+      assert_eq!(a.frequency.to_bits(), b.frequency.to_bits());
+    }
+  }
+  
+  #[test]
+  fn matches_astarlike2_winners_test_1() {
+    use crate::astarlike2::*;
+    
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(20);
+    
+    let system = GaussianSystem {
+      scale: 1.0
+    };
+    
+    let mut iter_system = IterativeSystem::setup(&dictionary, crate::high_level_substitutions2::HLSubstitutionList { substitutions: vec![] });
+    
+    // What astarlike2's own step found before it was replaced by genastarlike's.
+    for expected in ["[the]→0→ϑʌ", "[of]→1→ʌv", "[th]→2→ϑ", "[i]→3→ɪ"] {
+      let Outcome::FoundImprovement(winner, _) = iter_system.find_next_rule(&system, false) else { panic!("Failed to find improvement") };
+      assert_eq!(winner.encode(), expected);
+    }
+    
+    // The original spellings can still be recovered from the transformed ones.
+    for (w, original) in iter_system.dictionary.words.iter().zip(dictionary.words.iter()) {
+      assert_eq!(crate::glyphs::strip_aug(&iter_system.rules.deapply_anterior_copied(&w.transformed_spelling)), original.spelling);
+    }
+  }
+  
+  #[test]
+  fn verification_test_1() {
+    use crate::astarlike2::*;
+    use crate::objective::{evaluate, WeightedObjective};
+    
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(100);
+    
+    let system = GaussianSystem {
+      scale: 4.0
+    };
+    let objective = WeightedObjective::default();
+    
+    let mut iter_system = IterativeSystem::setup(&dictionary, crate::high_level_substitutions2::HLSubstitutionList { substitutions: vec![] });
+    iter_system.verify = Some(10);
+    
+    for _ in 0 .. 3 {
+      let before = evaluate(&objective, &iter_system.rules, &dictionary).score;
+      let Outcome::FoundImprovement(winner, _) = iter_system.find_next_rule(&system, false) else { panic!("Failed to find improvement") };
+      let after = evaluate(&objective, &iter_system.rules, &dictionary).score;
+      
+      let verification = iter_system.last_verification.clone().unwrap();
+      assert_eq!(verification.winner, winner);
+      assert!(verification.checked > 1 && verification.checked <= 11);
+      assert!(verification.regret() >= 0.0);
+      // The exact score is exactly what adding the rule did.
+      assert!((verification.winner_score - (after - before)).abs() < 1e-6);
+    }
+  }
 }
-
@@ -31,8 +31,68 @@
  * 
  * Then, once you know the gaussian, you can de-mix it from the working gaussian.
  * 
+ * How far a word can be expected to move depends on how far off it is already:
+ * a word that's already right can't improve. A Calibration gives the sigma of
+ * the worsening and of the improvement by current distance; the default is 1
+ * for both, except that words at distance 0 can't improve.
+ * 
  */
 
+use serde::{Serialize, Deserialize};
+
+/// Sigmas of a word's change by its current distance, in multiples of its
+/// frequency. The last entry of each applies to every larger distance too.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+  pub worsen_sigma: Vec<f64>,
+  pub improve_sigma: Vec<f64>
+}
+
+impl Default for Calibration {
+  fn default() -> Calibration {
+    Calibration {
+      worsen_sigma: vec![1.0],
+      improve_sigma: vec![0.0, 1.0]
+    }
+  }
+}
+
+impl Calibration {
+  fn at(sigmas: &[f64], distance: u32) -> f64 {
+    sigmas[(distance as usize).min(sigmas.len() - 1)]
+  }
+
+  pub fn worsen(&self, distance: u32) -> f64 {
+    Calibration::at(&self.worsen_sigma, distance)
+  }
+
+  pub fn improve(&self, distance: u32) -> f64 {
+    Calibration::at(&self.improve_sigma, distance)
+  }
+
+  /// The same calibration for word costs that count `unit` per wrong glyph
+  /// rather than 1. The bounds' scale is in sigmas, so it needn't change.
+  pub fn scaled(&self, unit: f64) -> Calibration {
+    Calibration {
+      worsen_sigma: self.worsen_sigma.iter().map(|s| s * unit).collect(),
+      improve_sigma: self.improve_sigma.iter().map(|s| s * unit).collect()
+    }
+  }
+
+  pub fn load(path: &str) -> Result<Calibration, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let res: Calibration = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    if res.worsen_sigma.is_empty() || res.improve_sigma.is_empty() {
+      return Err(format!("{}: both sigma lists need at least one entry", path));
+    }
+    Ok(res)
+  }
+
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    std::fs::write(path, serde_json::to_string_pretty(self).unwrap()).map_err(|e| format!("{}: {}", path, e))
+  }
+}
+
 #[derive(Debug, Clone)]
 pub struct Estimator {
   pub actual_weighted_change: f64,
@@ -41,39 +101,57 @@ pub struct Estimator {
   pub after_nonzero_ss_freq: f64,
 }
 
+// The sums of squares are of frequency times sigma, so with the default
+// calibration they're just of frequency, and "nonzero" means "can improve".
 pub struct ExpectationTable {
   ss_frequency_in_introducing_order: Vec<f64>,
   remaining_ss_frequency_in_introducing_order: Vec<f64>,
   remaining_nonzero_ss_frequency_in_introducing_order: Vec<f64>,
+  worsen_ss_in_introducing_order: Vec<f64>,
+  improve_ss_in_introducing_order: Vec<f64>,
 }
 
 pub fn build_expectation_table(
     frequencies_in_introducing_order: &Vec<f64>,
     current_distances_in_introducing_order: &Vec<u32>,
   ) -> ExpectationTable
+{
+  build_calibrated_expectation_table(frequencies_in_introducing_order, current_distances_in_introducing_order, &Calibration::default())
+}
+
+pub fn build_calibrated_expectation_table(
+    frequencies_in_introducing_order: &[f64],
+    current_distances_in_introducing_order: &[u32],
+    calibration: &Calibration
+  ) -> ExpectationTable
 {
   let n = frequencies_in_introducing_order.len();
   
+  let ss = |i: usize, sigma: f64| {
+    let f = frequencies_in_introducing_order[i];
+    f * f * (sigma * sigma)
+  };
+  let worsen_ss_in_introducing_order: Vec<f64> = (0 .. n).map(|i| ss(i, calibration.worsen(current_distances_in_introducing_order[i]))).collect();
+  let improve_ss_in_introducing_order: Vec<f64> = (0 .. n).map(|i| ss(i, calibration.improve(current_distances_in_introducing_order[i]))).collect();
+  
   let mut ss_frequency_in_introducing_order: Vec<f64> = vec![0.0; n];
   let mut sum = 0.0;
   for i in 0 .. n {
-    sum += frequencies_in_introducing_order[i] * frequencies_in_introducing_order[i];
+    sum += worsen_ss_in_introducing_order[i];
     ss_frequency_in_introducing_order[i] = sum;
   }
   
   let mut remaining_ss_frequency_in_introducing_order: Vec<f64> = vec![0.0; n];
   let mut sum = 0.0;
   for i in (0 .. n).rev() {
-    sum += frequencies_in_introducing_order[i] * frequencies_in_introducing_order[i];
+    sum += worsen_ss_in_introducing_order[i];
     remaining_ss_frequency_in_introducing_order[i] = sum;
   }
   
   let mut remaining_nonzero_ss_frequency_in_introducing_order: Vec<f64> = vec![0.0; n];
   let mut sum = 0.0;
   for i in (0 .. n).rev() {
-    if current_distances_in_introducing_order[i] > 0 {
-      sum += frequencies_in_introducing_order[i] * frequencies_in_introducing_order[i];
-    }
+    sum += improve_ss_in_introducing_order[i];
     remaining_nonzero_ss_frequency_in_introducing_order[i] = sum;
   }
   
@@ -81,6 +159,8 @@ pub fn build_expectation_table(
     ss_frequency_in_introducing_order,
     remaining_ss_frequency_in_introducing_order,
     remaining_nonzero_ss_frequency_in_introducing_order,
+    worsen_ss_in_introducing_order,
+    improve_ss_in_introducing_order,
   }
 }
 
@@ -185,6 +265,34 @@ pub fn update_edit(
   }
 }
 
+/// Like update_edit, but takes out what `et` put in, so it works for any calibration.
+pub fn update_calibrated_edit(
+    frequencies_in_introducing_order: &[f64],
+    et: &ExpectationTable,
+    introduced_i: usize,
+    updated_i: usize,
+    change_at_updated_i: i32,
+    working_expectation: &Estimator
+  ) -> Estimator
+{
+  let e = working_expectation;
+  if updated_i == introduced_i {
+    return e.clone();
+  }
+  
+  let shift = (change_at_updated_i as f64) * frequencies_in_introducing_order[updated_i];
+  let before = updated_i < introduced_i;
+  let worsen = et.worsen_ss_in_introducing_order[updated_i];
+  let improve = et.improve_ss_in_introducing_order[updated_i];
+  
+  Estimator {
+    actual_weighted_change: e.actual_weighted_change + shift,
+    before_ss_freq: if before { e.before_ss_freq - worsen } else { e.before_ss_freq },
+    after_ss_freq: if !before { e.after_ss_freq - worsen } else { e.after_ss_freq },
+    after_nonzero_ss_freq: if !before { e.after_nonzero_ss_freq - improve } else { e.after_nonzero_ss_freq },
+  }
+}
+
 pub fn calc_best_possible(e: &Estimator, scale: f64) -> f64 {
   e.actual_weighted_change - scale * e.after_nonzero_ss_freq.max(0.0).sqrt()
 }
@@ -298,6 +406,34 @@ mod tests {
       assert!(ex.after_nonzero_ss_freq.abs() < 0.01);
     }
   }
+  
+  #[test]
+  fn calibrated_goes_to_zero_test_1() {
+    let ExampleTable1 { num_words, table, frequencies_in_introducing_order, current_distances_in_introducing_order, introducing_order_rev } = example_table_1();
+    let calibration = Calibration {
+      worsen_sigma: vec![0.5, 2.0],
+      improve_sigma: vec![0.0, 0.25, 1.5]
+    };
+    let calibrated = build_calibrated_expectation_table(&frequencies_in_introducing_order, &current_distances_in_introducing_order, &calibration);
+    
+    for introduced_i in 0 .. num_words {
+      let mut default = introduce_edit(&frequencies_in_introducing_order, &table, introduced_i, -1);
+      let mut ex = introduce_edit(&frequencies_in_introducing_order, &calibrated, introduced_i, -1);
+      
+      for j in 0 .. num_words {
+        let i = introducing_order_rev[j];
+        if i != introduced_i {
+          let expected = update_edit(&frequencies_in_introducing_order, &current_distances_in_introducing_order, introduced_i, i, 1, &default);
+          default = update_calibrated_edit(&frequencies_in_introducing_order, &table, introduced_i, i, 1, &default);
+          assert_eq!((default.before_ss_freq, default.after_ss_freq, default.after_nonzero_ss_freq), (expected.before_ss_freq, expected.after_ss_freq, expected.after_nonzero_ss_freq));
+          ex = update_calibrated_edit(&frequencies_in_introducing_order, &calibrated, introduced_i, i, 1, &ex);
+        }
+      }
+      
+      assert!(ex.after_ss_freq.abs() < 0.01);
+      assert!(ex.before_ss_freq.abs() < 0.01);
+      assert!(ex.after_nonzero_ss_freq.abs() < 0.01);
+      assert_eq!(ex.actual_weighted_change, default.actual_weighted_change);
+    }
+  }
 }
-
-
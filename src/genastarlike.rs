@@ -8,6 +8,8 @@ use noisy_float::prelude::*;
 use crate::dictionary::Dictionary;
 use std::hash::Hash;
 use core::fmt::Debug;
+use crate::search_log::{SearchLog, SearchEvent};
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct SubWithImprovement<Edit: Eq + PartialEq> {
@@ -21,6 +23,16 @@ pub trait EditSystem<Edit: Eq + PartialEq, EditList> {
   
   fn distance(&self, rules: &EditList, spelling: &Vec<Glyph>, pronunciation: &Vec<Glyph>) -> u32;
   fn new_distance(&self, prior_rules: &EditList, new_rule: &Edit, spelling: &Vec<Glyph>, pronunciation: &Vec<Glyph>) -> Option<u32>;
+  
+  /// What adding `new_rule` costs on its own, the same as find_improving_edits
+  /// gives it.
+  fn size_cost(&self, prior_rules: &EditList, new_rule: &Edit) -> f64;
+  
+  /// The indices of the only words `new_rule` could change, if the system can
+  /// tell without trying it on each of them.
+  fn affected_words(&self, _prior_rules: &EditList, _new_rule: &Edit) -> Option<Vec<usize>> {
+    None
+  }
 }
 
 pub trait Table<Estimate: Clone, Estimator: Clone, Edit> {
@@ -65,7 +77,9 @@ pub trait EstimationSystem<T: Table<Estimate, Estimator, Edit>, Estimate: Clone,
 #[derive(Debug)]
 pub enum Outcome<Edit: Debug> {
   FoundImprovement(Edit, f64),
-  FailedToFindImprovement(Edit, f64)
+  FailedToFindImprovement(Edit, f64),
+  /// No word had any improving edits left to propose.
+  Exhausted
 }
 
 #[derive(Debug, Clone)]
@@ -81,7 +95,11 @@ pub struct WorkingEntry<Estimator> {
   
   // This is an index into the exploring vector,
   // which is ordered by frequency.
-  next_to_explore_index: usize
+  next_to_explore_index: usize,
+  
+  // The words this edit could change, sorted, if the edit system can tell.
+  // Exploring any other word is known to change nothing.
+  affected: Option<Arc<[usize]>>
 }
 
 pub struct ReferenceData<'d, T, EditList> {
@@ -164,7 +182,43 @@ pub struct WorkingData<
   best_possible: KeyedPriorityQueue<Edit, FloatOrd<f64>>,
   best_possible_rev: KeyedPriorityQueue<Edit, FloatOrd<f64>>,
   
-  introducing_working_index: usize
+  introducing_working_index: usize,
+  words_explored: usize,
+  
+  log: Option<SearchLog>,
+  // Only kept once keep_culled has been called.
+  culled: Option<Vec<Candidate<Edit>>>
+}
+
+/// An edit the search was considering, with the best it might have scored.
+#[derive(Debug, Clone)]
+pub struct Candidate<Edit> {
+  pub edit: Edit,
+  pub best_possible: f64,
+  pub size_cost: f64
+}
+
+impl<Estimator, Edit: Eq + PartialEq + Hash> WorkingData<Estimator, Edit> {
+  /// Starts (or stops, with None) logging events, returning the previous log.
+  pub fn set_log(&mut self, log: Option<SearchLog>) -> Option<SearchLog> {
+    std::mem::replace(&mut self.log, log)
+  }
+  
+  /// How many (edit, word) pairs have been scored exactly so far.
+  pub fn words_explored(&self) -> usize {
+    self.words_explored
+  }
+  
+  /// Remembers culled edits from now on, so that verify can check them.
+  pub fn keep_culled(&mut self) {
+    self.culled.get_or_insert_with(Vec::new);
+  }
+  
+  fn log(&mut self, event: SearchEvent) {
+    if let Some(log) = &mut self.log {
+      log.log(event);
+    }
+  }
 }
 
 fn improving_edits_at_i<
@@ -197,7 +251,11 @@ pub fn init_working_data<Estimator, Edit: Eq + PartialEq + Hash>() -> WorkingDat
     best_possible,
     best_possible_rev,
     
-    introducing_working_index: 0
+    introducing_working_index: 0,
+    words_explored: 0,
+    
+    log: None,
+    culled: None
   }
 }
 
@@ -254,6 +312,8 @@ fn introduce<
   let introducing_improving_edits = improving_edits_at_i(edit_sys, r, w.introducing_working_index);
    
   let i = w.introducing_working_index;
+  let edits = introducing_improving_edits.len();
+  let before = w.working_table.len();
   
   for edit in introducing_improving_edits {
     if !w.working_table.contains_key(&edit.sub) {
@@ -272,7 +332,11 @@ fn introduce<
         worst_possible,
         introducing_index: w.introducing_working_index,
         next_to_explore_index: 0,
-        size_cost: edit.size_cost
+        size_cost: edit.size_cost,
+        affected: edit_sys.affected_words(r.rules, &edit.sub).map(|mut words| {
+          words.sort_unstable();
+          words.into()
+        })
       };
       
       w.working_table.insert(edit.sub.clone(), entry);
@@ -284,10 +348,16 @@ fn introduce<
     }
   }
   
+  if w.log.is_some() {
+    let word = crate::glyphs::encode(&r.dictionary.words[r.introducing_order[i]].spelling);
+    let new_edits = w.working_table.len() - before;
+    w.log(SearchEvent::Introduced { word, edits, new_edits });
+  }
+  
   w.introducing_working_index += 1;
 }
 
-fn cull_working_table<'d, 'h, Estimator, Edit: Eq + PartialEq + Hash + Debug>(w: &mut WorkingData<Estimator, Edit>, debug: bool) {
+fn cull_working_table<'d, 'h, Estimator, Edit: Eq + PartialEq + Hash + Debug + Clone>(w: &mut WorkingData<Estimator, Edit>, debug: bool) {
   // Use best_possible_rev to delete working table entries where the best possible is not
   // better than the worst_possible if the top of best_possible.
   if debug { println!("cull_working_table"); }
@@ -309,7 +379,10 @@ fn cull_working_table<'d, 'h, Estimator, Edit: Eq + PartialEq + Hash + Debug>(w:
       let sub = w.best_possible_rev.peek().unwrap().0;
       if debug { println!("  Removing {:?} because {} is not better than {}", sub, worst_best, best_worst); }
       w.best_possible.remove(sub);
-      w.working_table.remove(sub);
+      let entry = w.working_table.remove(sub).unwrap();
+      if let Some(culled) = &mut w.culled {
+        culled.push(Candidate { edit: sub.clone(), best_possible: worst_best, size_cost: entry.size_cost });
+      }
       w.best_possible_rev.pop();
     }
     else {
@@ -423,7 +496,9 @@ fn check_for_winner<
 
 struct WorkIntermediate<E> {
   estimator: E,
-  next_to_explore_index: usize
+  next_to_explore_index: usize,
+  // How many words were scored exactly, rather than skipped as unaffected.
+  scored: usize
 }
 
 fn advance_many<
@@ -445,6 +520,7 @@ fn advance_many<
 {
   let mut j = working.next_to_explore_index;
   let mut k = 0;
+  let mut scored = 0;
   let mut estimator = working.estimator.clone();
   
   while k < num_to_advance && j < r.n {
@@ -456,14 +532,21 @@ fn advance_many<
     else {
       let word = &r.dictionary.words[j];
       
-      let change = calc_change(
-        edit_sys,
-        r.rules,
-        &sub,
-        &word.spelling,
-        &word.pronunciation,
-        r.current_distances[j]
-      );
+      // Unaffected words still count towards the estimate, as a change of 0.
+      let change = if working.affected.as_ref().is_none_or(|words| words.binary_search(&j).is_ok()) {
+        scored += 1;
+        calc_change(
+          edit_sys,
+          r.rules,
+          &sub,
+          &word.spelling,
+          &word.pronunciation,
+          r.current_distances[j]
+        )
+      }
+      else {
+        0
+      };
       
       if debug {
         println!("  old distance = {}", r.current_distances[j]);
@@ -486,7 +569,8 @@ fn advance_many<
   
   WorkIntermediate {
     estimator,
-    next_to_explore_index: j
+    next_to_explore_index: j,
+    scored
   }
 }
 
@@ -500,7 +584,8 @@ struct AdvancingResult<Estimator, Edit> {
   estimator: Estimator,
   best_possible: f64,
   worst_possible: f64,
-  next_to_explore_index: usize
+  next_to_explore_index: usize,
+  scored: usize
 }
 
 fn do_work<
@@ -560,10 +645,15 @@ fn do_work<
       estimator: intermediate.estimator,
       best_possible,
       worst_possible,
-      next_to_explore_index: intermediate.next_to_explore_index
+      next_to_explore_index: intermediate.next_to_explore_index,
+      scored: intermediate.scored
     }
   }).collect();
   
+  let words: usize = result_chunk.iter().map(|res| res.scored).sum();
+  w.words_explored += words;
+  w.log(SearchEvent::Advanced { edits: result_chunk.len(), words });
+  
   for result in result_chunk {
     w.best_possible_rev.set_priority(&result.edit, FloatOrd(result.best_possible)).unwrap();
     w.best_possible.push(result.edit.clone(), FloatOrd(-result.best_possible));
@@ -620,7 +710,16 @@ pub fn step<
   
   if debug { dump_state(r, w); }
   
+  if w.introducing_working_index == 0 && w.working_table.is_empty() {
+    w.log(SearchEvent::Started { words: r.n });
+  }
+  
   if w.working_table.is_empty() {
+    if w.introducing_working_index >= r.n {
+      if debug { println!("Nothing left to introduce."); }
+      w.log(SearchEvent::Exhausted);
+      return Some(Outcome::Exhausted);
+    }
     if debug { println!("Working table is empty."); }
     introduce(est_sys, edit_sys, r, w, debug);
     None
@@ -630,7 +729,12 @@ pub fn step<
     // This means that once the working table is down to a single element, and that element's
     // worst_possible is better than best_possible_from_introducing_iterator, the algorithm
     // may terminate.
+    let before = w.working_table.len();
     cull_working_table(w, debug);
+    let removed = before - w.working_table.len();
+    if removed > 0 {
+      w.log(SearchEvent::Culled { removed, remaining: w.working_table.len() });
+    }
     
     let estimate_from_introducing_iterator = estimate_from_introducing_iterator(r, w, debug);
     let best_possible_from_introducing_iterator = estimate_from_introducing_iterator.map(|e| est_sys.calc_best_possible(&e).raw());
@@ -651,6 +755,11 @@ pub fn step<
     }
     else {
       if let Some(res) = check_for_winner(r, w, best_possible_from_introducing_iterator, debug) {
+        match &res {
+          Outcome::FoundImprovement(sub, score) => w.log(SearchEvent::Winner { edit: format!("{:?}", sub), score: *score }),
+          Outcome::FailedToFindImprovement(sub, score) => w.log(SearchEvent::Failed { edit: format!("{:?}", sub), score: *score }),
+          Outcome::Exhausted => {}
+        }
         Some(res)
       }
       else {
@@ -661,6 +770,114 @@ pub fn step<
   }
 }
 
+/// How a winner compares with the other candidates once they're all scored exactly.
+#[derive(Debug, Clone)]
+pub struct Verification<Edit> {
+  pub winner: Edit,
+  pub winner_score: f64,
+  /// The best of the winner and the candidates checked.
+  pub best: Edit,
+  pub best_score: f64,
+  /// How many edits were scored, the winner included.
+  pub checked: usize
+}
+
+impl<Edit> Verification<Edit> {
+  /// How much better the best candidate would have been.
+  pub fn regret(&self) -> f64 {
+    self.winner_score - self.best_score
+  }
+  
+  pub fn picked_wrongly(&self) -> bool {
+    self.regret() > 1e-9
+  }
+}
+
+/// An edit's score over the whole dictionary, as the search would have found
+/// it had it explored every word. Only the words affected_words names are
+/// actually visited.
+pub fn exact_score<
+    'd,
+    T,
+    EditSys: EditSystem<Edit, EditList> + Sync,
+    Edit: Eq + PartialEq + Hash + Sync,
+    EditList: Sync
+  >(
+    edit_sys: &EditSys,
+    r: &ReferenceData<'d, T, EditList>,
+    edit: &Edit,
+    size_cost: f64
+  ) -> f64
+{
+  let (dictionary, rules, current_distances) = (r.dictionary, r.rules, &r.current_distances);
+  let word_change = |j: usize| {
+    let w = &dictionary.words[j];
+    (calc_change(edit_sys, rules, edit, &w.spelling, &w.pronunciation, current_distances[j]) as f64) * w.frequency
+  };
+  let change: f64 = match edit_sys.affected_words(rules, edit) {
+    Some(words) => words.into_par_iter().map(word_change).sum(),
+    None => (0 .. r.n).into_par_iter().map(word_change).sum()
+  };
+  change + size_cost
+}
+
+/// Scores `winner` and the `runner_ups` most promising other candidates exactly:
+/// those still in the working table, and those culled since keep_culled was called.
+/// Edits that were never introduced aren't candidates, so this only ranks what
+/// the search actually looked at. The result is logged too.
+pub fn verify<
+    'd,
+    T,
+    Estimator,
+    EditSys: EditSystem<Edit, EditList> + Sync,
+    Edit: Eq + PartialEq + Hash + Clone + Debug + Sync,
+    EditList: Sync
+  >(
+    edit_sys: &EditSys,
+    r: &ReferenceData<'d, T, EditList>,
+    w: &mut WorkingData<Estimator, Edit>,
+    winner: &Edit,
+    runner_ups: usize
+  ) -> Verification<Edit>
+{
+  let mut candidates: Vec<Candidate<Edit>> = w.working_table.iter().map(|(edit, entry)| Candidate {
+    edit: edit.clone(),
+    best_possible: entry.best_possible,
+    size_cost: entry.size_cost
+  }).collect();
+  candidates.extend(w.culled.iter().flatten().cloned());
+  
+  let winner_size_cost = edit_sys.size_cost(r.rules, winner);
+  candidates.retain(|c| c.edit != *winner);
+  candidates.sort_by_key(|c| FloatOrd(c.best_possible));
+  candidates.truncate(runner_ups);
+  
+  let winner_score = exact_score(edit_sys, r, winner, winner_size_cost);
+  let mut res = Verification {
+    winner: winner.clone(),
+    winner_score,
+    best: winner.clone(),
+    best_score: winner_score,
+    checked: candidates.len() + 1
+  };
+  for c in candidates {
+    let score = exact_score(edit_sys, r, &c.edit, c.size_cost);
+    if score < res.best_score {
+      res.best = c.edit;
+      res.best_score = score;
+    }
+  }
+  
+  w.log(SearchEvent::Verified {
+    winner: format!("{:?}", res.winner),
+    winner_score: res.winner_score,
+    best: format!("{:?}", res.best),
+    best_score: res.best_score,
+    checked: res.checked
+  });
+  res
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -793,10 +1010,13 @@ mod tests {
     fn new_distance(&self, _prior_rules: &Test1EditList, new_rule: &Test1Edit, spelling: &Vec<Glyph>, pronunciation: &Vec<Glyph>) -> Option<u32> {
       self.new_distances.get(&(spelling.clone(), pronunciation.clone(), new_rule.clone())).unwrap().clone()
     }
+    
+    fn size_cost(&self, _prior_rules: &Test1EditList, new_rule: &Test1Edit) -> f64 {
+      self.improving_edits.values().flatten().find(|s| s.sub == *new_rule).map_or(0.0, |s| s.size_cost)
+    }
   }
   
-  #[test]
-  fn genastarlike_test_1() {
+  fn test1_systems() -> (Test1EditSystem, Test1EstimationSystem, Dictionary) {
     let edit_sys = Test1EditSystem {
       improving_edits: vec![
         (
@@ -896,6 +1116,13 @@ mod tests {
       ]
     };
     
+    (edit_sys, est_sys, dictionary)
+  }
+  
+  #[test]
+  fn genastarlike_test_1() {
+    let (edit_sys, est_sys, dictionary) = test1_systems();
+    
     println!("Initializing ref data...");
     let rules = Test1EditList { };
     
@@ -930,5 +1157,22 @@ mod tests {
     
     assert_eq!(the_winner.unwrap(), Test1Edit { name: "foo".to_owned() });
   }
+  
+  #[test]
+  fn verify_test_1() {
+    let (mut edit_sys, est_sys, dictionary) = test1_systems();
+    for s in edit_sys.improving_edits.values_mut().flatten() {
+      s.size_cost = 0.25;
+    }
+    let rules = Test1EditList { };
+    let r = init_ref_data(&est_sys, &edit_sys, &dictionary, &rules);
+    // Nothing's been introduced, so the winner's size cost has to come from
+    // the edit system.
+    let mut w: WorkingData<Test1Estimator, Test1Edit> = init_working_data();
+    
+    let foo = Test1Edit { name: "foo".to_owned() };
+    let verification = verify(&edit_sys, &r, &mut w, &foo, 10);
+    assert_eq!(verification.checked, 1);
+    assert_eq!(verification.winner_score, -2.0 + 0.25);
+  }
 }
-
@@ -8,25 +8,31 @@ use tempfile::{Builder, NamedTempFile};
 use crate::{substitutions2::*, glyphs::AugGlyph};
 use crate::fea_parsing as p;
 
-pub fn apply_using_hbshape(slist: &SubstitutionList, text: &Vec<AugGlyph>) -> io::Result<Vec<AugGlyph>> {
-  let encoded_text = &crate::glyphs::aug_encode(text);
-  
-  let fea_file = Builder::new().suffix(".fea").tempfile()?;
-  let in_otf_path = Path::new("../t1-1.otf");
-  let out_otf_file = Builder::new().suffix(".otf").tempfile()?;
-  
-  let text = format!("
+fn render_fea_file(slist: &SubstitutionList) -> String {
+  format!("
 @lc = [a b c d e f g h i j k l m n o p q r s t u v w x y z];
   
 feature rlig {{
   {}
 }} rlig;
-", p::render_fea_feature_body(slist));
-  
-  fs::write(&fea_file, text)?;
+", p::render_fea_feature_body(slist))
+}
+
+/// Builds `font` with the features added into a temporary file, which has to be
+/// kept for as long as the font is needed.
+fn compile(slist: &SubstitutionList, font: &Path) -> io::Result<NamedTempFile> {
+  let fea_file = Builder::new().suffix(".fea").tempfile()?;
+  let out_otf_file = Builder::new().suffix(".otf").tempfile()?;
+  fs::write(&fea_file, render_fea_file(slist))?;
   
   // fonttools feaLib -o t1-1-with-feats.otf features.fea t1-1.otf
-  successful(Command::new("fonttools").args(["feaLib", "-o", p(&out_otf_file), p(&fea_file), p2(in_otf_path)]).status()?, "fonttools")?;
+  successful(Command::new("fonttools").args(["feaLib", "-o", p(&out_otf_file), p(&fea_file), p2(font)]).status()?, "fonttools")?;
+  Ok(out_otf_file)
+}
+
+pub fn apply_using_hbshape(slist: &SubstitutionList, text: &Vec<AugGlyph>) -> io::Result<Vec<AugGlyph>> {
+  let encoded_text = &crate::glyphs::aug_encode(text);
+  let out_otf_file = compile(slist, Path::new("../t1-1.otf"))?;
   
   // hb-shape t1-1-with-feats.otf 'you'
   let output = Command::new("hb-shape").args([p(&out_otf_file), encoded_text]).output()?;
@@ -37,6 +43,26 @@ feature rlig {{
   Ok(crate::glyphs::aug_decode(output_text))
 }
 
+/// The size in bytes of the GSUB table fonttools compiles `slist` into, when
+/// added to `font`.
+pub fn compiled_gsub_size(slist: &SubstitutionList, font: &Path) -> io::Result<usize> {
+  let out_otf_file = compile(slist, font)?;
+  gsub_size(&fs::read(out_otf_file.path())?)
+}
+
+/// Reads the GSUB length out of an OpenType font's table directory.
+fn gsub_size(font: &[u8]) -> io::Result<usize> {
+  let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not an OpenType font");
+  let num_tables = u16::from_be_bytes(font.get(4 .. 6).ok_or_else(invalid)?.try_into().unwrap()) as usize;
+  for i in 0 .. num_tables {
+    let record = font.get(12 + 16 * i .. 28 + 16 * i).ok_or_else(invalid)?;
+    if &record[.. 4] == b"GSUB" {
+      return Ok(u32::from_be_bytes(record[12 .. 16].try_into().unwrap()) as usize);
+    }
+  }
+  Err(io::Error::new(io::ErrorKind::NotFound, "no GSUB table"))
+}
+
 lazy_static! {
   static ref STRIPPING_RE: Regex = Regex::new(r"^\[|\=\d+\+\d+\|?|\]$").unwrap();
 }
@@ -53,6 +79,15 @@ mod stripping_tests {
   fn test_stripping_1() {
     assert_eq!(strip_shaping_stuff("[y=0+400|u=1+1000]"), "yu".to_owned());
   }
+  
+  #[test]
+  fn reads_gsub_size() {
+    let mut font = vec![0, 1, 0, 0, 0, 2, 0, 32, 0, 1, 0, 0];
+    font.extend(b"GPOS\0\0\0\0\0\0\0\x2c\0\0\0\x10");
+    font.extend(b"GSUB\0\0\0\0\0\0\0\x3c\0\0\x01\x02");
+    assert_eq!(gsub_size(&font).unwrap(), 258);
+    assert!(gsub_size(&font[.. 30]).is_err());
+  }
 }
 
 fn p(f: &NamedTempFile) -> &str { f.path().to_str().unwrap() }
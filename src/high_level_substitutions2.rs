@@ -2,11 +2,22 @@
 use crate::glyphs::AugGlyph;
 use std::collections::HashSet;
 use crate::substitutions2 as s2;
+use serde::{Serialize, Deserialize};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct HLSubstitutionList {
   pub substitutions: Vec<HLSubstitution>
 }
 
+/// Bump this whenever the serialized form of the types below changes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct HLSubstitutionListStorage {
+  schema_version: u32,
+  substitutions: Vec<HLSubstitution>
+}
+
 impl HLSubstitutionList {
   pub fn new(substitutions: Vec<HLSubstitution>) -> HLSubstitutionList {
     let res = HLSubstitutionList {
@@ -22,19 +33,9 @@ impl HLSubstitutionList {
     let mut seen_mids = HashSet::new();
     for sub in &self.substitutions {
       if seen_mids.contains(&sub.mid) { return Err("Already contains mid".to_owned()); }
-      for g in &sub.anterior.pre_key {
+      for g in sub.anterior.glyphs() {
         if let AugGlyph::Synthetic(n) = g {
-          if !seen_mids.contains(n) { return Err("Missing back-ref".to_owned()); }
-        }
-      }
-      for g in &sub.anterior.at_key {
-        if let AugGlyph::Synthetic(n) = g {
-          if !seen_mids.contains(n) { return Err("Missing back-ref".to_owned()); }
-        }
-      }
-      for g in &sub.anterior.post_key {
-        if let AugGlyph::Synthetic(n) = g {
-          if !seen_mids.contains(n) { return Err("Missing back-ref".to_owned()); }
+          if !seen_mids.contains(&n) { return Err("Missing back-ref".to_owned()); }
         }
       }
       for g in &sub.posterior.content {
@@ -101,8 +102,33 @@ impl HLSubstitutionList {
     }
     working
   }
+
+  /// Undoes the anteriors by expanding every synthetic glyph back into the key it
+  /// replaced. Anteriors only ever consume their key, so this recovers the word
+  /// they were applied to.
+  pub fn deapply_anterior_copied(&self, word: &Vec<AugGlyph>) -> Vec<AugGlyph> {
+    let mut res = vec![];
+    for g in word {
+      match g {
+        AugGlyph::Synthetic(mid) => match self.substitutions.iter().find(|s| s.mid == *mid) {
+          Some(s) => res.extend(self.deapply_anterior_copied(&s.anterior.at_key)),
+          None => res.push(*g)
+        },
+        AugGlyph::Real(_) => res.push(*g)
+      }
+    }
+    res
+  }
   
   pub fn low_level(&self) -> s2::SubstitutionList {
+    let posterior_order: Vec<usize> = (0 .. self.substitutions.len()).rev().collect();
+    self.low_level_with_posterior_order(&posterior_order)
+  }
+  
+  /// low_level, but with the posteriors applied in `posterior_order` (indices into
+  /// the rules) instead of last rule first. Only orders that give the same outputs
+  /// make sense; nothing here checks that.
+  pub fn low_level_with_posterior_order(&self, posterior_order: &[usize]) -> s2::SubstitutionList {
     use AugGlyph::*;
     
     let mut anterior_lookups: Vec<s2::Lookup> = vec![];
@@ -116,13 +142,13 @@ impl HLSubstitutionList {
     for s in &self.substitutions {
       let here_has_non_letters = s.anterior.at_key.iter().any(|g| !g.is_letter_or_phonetic()) || s.posterior.content.iter().any(|g| !g.is_letter_or_phonetic());
       
+      let context_glyphs = s.anterior.context_glyphs();
+      
       if 
            s.anterior.at_key.iter().any(|g| working_problem_symbols.contains(&g))
-        || s.anterior.pre_key.iter().any(|g| working_at_problem_symbols.contains(&g))
-        || s.anterior.post_key.iter().any(|g| working_at_problem_symbols.contains(&g))
+        || context_glyphs.iter().any(|g| working_at_problem_symbols.contains(&g))
         || s.anterior.at_key.iter().any(|g| match g { Real(_) => false, Synthetic(n) => working_produced_symbols.contains(&n) })
-        || s.anterior.pre_key.iter().any(|g| match g { Real(_) => false, Synthetic(n) => working_produced_symbols.contains(&n) })
-        || s.anterior.post_key.iter().any(|g| match g { Real(_) => false, Synthetic(n) => working_produced_symbols.contains(&n) })
+        || context_glyphs.iter().any(|g| match g { Real(_) => false, Synthetic(n) => working_produced_symbols.contains(&n) })
         || (has_non_letters && (s.anterior.at_start || s.anterior.at_end))
         || (has_start_or_end && here_has_non_letters)
       {
@@ -140,8 +166,7 @@ impl HLSubstitutionList {
       working_anterior_lookup.extend(s.anterior_low_level());
       
       working_problem_symbols.extend(s.anterior.at_key.clone());
-      working_problem_symbols.extend(s.anterior.pre_key.clone());
-      working_problem_symbols.extend(s.anterior.post_key.clone());
+      working_problem_symbols.extend(context_glyphs);
       working_at_problem_symbols.extend(s.anterior.at_key.clone());
       has_non_letters = has_non_letters || here_has_non_letters;
       has_start_or_end = has_start_or_end || s.anterior.at_start || s.anterior.at_end;
@@ -155,7 +180,7 @@ impl HLSubstitutionList {
     let mut working_posterior_lookup: Vec<s2::Substitution> = vec![];
     let mut working_problem_symbols: HashSet<u32> = HashSet::new();
     
-    for s in self.substitutions.iter().rev() {
+    for s in posterior_order.iter().map(|&i| &self.substitutions[i]) {
       if working_problem_symbols.contains(&s.mid) {
         if !working_posterior_lookup.is_empty() {
           posterior_lookups.push(s2::Lookup { substitutions: working_posterior_lookup });
@@ -206,6 +231,75 @@ impl HLSubstitutionList {
     Ok(hlist)
   }
   
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(&HLSubstitutionListStorage {
+      schema_version: SCHEMA_VERSION,
+      substitutions: self.substitutions.clone()
+    }).unwrap()
+  }
+  
+  fn check_schema_version(schema_version: Option<u64>) -> Result<(), String> {
+    let schema_version = schema_version.ok_or("No schema_version".to_owned())?;
+    if schema_version != SCHEMA_VERSION as u64 {
+      return Err(format!("Schema version {} is not supported, expected {}", schema_version, SCHEMA_VERSION));
+    }
+    Ok(())
+  }
+  
+  /// Checks what serde can't: the back references, and that each anterior's
+  /// context is one `Anterior::decode` would accept.
+  fn from_storage(storage: HLSubstitutionListStorage) -> Result<HLSubstitutionList, String> {
+    let hlist = HLSubstitutionList {
+      substitutions: storage.substitutions
+    };
+    for sub in &hlist.substitutions {
+      sub.anterior.check_context()?;
+    }
+    hlist.check_back_refs()?;
+    Ok(hlist)
+  }
+  
+  pub fn from_json(text: &str) -> Result<HLSubstitutionList, String> {
+    // Look at the version before anything else, so that a newer file gives a
+    // useful error instead of whatever serde makes of the unfamiliar fields.
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    Self::check_schema_version(value.get("schema_version").and_then(|v| v.as_u64()))?;
+    let storage: HLSubstitutionListStorage = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    Self::from_storage(storage)
+  }
+  
+  pub fn to_toml(&self) -> String {
+    toml::to_string(&HLSubstitutionListStorage {
+      schema_version: SCHEMA_VERSION,
+      substitutions: self.substitutions.clone()
+    }).unwrap()
+  }
+  
+  pub fn from_toml(text: &str) -> Result<HLSubstitutionList, String> {
+    let value: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    Self::check_schema_version(value.get("schema_version").and_then(|v| v.as_integer()).and_then(|v| u64::try_from(v).ok()))?;
+    let storage: HLSubstitutionListStorage = value.try_into().map_err(|e: toml::de::Error| e.to_string())?;
+    Self::from_storage(storage)
+  }
+  
+  pub fn save_json(&self, path: &str) -> Result<(), String> {
+    std::fs::write(path, self.to_json()).map_err(|e| format!("{}: {}", path, e))
+  }
+  
+  pub fn load_json(path: &str) -> Result<HLSubstitutionList, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    HLSubstitutionList::from_json(&text).map_err(|e| format!("{}: {}", path, e))
+  }
+  
+  pub fn save_toml(&self, path: &str) -> Result<(), String> {
+    std::fs::write(path, self.to_toml()).map_err(|e| format!("{}: {}", path, e))
+  }
+  
+  pub fn load_toml(path: &str) -> Result<HLSubstitutionList, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    HLSubstitutionList::from_toml(&text).map_err(|e| format!("{}: {}", path, e))
+  }
+  
   pub fn set_1() -> HLSubstitutionList {
     HLSubstitutionList::decode("
       [th]→0→ϑ
@@ -612,7 +706,7 @@ impl HLSubstitutionList {
   }
 }
 
-#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct HLSubstitution {
   pub anterior: Anterior,
   pub mid: u32,
@@ -685,29 +779,311 @@ impl std::fmt::Debug for HLSubstitution {
   }
 }
 
-#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+/// A set of glyphs that a context position can match. `Vowel` and `Consonant` only
+/// ever match real glyphs, since we don't know what a synthetic glyph will become.
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum GlyphClass {
+  Vowel,
+  Consonant,
+  Set(Vec<AugGlyph>)
+}
+
+impl GlyphClass {
+  pub fn contains(&self, g: &AugGlyph) -> bool {
+    match self {
+      GlyphClass::Vowel => match g { AugGlyph::Real(g) => g.is_vowel(), AugGlyph::Synthetic(_) => false },
+      GlyphClass::Consonant => match g { AugGlyph::Real(g) => g.is_letter_or_phonetic() && !g.is_vowel(), AugGlyph::Synthetic(_) => false },
+      GlyphClass::Set(gs) => gs.contains(g)
+    }
+  }
+  
+  pub fn members(&self) -> Vec<AugGlyph> {
+    match self {
+      GlyphClass::Set(gs) => gs.clone(),
+      _ => crate::glyphs::Glyph::all().into_iter().map(AugGlyph::Real).filter(|g| self.contains(g)).collect()
+    }
+  }
+  
+  pub fn encode(&self) -> String {
+    match self {
+      GlyphClass::Vowel => "{V}".to_owned(),
+      GlyphClass::Consonant => "{C}".to_owned(),
+      GlyphClass::Set(gs) => format!("{{{}}}", crate::glyphs::aug_encode(gs))
+    }
+  }
+  
+  /// Decodes the inside of a `{...}`. Returns None if it's a number, i.e. a synthetic
+  /// glyph. An empty set is an error, since it could never match.
+  pub fn decode(inner: &str) -> Result<Option<GlyphClass>, String> {
+    if inner.parse::<u32>().is_ok() {
+      Ok(None)
+    }
+    else if inner == "V" {
+      Ok(Some(GlyphClass::Vowel))
+    }
+    else if inner == "C" {
+      Ok(Some(GlyphClass::Consonant))
+    }
+    else if inner.is_empty() {
+      Err("Empty class {}".to_owned())
+    }
+    else {
+      let mut gs = crate::glyphs::aug_decode(inner);
+      gs.sort();
+      gs.dedup();
+      Ok(Some(GlyphClass::Set(gs)))
+    }
+  }
+}
+
+/// One position of a pre- or post-key.
+/// 
+/// `Not` is only allowed as the outermost element (first of the pre-key, last of
+/// the post-key), and says that the glyph there must not match. A word boundary
+/// satisfies it. `Optional` elements may or may not be present. They are resolved
+/// greedily: an optional element is taken whenever it's there, and the edge
+/// conditions (`^`, `$` and `Not`) are then checked against that. Neither may be
+/// nested.
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum ContextElem {
+  Glyph(AugGlyph),
+  Class(GlyphClass),
+  Not(Box<ContextElem>),
+  Optional(Box<ContextElem>)
+}
+
+impl ContextElem {
+  pub fn matches(&self, g: &AugGlyph) -> bool {
+    match self {
+      ContextElem::Glyph(h) => g == h,
+      ContextElem::Class(c) => c.contains(g),
+      ContextElem::Not(e) | ContextElem::Optional(e) => e.matches(g)
+    }
+  }
+  
+  pub fn glyphs(&self) -> Vec<AugGlyph> {
+    match self {
+      ContextElem::Glyph(g) => vec![*g],
+      ContextElem::Class(c) => c.members(),
+      ContextElem::Not(e) | ContextElem::Optional(e) => e.glyphs()
+    }
+  }
+  
+  pub fn low_level(&self) -> s2::KeyElem {
+    match self {
+      ContextElem::Glyph(g) => s2::KeyElem::Glyph(*g),
+      ContextElem::Class(c) => s2::KeyElem::Class(c.members()),
+      ContextElem::Not(e) | ContextElem::Optional(e) => e.low_level()
+    }
+  }
+  
+  fn is_simple(&self) -> bool {
+    matches!(self, ContextElem::Glyph(_) | ContextElem::Class(_))
+  }
+  
+  pub fn encode(&self) -> String {
+    match self {
+      ContextElem::Glyph(g) => crate::glyphs::aug_encode(&vec![*g]),
+      ContextElem::Class(c) => c.encode(),
+      ContextElem::Not(e) => format!("!{}", e.encode()),
+      ContextElem::Optional(e) => format!("({})", e.encode())
+    }
+  }
+}
+
+pub fn context_from_glyphs(glyphs: &[AugGlyph]) -> Vec<ContextElem> {
+  glyphs.iter().map(|g| ContextElem::Glyph(*g)).collect()
+}
+
+pub fn context_encode(context: &[ContextElem]) -> String {
+  context.iter().map(|e| e.encode()).collect()
+}
+
+/// Decodes one glyph or class from the start of `text`.
+fn context_decode_simple(text: &str) -> Result<(ContextElem, &str), String> {
+  if let Some(rest) = text.strip_prefix('{') {
+    // Sets can hold synthetic glyphs, which have braces of their own.
+    let mut depth = 0;
+    let Some(close) = rest.find(|c| {
+      match c {
+        '{' => depth += 1,
+        '}' if depth == 0 => return true,
+        '}' => depth -= 1,
+        _ => {}
+      }
+      false
+    }) else { return Err("No }".to_owned()) };
+    let inner = &rest[.. close];
+    let elem = match GlyphClass::decode(inner)? {
+      Some(c) => ContextElem::Class(c),
+      None => ContextElem::Glyph(AugGlyph::Synthetic(inner.parse().unwrap()))
+    };
+    Ok((elem, &rest[close + 1 ..]))
+  }
+  else {
+    match crate::glyphs::Glyph::from_char(text) {
+      Some((g, rest)) => Ok((ContextElem::Glyph(AugGlyph::Real(g)), rest)),
+      None => Err(format!("Can't parse {:?}", text.chars().next().unwrap()))
+    }
+  }
+}
+
+pub fn context_decode(text: &str) -> Result<Vec<ContextElem>, String> {
+  let mut res = vec![];
+  let mut working = text;
+  
+  while !working.is_empty() {
+    if let Some(rest) = working.strip_prefix('!') {
+      if rest.is_empty() { return Err("Nothing after !".to_owned()) }
+      let (elem, rest) = context_decode_simple(rest)?;
+      res.push(ContextElem::Not(Box::new(elem)));
+      working = rest;
+    }
+    else if let Some(rest) = working.strip_prefix('(') {
+      if rest.is_empty() { return Err("Nothing in ()".to_owned()) }
+      let (elem, rest) = context_decode_simple(rest)?;
+      let Some(rest) = rest.strip_prefix(')') else { return Err("No )".to_owned()) };
+      res.push(ContextElem::Optional(Box::new(elem)));
+      working = rest;
+    }
+    else {
+      let (elem, rest) = context_decode_simple(working)?;
+      res.push(elem);
+      working = rest;
+    }
+  }
+  
+  Ok(res)
+}
+
+fn context_matches(context: &[ContextElem], word: &[AugGlyph]) -> bool {
+  context.len() == word.len() && context.iter().zip(word.iter()).all(|(c, g)| c.matches(g))
+}
+
+/// An anterior with its optional elements resolved one particular way, and its
+/// negative elements split off.
+struct AnteriorVariant {
+  pre_not: Option<ContextElem>,
+  pre_key: Vec<ContextElem>,
+  post_key: Vec<ContextElem>,
+  post_not: Option<ContextElem>
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Anterior {
-  pub pre_key: Vec<AugGlyph>,
+  pub pre_key: Vec<ContextElem>,
   pub at_key: Vec<AugGlyph>,
-  pub post_key: Vec<AugGlyph>,
+  pub post_key: Vec<ContextElem>,
   pub at_start: bool,
   pub at_end: bool,
 }
 
 impl Anterior {
+  /// Every glyph the pre- and post-key could match, with classes expanded.
+  pub fn context_glyphs(&self) -> Vec<AugGlyph> {
+    self.pre_key.iter().chain(self.post_key.iter()).flat_map(|e| e.glyphs()).collect()
+  }
+  
+  /// Every glyph this anterior looks at, with classes expanded.
+  pub fn glyphs(&self) -> Vec<AugGlyph> {
+    let mut res = self.at_key.clone();
+    res.extend(self.context_glyphs());
+    res
+  }
+  
+  /// Checks that `Not` only appears at the outer edges, that nothing is nested and
+  /// that no class is an empty set.
+  pub fn check_context(&self) -> Result<(), String> {
+    for e in self.pre_key.iter().chain(self.post_key.iter()) {
+      let inner = match e { ContextElem::Not(inner) | ContextElem::Optional(inner) => inner, _ => e };
+      if *inner == ContextElem::Class(GlyphClass::Set(vec![])) {
+        return Err(format!("Empty class in {}", e.encode()));
+      }
+    }
+    for (i, e) in self.pre_key.iter().enumerate() {
+      match e {
+        ContextElem::Not(inner) if i == 0 && inner.is_simple() => {},
+        ContextElem::Optional(inner) if inner.is_simple() => {},
+        ContextElem::Not(_) | ContextElem::Optional(_) => return Err(format!("Bad context element {}", e.encode())),
+        _ => {}
+      }
+    }
+    for (i, e) in self.post_key.iter().enumerate() {
+      match e {
+        ContextElem::Not(inner) if i + 1 == self.post_key.len() && inner.is_simple() => {},
+        ContextElem::Optional(inner) if inner.is_simple() => {},
+        ContextElem::Not(_) | ContextElem::Optional(_) => return Err(format!("Bad context element {}", e.encode())),
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+  
+  /// Every way of resolving the optional elements, most greedy first. Earlier
+  /// optional elements take priority over later ones.
+  fn variants(&self) -> Vec<AnteriorVariant> {
+    let num_optional = self.pre_key.iter().chain(self.post_key.iter()).filter(|e| matches!(e, ContextElem::Optional(_))).count();
+    
+    (0 .. (1usize << num_optional)).map(|mask| {
+      let mut optional_i = 0;
+      let mut resolve = |context: &Vec<ContextElem>| -> (Option<ContextElem>, Vec<ContextElem>) {
+        let mut not = None;
+        let mut key = vec![];
+        for e in context {
+          match e {
+            ContextElem::Not(inner) => not = Some((**inner).clone()),
+            ContextElem::Optional(inner) => {
+              // The first optional element is the top bit, so that including it comes first.
+              if mask & (1 << (num_optional - 1 - optional_i)) == 0 {
+                key.push((**inner).clone());
+              }
+              optional_i += 1;
+            },
+            _ => key.push(e.clone())
+          }
+        }
+        (not, key)
+      };
+      let (pre_not, pre_key) = resolve(&self.pre_key);
+      let (post_not, post_key) = resolve(&self.post_key);
+      AnteriorVariant { pre_not, pre_key, post_key, post_not }
+    }).collect()
+  }
+  
+  /// None if `v` doesn't match at `pos`, otherwise whether the edge conditions allow it.
+  fn variant_allows(&self, v: &AnteriorVariant, word: &[AugGlyph], pos: usize) -> Option<bool> {
+    let pre_len = v.pre_key.len();
+    let post_end = pos + self.at_key.len() + v.post_key.len();
+    
+    if pos < pre_len
+      || post_end > word.len()
+      || !context_matches(&v.pre_key, &word[pos - pre_len .. pos])
+      || self.at_key[..] != word[pos .. pos + self.at_key.len()]
+      || !context_matches(&v.post_key, &word[pos + self.at_key.len() .. post_end])
+    {
+      return None;
+    }
+    
+    let before = if pos > pre_len { Some(&word[pos - pre_len - 1]) } else { None };
+    let after = word.get(post_end);
+    
+    Some(!(
+         (self.at_start && before.is_some_and(|g| g.is_letter_or_phonetic()))
+      || (self.at_end && after.is_some_and(|g| g.is_letter_or_phonetic()))
+      || v.pre_not.as_ref().is_some_and(|e| before.is_some_and(|g| e.matches(g)))
+      || v.post_not.as_ref().is_some_and(|e| after.is_some_and(|g| e.matches(g)))
+    ))
+  }
+  
   pub fn apply(&self, word: &mut Vec<AugGlyph>, mid: u32) -> bool {
+    let variants = self.variants();
     let mut pos: usize = 0;
     let mut any_mod = false;
     while pos < word.len() {
-      if !(
-           (pos < self.pre_key.len())
-        || (pos + self.at_key.len() + self.post_key.len() > word.len())
-        || (self.at_start && pos > self.pre_key.len() && word[pos-self.pre_key.len()-1].is_letter_or_phonetic())
-        || (self.at_end && pos + self.at_key.len() + self.post_key.len() < word.len() && word[pos+self.at_key.len()+self.post_key.len()].is_letter_or_phonetic())
-        || (&self.pre_key != &word[pos - self.pre_key.len() .. pos])
-        || (&self.at_key != &word[pos .. pos + self.at_key.len()])
-        || (&self.post_key != &word[pos + self.at_key.len() .. pos + self.at_key.len() + self.post_key.len()])
-      ) {
+      // The first variant that matches decides, just like the first matching
+      // line (ignore or not) does in the low-level lookup.
+      let allowed = variants.iter().find_map(|v| self.variant_allows(v, word, pos));
+      if allowed == Some(true) {
         let working_s1 = pos;
         let working_s2 = pos + self.at_key.len();
         word.splice(working_s1 .. working_s2, vec![AugGlyph::Synthetic(mid)]);
@@ -744,13 +1120,15 @@ impl Anterior {
 
     if let [pre_str, rest] = anterior_str.split("[").collect::<Vec<_>>()[..] {
       if let [at_str, post_str] = rest.split("]").collect::<Vec<_>>()[..] {
-        Ok(Anterior {
+        let anterior = Anterior {
           at_start,
           at_end,
-          pre_key: crate::glyphs::aug_decode(pre_str),
+          pre_key: context_decode(pre_str)?,
           at_key: crate::glyphs::aug_decode(at_str),
-          post_key: crate::glyphs::aug_decode(post_str)
-        })
+          post_key: context_decode(post_str)?
+        };
+        anterior.check_context()?;
+        Ok(anterior)
       }
       else {
         Err("No ]".to_owned())
@@ -764,41 +1142,58 @@ impl Anterior {
   pub fn low_level(&self, mid: u32) -> Vec<s2::Substitution> {
     let mut working = vec![];
     
-    let el_pre_key: Vec<s2::KeyElem> = self.pre_key.iter().map(|g| s2::KeyElem::Glyph(*g)).collect();
-    let el_post_key: Vec<s2::KeyElem> = self.post_key.iter().map(|g| s2::KeyElem::Glyph(*g)).collect();
-    
-    if self.at_start {
-      let mut start_pre_key = vec![s2::KeyElem::AnyLetter];
-      start_pre_key.extend(el_pre_key.clone());
-      let mut end_post_key: Vec<s2::KeyElem> = self.at_key[1 ..].iter().map(|g| s2::KeyElem::Glyph(*g)).collect();
-      end_post_key.extend(el_post_key.clone());
-      working.push(s2::Substitution {
-        pre_key: start_pre_key,
-        at_key: vec![self.at_key[0]],
-        post_key: end_post_key,
-        sub_content: s2::SubContent::Ignore
-      });
-    }
-    
-    if self.at_end {
-      let mut end_post_key: Vec<s2::KeyElem> = self.at_key[1 ..].iter().map(|g| s2::KeyElem::Glyph(*g)).collect();
-      end_post_key.extend(el_post_key.clone());
-      end_post_key.push(s2::KeyElem::AnyLetter);
+    for v in self.variants() {
+      let el_pre_key: Vec<s2::KeyElem> = v.pre_key.iter().map(|e| e.low_level()).collect();
+      let el_post_key: Vec<s2::KeyElem> = v.post_key.iter().map(|e| e.low_level()).collect();
+      let mut el_rest_key: Vec<s2::KeyElem> = self.at_key[1 ..].iter().map(|g| s2::KeyElem::Glyph(*g)).collect();
+      el_rest_key.extend(el_post_key.clone());
+      
+      // Anything that has to be absent before or after the match becomes an ignore.
+      let mut before_exceptions = vec![];
+      if self.at_start {
+        before_exceptions.push(s2::KeyElem::AnyLetter);
+      }
+      if let Some(e) = &v.pre_not {
+        before_exceptions.push(e.low_level());
+      }
+      let mut after_exceptions = vec![];
+      if self.at_end {
+        after_exceptions.push(s2::KeyElem::AnyLetter);
+      }
+      if let Some(e) = &v.post_not {
+        after_exceptions.push(e.low_level());
+      }
+      
+      for e in before_exceptions {
+        let mut start_pre_key = vec![e];
+        start_pre_key.extend(el_pre_key.clone());
+        working.push(s2::Substitution {
+          pre_key: start_pre_key,
+          at_key: vec![self.at_key[0]],
+          post_key: el_rest_key.clone(),
+          sub_content: s2::SubContent::Ignore
+        });
+      }
+      
+      for e in after_exceptions {
+        let mut end_post_key = el_rest_key.clone();
+        end_post_key.push(e);
+        working.push(s2::Substitution {
+          pre_key: el_pre_key.clone(),
+          at_key: vec![self.at_key[0]],
+          post_key: end_post_key,
+          sub_content: s2::SubContent::Ignore
+        });
+      }
+      
       working.push(s2::Substitution {
-        pre_key: el_pre_key.clone(),
-        at_key: vec![self.at_key[0]],
-        post_key: end_post_key,
-        sub_content: s2::SubContent::Ignore
+        pre_key: el_pre_key,
+        at_key: self.at_key.clone(),
+        post_key: el_post_key,
+        sub_content: s2::SubContent::Sub(vec![AugGlyph::Synthetic(mid)])
       });
     }
     
-    working.push(s2::Substitution {
-      pre_key: el_pre_key,
-      at_key: self.at_key.clone(),
-      post_key: el_post_key,
-      sub_content: s2::SubContent::Sub(vec![AugGlyph::Synthetic(mid)])
-    });
-    
     working
   }
   
@@ -806,15 +1201,15 @@ impl Anterior {
     use crate::glyphs::aug_encode;
     format!("{}{}[{}]{}{}",
       if self.at_start { "^" } else { "" },
-      aug_encode(&self.pre_key),
+      context_encode(&self.pre_key),
       aug_encode(&self.at_key),
-      aug_encode(&self.post_key),
+      context_encode(&self.post_key),
       if self.at_end { "$" } else { "" },
     )
   }
 }
 
-#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Posterior {
   pub content: Vec<AugGlyph>
 }
@@ -952,11 +1347,100 @@ mod tests {
       "^[a]$→3→b",
       "[a]$→3→bgq",
       "[a]{7}$→3→bgq",
+      "[c]{V}→3→s",
+      "{C}[a]{eiy}→3→b",
+      "{0}{C}[a]→3→b",
+      "[c]!{V}→3→k",
+      "!s(r)[a]→3→b",
+      "^(h)[a]t(e)!s$→3→b",
     ] {
       println!("{}", str);
       assert_eq!(&HLSubstitution::encode(&HLSubstitution::decode(str).unwrap()), str);
     }
   }
+
+  #[test]
+  fn test_encoding_synthetic_sets() {
+    let set = GlyphClass::Set(vec![AugGlyph::Real(crate::glyphs::Glyph::A), AugGlyph::Synthetic(0), AugGlyph::Synthetic(12)]);
+    let sub = HLSubstitution {
+      anterior: Anterior { pre_key: vec![ContextElem::Class(set.clone())], at_key: aug_decode("c"), post_key: vec![ContextElem::Not(Box::new(ContextElem::Class(set)))], at_start: false, at_end: false },
+      mid: 13,
+      posterior: Posterior { content: aug_decode("k") }
+    };
+    assert_eq!(sub.encode(), "{a{0}{12}}[c]!{a{0}{12}}→13→k");
+    assert!(HLSubstitution::decode(&sub.encode()).unwrap() == sub);
+
+    for str in ["[c]%→0→k", "{a{0}[c]→0→k", "[c] {V}→0→k"] {
+      assert!(HLSubstitution::decode(str).is_err(), "{}", str);
+    }
+  }
+
+  #[test]
+  fn test_json_1() {
+    let set_1 = HLSubstitutionList::set_1();
+    let decoded = HLSubstitutionList::from_json(&set_1.to_json()).unwrap();
+    assert!(decoded.substitutions == set_1.substitutions);
+    
+    let with_contexts = HLSubstitutionList::decode("
+      [c]→0→k
+      ^!s(r){0}[a]{eiy}$→1→æ
+    ").unwrap();
+    let decoded = HLSubstitutionList::from_json(&with_contexts.to_json()).unwrap();
+    assert!(decoded.substitutions == with_contexts.substitutions);
+  }
+  
+  #[test]
+  fn test_json_2() {
+    let json = HLSubstitutionList::decode("[c]{V}→0→k").unwrap().to_json();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value, serde_json::json!({
+      "schema_version": SCHEMA_VERSION,
+      "substitutions": [{
+        "anterior": {
+          "pre_key": [],
+          "at_key": [{"Real": "C"}],
+          "post_key": [{"Class": "Vowel"}],
+          "at_start": false,
+          "at_end": false
+        },
+        "mid": 0,
+        "posterior": { "content": [{"Real": "K"}] }
+      }]
+    }));
+    
+    let newer = json.replace(&format!("\"schema_version\": {}", SCHEMA_VERSION), "\"schema_version\": 999");
+    assert_eq!(HLSubstitutionList::from_json(&newer).err(), Some(format!("Schema version 999 is not supported, expected {}", SCHEMA_VERSION)));
+    
+    let missing_back_ref = json.replace("\"Real\": \"C\"", "\"Synthetic\": 3");
+    assert!(HLSubstitutionList::from_json(&missing_back_ref).is_err());
+    
+    // A Not that isn't outermost, which Anterior::decode wouldn't let through either.
+    let mut bad_context = HLSubstitutionList::decode("[c]!{V}→0→k").unwrap();
+    bad_context.substitutions[0].anterior.post_key.push(ContextElem::Class(GlyphClass::Vowel));
+    assert_eq!(HLSubstitutionList::from_json(&bad_context.to_json()).err(), Some("Bad context element !{V}".to_owned()));
+    assert_eq!(HLSubstitutionList::from_toml(&bad_context.to_toml()).err(), Some("Bad context element !{V}".to_owned()));
+    
+    let mut empty_class = HLSubstitutionList::decode("[c]{V}→0→k").unwrap();
+    empty_class.substitutions[0].anterior.post_key[0] = ContextElem::Class(GlyphClass::Set(vec![]));
+    assert_eq!(HLSubstitutionList::from_json(&empty_class.to_json()).err(), Some("Empty class in {}".to_owned()));
+  }
+  
+  #[test]
+  fn test_toml() {
+    let rules = HLSubstitutionList::decode("
+      [c]→0→k
+      ^!s(r){0}[a]{eiy}$→1→æ
+    ").unwrap();
+    let toml = rules.to_toml();
+    assert!(toml.starts_with(&format!("schema_version = {}", SCHEMA_VERSION)));
+    assert!(HLSubstitutionList::from_toml(&toml).unwrap().substitutions == rules.substitutions);
+    
+    let set_1 = HLSubstitutionList::set_1();
+    assert!(HLSubstitutionList::from_toml(&set_1.to_toml()).unwrap().substitutions == set_1.substitutions);
+    
+    let newer = toml.replace(&format!("schema_version = {}", SCHEMA_VERSION), "schema_version = 999");
+    assert_eq!(HLSubstitutionList::from_toml(&newer).err(), Some(format!("Schema version 999 is not supported, expected {}", SCHEMA_VERSION)));
+  }
   
   #[test]
   fn posterior_low_level_test_1() {
@@ -998,6 +1482,12 @@ mod tests {
       ("a[a]", "aaaa", "a{0}a{0}"),
       ("^[a]", "cat", "cat"),
       ("[at]$", "cat", "c{0}"),
+      ("[c]{V}", "cat", "{0}at"),
+      ("[c]{V}", "ct", "ct"),
+      ("{C}[a]", "cat", "c{0}t"),
+      ("{C}[a]", "aat", "aat"),
+      ("[c]{eiy}", "cicada", "{0}icada"),
+      ("[c]{V}", "c{1}", "c{1}"),
     ] {
       println!("{} {} {}", anterior_str, word_str, should_str);
       
@@ -1025,6 +1515,146 @@ mod tests {
     }
   }
   
+  #[test]
+  fn test_bad_context() {
+    assert!(Anterior::decode("a!b[c]").is_err());
+    assert!(Anterior::decode("[c]!ab").is_err());
+    assert!(Anterior::decode("[c](a").is_err());
+    assert!(Anterior::decode("!(a)[c]").is_err());
+    assert_eq!(Anterior::decode("{}[c]").err(), Some("Empty class {}".to_owned()));
+    assert!(Anterior::decode("[c]!{}").is_err());
+    
+    let mut empty = Anterior::decode("[c]{V}").unwrap();
+    empty.post_key[0] = ContextElem::Not(Box::new(ContextElem::Class(GlyphClass::Set(vec![]))));
+    assert_eq!(empty.check_context(), Err("Empty class in !{}".to_owned()));
+  }
+  
+  #[test]
+  fn anterior_low_level_test_2() {
+    for (anterior_str, should_str) in [
+      ("[c]!e", "ignore sub c' e; sub c' by syn0;"),
+      ("!s[a]", "ignore sub s a'; sub a' by syn0;"),
+      ("(r)[a]", "sub r a' by syn0; sub a' by syn0;"),
+      ("^(r)[a]", "ignore sub @lc r a'; sub r a' by syn0; ignore sub @lc a'; sub a' by syn0;"),
+    ] {
+      let anterior = Anterior::decode(anterior_str).unwrap();
+      let low_level = anterior.low_level(0);
+      assert_eq!(
+         itertools::Itertools::intersperse(low_level.iter().map(|s| crate::fea_parsing::render_fea_sub(s)), " ".to_owned()).collect::<String>(),
+        should_str
+      );
+    }
+  }
+  
+  #[test]
+  fn test_anterior_correspondence_5() {
+    for (anterior_str, word_str, should_str) in [
+      ("[c]!{V}", "cat", "cat"),
+      ("[c]!{V}", "act", "a{0}t"),
+      ("[c]!{V}", "tic", "ti{0}"),
+      ("!s[c]", "scat", "scat"),
+      ("!s[c]", "cat", "{0}at"),
+      ("t(r)[a]", "tap", "t{0}p"),
+      ("t(r)[a]", "trap", "tr{0}p"),
+      ("t(r)[a]", "rap", "rap"),
+      ("!s(r)[a]", "sra", "sra"),
+      ("!s(r)[a]", "sa", "sa"),
+      ("!s(r)[a]", "ra", "r{0}"),
+      ("^(r)[a]", "ra", "r{0}"),
+      ("^(r)[a]", "xra", "xra"),
+      ("[a](e)$", "ae", "{0}e"),
+      ("[a](e)$", "aet", "aet"),
+    ] {
+      println!("{} {} {}", anterior_str, word_str, should_str);
+      
+      let word = aug_decode(word_str);
+      let anterior = Anterior::decode(anterior_str).unwrap();
+      
+      let mut applied_directly = word.clone();
+      anterior.apply(&mut applied_directly, 0);
+      
+      let mut applied_by_low_level = word.clone();
+      let sl = s2::SubstitutionList {
+        lookups: vec![s2::Lookup {
+          substitutions: anterior.low_level(0)
+        }]
+      };
+      s2::apply_all(&mut applied_by_low_level, &sl);
+      
+      assert_eq!(aug_encode(&applied_directly), should_str);
+      assert_eq!(aug_encode(&applied_by_low_level), should_str);
+    }
+  }
+  
+  #[test]
+  fn test_anterior_correspondence_6() {
+    use rand::{distributions::{Uniform, Bernoulli}, prelude::Distribution};
+    let mut rng = rand::thread_rng();
+    
+    let glyphs = crate::glyphs::decode("abʃ-'");
+    
+    let random_elem = |rng: &mut rand::rngs::ThreadRng| {
+      match Uniform::new(0, 4).sample(rng) {
+        0 => ContextElem::Glyph(AugGlyph::Synthetic(0)),
+        1 => ContextElem::Class(GlyphClass::Vowel),
+        _ => ContextElem::Glyph(AugGlyph::Real(glyphs[Uniform::new(0, glyphs.len()).sample(rng)]))
+      }
+    };
+    
+    for _ in 0 .. 10000 {
+      let word: Vec<AugGlyph> = (0 .. Uniform::new_inclusive(1, 6).sample(&mut rng)).map(|_| {
+        match Bernoulli::new(0.1).unwrap().sample(&mut rng) {
+          false => AugGlyph::Real(glyphs[Uniform::new(0, glyphs.len()).sample(&mut rng)]),
+          true => AugGlyph::Synthetic(0),
+        }
+      }).collect();
+      
+      let mut pre_key: Vec<ContextElem> = (0 .. Uniform::new_inclusive(0, 2).sample(&mut rng)).map(|_| {
+        let e = random_elem(&mut rng);
+        if Bernoulli::new(0.3).unwrap().sample(&mut rng) { ContextElem::Optional(Box::new(e)) } else { e }
+      }).collect();
+      if Bernoulli::new(0.3).unwrap().sample(&mut rng) {
+        pre_key.insert(0, ContextElem::Not(Box::new(random_elem(&mut rng))));
+      }
+      let mut post_key: Vec<ContextElem> = (0 .. Uniform::new_inclusive(0, 2).sample(&mut rng)).map(|_| {
+        let e = random_elem(&mut rng);
+        if Bernoulli::new(0.3).unwrap().sample(&mut rng) { ContextElem::Optional(Box::new(e)) } else { e }
+      }).collect();
+      if Bernoulli::new(0.3).unwrap().sample(&mut rng) {
+        post_key.push(ContextElem::Not(Box::new(random_elem(&mut rng))));
+      }
+      
+      let anterior = Anterior {
+        at_start: Bernoulli::new(0.2).unwrap().sample(&mut rng),
+        at_end: Bernoulli::new(0.2).unwrap().sample(&mut rng),
+        pre_key,
+        at_key: (0 .. Uniform::new_inclusive(1, 2).sample(&mut rng)).map(|_| {
+          AugGlyph::Real(glyphs[Uniform::new(0, glyphs.len()).sample(&mut rng)])
+        }).collect(),
+        post_key,
+      };
+      assert!(anterior.check_context().is_ok());
+      
+      let mut applied_directly = word.clone();
+      anterior.apply(&mut applied_directly, 1);
+      
+      let mut applied_by_low_level = word.clone();
+      let sl = s2::SubstitutionList {
+        lookups: vec![s2::Lookup {
+          substitutions: anterior.low_level(1)
+        }]
+      };
+      s2::apply_all(&mut applied_by_low_level, &sl);
+      
+      if aug_encode(&applied_directly) != aug_encode(&applied_by_low_level) {
+        println!("{} {}", anterior.encode(), aug_encode(&word));
+        println!("directly: {}", aug_encode(&applied_directly));
+        println!("by low level: {}", aug_encode(&applied_by_low_level));
+      }
+      assert_eq!(aug_encode(&applied_directly), aug_encode(&applied_by_low_level));
+    }
+  }
+  
   #[test]
   fn test_anterior_correspondence_2() {
     use rand::{distributions::{Uniform, Bernoulli}, prelude::Distribution};
@@ -1050,7 +1680,7 @@ mod tests {
             false => AugGlyph::Real(glyphs[Uniform::new(0, glyphs.len()).sample(&mut rng)]),
             true => AugGlyph::Synthetic(0),
           }
-        }).collect(),
+        }).map(ContextElem::Glyph).collect(),
         at_key: (0 .. Uniform::new_inclusive(1, 3).sample(&mut rng)).map(|_| {
           match Bernoulli::new(0.1).unwrap().sample(&mut rng) {
             false => AugGlyph::Real(glyphs[Uniform::new(0, glyphs.len()).sample(&mut rng)]),
@@ -1062,7 +1692,7 @@ mod tests {
             false => AugGlyph::Real(glyphs[Uniform::new(0, glyphs.len()).sample(&mut rng)]),
             true => AugGlyph::Synthetic(0),
           }
-        }).collect(),
+        }).map(ContextElem::Glyph).collect(),
       };
       
       let word_str = aug_encode(&word);
@@ -1517,7 +2147,7 @@ lookup l1 {
                   true => AugGlyph::Synthetic(Uniform::new(0, i).sample(&mut rng)),
                 }
               }
-            }).collect(),
+            }).map(ContextElem::Glyph).collect(),
             at_key: (0 .. Uniform::new_inclusive(1, 3).sample(&mut rng)).map(|_| {
               if i == 0 {
                 AugGlyph::Real(glyphs[Uniform::new(0, glyphs.len()).sample(&mut rng)])
@@ -1539,7 +2169,7 @@ lookup l1 {
                   true => AugGlyph::Synthetic(Uniform::new(0, i).sample(&mut rng)),
                 }
               }
-            }).collect(),
+            }).map(ContextElem::Glyph).collect(),
           };
           
           let posterior = Posterior {
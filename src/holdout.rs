@@ -0,0 +1,216 @@
+
+/* Held-out evaluation of rule sets.
+ *
+ * A rule set scored on the words it was learned from always looks better with
+ * one more rule, even when that rule only fixes a single word, like
+ * ^[{16}s]→90→y{64}{31} for "use". Setting some words aside and scoring the
+ * rules on those as well shows when new rules stop generalising.
+ *
+ * split ranks the words by frequency and deals them out to `folds` folds in
+ * turn, so every fold gets its share of common and rare words, and the same
+ * dictionary always splits the same way. A Holdout follows a search on the
+ * training words, applying each new rule to the test words too, and reports
+ * the mean word cost of both. cross_validate does that for every fold.
+ */
+
+use crate::astarlike2::{IterativeSystem, Outcome};
+use crate::dictionary::Dictionary;
+use crate::genastarlike;
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList};
+use serde::{Serialize, Deserialize};
+
+fn check_fold(folds: usize, fold: usize) -> Result<(), String> {
+  if folds < 2 {
+    return Err(format!("need at least 2 folds, not {}", folds));
+  }
+  if fold >= folds {
+    return Err(format!("fold {} is out of range for {} folds", fold, folds));
+  }
+  Ok(())
+}
+
+/// The training and test words for one fold, each in dictionary order.
+pub fn split(dictionary: &Dictionary, folds: usize, fold: usize) -> Result<(Dictionary, Dictionary), String> {
+  check_fold(folds, fold)?;
+  let mut by_frequency: Vec<usize> = (0 .. dictionary.words.len()).collect();
+  by_frequency.sort_by(|&a, &b| dictionary.words[b].frequency.total_cmp(&dictionary.words[a].frequency));
+  let mut in_test = vec![false; dictionary.words.len()];
+  for (rank, &i) in by_frequency.iter().enumerate() {
+    in_test[i] = rank % folds == fold;
+  }
+
+  let (mut train, mut test) = (vec![], vec![]);
+  for (w, &t) in dictionary.words.iter().zip(&in_test) {
+    if t { test.push(w.clone()) } else { train.push(w.clone()) }
+  }
+  Ok((Dictionary { words: train }, Dictionary { words: test }))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleReport {
+  /// How many rules there were.
+  pub rules: usize,
+  /// The one just added, encoded; None before the first.
+  pub rule: Option<String>,
+  /// Mean word costs, as IterativeSystem::mean_word_cost.
+  pub train: f64,
+  pub test: f64
+}
+
+/// The report with the lowest test cost, the earliest if there's a tie.
+pub fn best(reports: &[RuleReport]) -> &RuleReport {
+  reports.iter().reduce(|best, r| if r.test < best.test { r } else { best }).unwrap()
+}
+
+pub struct Holdout {
+  /// The test words, with the same rules applied as the training system.
+  pub test: IterativeSystem,
+  pub reports: Vec<RuleReport>
+}
+
+impl Holdout {
+  /// Starts with a report for the rules `train` already has.
+  pub fn new(test: &Dictionary, train: &IterativeSystem) -> Holdout {
+    let mut system = IterativeSystem::setup(test, HLSubstitutionList { substitutions: vec![] });
+    system.objective = train.objective.clone();
+    let mut res = Holdout { test: system, reports: vec![] };
+    res.record(train);
+    res
+  }
+
+  /// Catches the test words up with the rules `train` has found since the last
+  /// report, and reports on both.
+  pub fn record(&mut self, train: &IterativeSystem) -> &RuleReport {
+    let new_rules = &train.rules.substitutions[self.test.rules.substitutions.len() ..];
+    for sub in new_rules {
+      self.test.push_rule(sub.clone());
+    }
+    self.reports.push(RuleReport {
+      rules: train.rules.substitutions.len(),
+      rule: train.rules.substitutions.last().filter(|_| !new_rules.is_empty()).map(HLSubstitution::encode),
+      train: train.mean_word_cost(),
+      test: self.test.mean_word_cost()
+    });
+    self.reports.last().unwrap()
+  }
+
+  pub fn best(&self) -> &RuleReport {
+    best(&self.reports)
+  }
+
+  /// Whether the last `patience` rules have all failed to beat the best test cost.
+  pub fn stalled(&self, patience: usize) -> bool {
+    self.reports.last().unwrap().rules >= self.best().rules + patience
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FoldResult {
+  pub fold: usize,
+  pub reports: Vec<RuleReport>
+}
+
+/// Searches for up to `max_rules` rules on each fold's training words, stopping
+/// early once `patience` rules in a row haven't improved the test cost.
+/// `configure` is called on each training system before it starts.
+pub fn cross_validate<T, Estimate, Estimator, S>(sys: &S, dictionary: &Dictionary, folds: usize, max_rules: usize, patience: Option<usize>, configure: &dyn Fn(&mut IterativeSystem)) -> Result<Vec<FoldResult>, String> where
+  T: genastarlike::Table<Estimate, Estimator, HLSubstitution> + Send + Sync,
+  Estimate: Clone + Send + Sync,
+  Estimator: Clone + Send + Sync,
+  S: genastarlike::EstimationSystem<T, Estimate, Estimator, HLSubstitution> + Send + Sync
+{
+  check_fold(folds, 0)?;
+  (0 .. folds).map(|fold| {
+    let (train, test) = split(dictionary, folds, fold)?;
+    let mut system = IterativeSystem::setup(&train, HLSubstitutionList { substitutions: vec![] });
+    configure(&mut system);
+    system.check_objective()?;
+    let mut holdout = Holdout::new(&test, &system);
+    while system.steps < max_rules && !patience.is_some_and(|p| holdout.stalled(p)) {
+      let Outcome::FoundImprovement(..) = system.find_next_rule(sys, false) else { break };
+      holdout.record(&system);
+    }
+    Ok(FoldResult { fold, reports: holdout.reports })
+  }).collect()
+}
+
+/// For each rule count, the mean train and test costs over the folds that got
+/// that far, and how many folds that was.
+pub fn mean_by_rule_count(results: &[FoldResult]) -> Vec<(usize, f64, f64, usize)> {
+  let longest = results.iter().map(|r| r.reports.len()).max().unwrap_or(0);
+  (0 .. longest).map(|i| {
+    let reports: Vec<&RuleReport> = results.iter().filter_map(|r| r.reports.get(i)).collect();
+    let n = reports.len() as f64;
+    (
+      reports[0].rules,
+      reports.iter().map(|r| r.train).sum::<f64>() / n,
+      reports.iter().map(|r| r.test).sum::<f64>() / n,
+      reports.len()
+    )
+  }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gaussian_astarlike22::GaussianSystem;
+  use crate::objective::{evaluate, weighted_word_cost, ObjectiveWeights, WeightedObjective};
+
+  #[test]
+  fn splits_are_stratified_and_disjoint() {
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(1000);
+    let total: f64 = dictionary.words.iter().map(|w| w.frequency).sum();
+
+    let mut seen = std::collections::HashSet::new();
+    for fold in 0 .. 4 {
+      let (train, test) = split(&dictionary, 4, fold).unwrap();
+      assert_eq!((train.words.len(), test.words.len()), (750, 250));
+      for w in &test.words {
+        assert!(seen.insert(crate::glyphs::encode(&w.spelling)));
+      }
+      // Each fold gets about its share of the frequency, not just of the words.
+      let test_total: f64 = test.words.iter().map(|w| w.frequency).sum();
+      assert!((test_total / total - 0.25).abs() < 0.1);
+
+      let (again, _) = split(&dictionary, 4, fold).unwrap();
+      assert!(train.words.iter().zip(&again.words).all(|(a, b)| a.spelling == b.spelling));
+    }
+    assert_eq!(seen.len(), 1000);
+
+    assert!(split(&dictionary, 3, 5).is_err());
+    // One fold would leave nothing to train on.
+    assert!(split(&dictionary, 1, 0).is_err());
+  }
+
+  #[test]
+  fn reports_train_and_test_costs() {
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(300);
+    let results = cross_validate(&GaussianSystem { scale: 4.0 }, &dictionary, 3, 3, None, &|_| {}).unwrap();
+    assert_eq!(results.len(), 3);
+    for result in &results {
+      assert_eq!(result.reports.iter().map(|r| r.rules).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+      assert!(result.reports[0].rule.is_none() && result.reports[1].rule.is_some());
+      assert!(result.reports.windows(2).all(|w| w[1].train < w[0].train));
+    }
+
+    // The test costs are what the rules actually do to the test words.
+    let (_, test) = split(&dictionary, 3, 0).unwrap();
+    let last = results[0].reports.last().unwrap();
+    let rules = HLSubstitutionList {
+      substitutions: results[0].reports[1 ..].iter().map(|r| HLSubstitution::decode(r.rule.as_ref().unwrap()).unwrap()).collect()
+    };
+    let evaluation = evaluate(&WeightedObjective::default(), &rules, &test);
+    let total: f64 = test.words.iter().map(|w| w.frequency).sum();
+    assert!((weighted_word_cost(&evaluation.word_costs, &test) / total - last.test).abs() < 1e-9);
+
+    let means = mean_by_rule_count(&results);
+    assert_eq!(means.len(), 4);
+    assert!(means.iter().all(|&(_, _, _, folds)| folds == 3));
+
+    // The greedy search can't see homographs, so it won't pretend to.
+    let homographs = WeightedObjective { weights: ObjectiveWeights { homographs: 1.0, ..ObjectiveWeights::default() }, ..WeightedObjective::default() };
+    assert!(cross_validate(&GaussianSystem { scale: 4.0 }, &dictionary, 3, 3, None, &|s| s.objective = std::sync::Arc::new(homographs.clone())).is_err());
+  }
+}
@@ -24,4 +24,23 @@ pub mod genastarlike;
 pub mod simple_substitutions;
 pub mod readlex;
 pub mod ipa;
+pub mod rule_minimization;
+pub mod lookup_minimization;
+pub mod rule_files;
+pub mod equivalence_checking;
+pub mod rule_diff;
+pub mod rule_graph;
 
+pub mod beam_search;
+pub mod annealing;
+pub mod distance_metrics;
+pub mod objective;
+pub mod constraints;
+pub mod search_log;
+pub mod benchmark;
+pub mod calibration;
+pub mod empirical_astarlike22;
+pub mod ngram_index;
+pub mod alignment;
+pub mod holdout;
+pub mod pareto;
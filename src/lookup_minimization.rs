@@ -0,0 +1,242 @@
+
+/* HLSubstitutionList::low_level starts a new lookup whenever a rule conflicts
+ * with something already in the working lookup, so how many lookups come out
+ * depends on the order the rules are in. Fewer lookups means faster shaping and
+ * a smaller font.
+ *
+ * This looks for a reordering that compiles to fewer lookups, in two passes.
+ * The first moves whole rules, which reorders their anteriors and posteriors
+ * together. The second keeps the anteriors where they are and regroups only the
+ * posteriors, which low_level otherwise always applies last rule first.
+ * Reordering can of course change what the rules do, so a candidate order is
+ * only accepted once it has been replayed against the dictionary (plus some
+ * random words) and gives the same output as the original on every one of them.
+ */
+
+use crate::glyphs::{AugGlyph, Glyph, augment};
+use crate::equivalence_checking::rule_alphabet;
+use crate::high_level_substitutions2::HLSubstitutionList;
+use crate::substitutions2 as s2;
+use crate::dictionary::Dictionary;
+use rand::{SeedableRng, rngs::SmallRng, distributions::Uniform, prelude::Distribution};
+use rayon::prelude::*;
+
+pub struct LookupMinimizationResult {
+  pub rules: HLSubstitutionList,
+  /// Which order to apply the posteriors of `rules` in, as indices into them.
+  pub posterior_order: Vec<usize>,
+  pub initial_lookups: usize,
+  pub final_lookups: usize
+}
+
+impl LookupMinimizationResult {
+  /// What to ship: `rules` lowered with `posterior_order`.
+  pub fn low_level(&self) -> s2::SubstitutionList {
+    self.rules.low_level_with_posterior_order(&self.posterior_order)
+  }
+}
+
+pub fn num_lookups(rules: &HLSubstitutionList) -> usize {
+  rules.low_level().lookups.len()
+}
+
+/// Random words built from the letters the rules actually look at (plus the basic
+/// alphabet), so that they're likely to exercise interactions the dictionary misses.
+pub fn random_words(rules: &HLSubstitutionList, num_words: usize, max_len: usize, seed: u64) -> Vec<Vec<AugGlyph>> {
+  let mut alphabet = rule_alphabet(rules);
+  alphabet.extend("abcdefghijklmnopqrstuvwxyz-'".chars().filter_map(|c| Glyph::from_char(&c.to_string()).map(|(g, _)| g)));
+  let alphabet: Vec<Glyph> = alphabet.into_iter().collect();
+
+  let mut rng = SmallRng::seed_from_u64(seed);
+  let len_dist = Uniform::new_inclusive(1, max_len);
+  let glyph_dist = Uniform::new(0, alphabet.len());
+
+  (0 .. num_words).map(|_| {
+    (0 .. len_dist.sample(&mut rng)).map(|_| AugGlyph::Real(alphabet[glyph_dist.sample(&mut rng)])).collect()
+  }).collect()
+}
+
+/// The dictionary spellings followed by `num_random` random words.
+pub fn equivalence_words(rules: &HLSubstitutionList, dictionary: &Dictionary, num_random: usize, seed: u64) -> Vec<Vec<AugGlyph>> {
+  let mut words: Vec<Vec<AugGlyph>> = dictionary.words.iter().map(|w| augment(&w.spelling)).collect();
+  words.extend(random_words(rules, num_random, 8, seed));
+  words
+}
+
+/// Compiles both rule lists and replays `words` through them. Returns the first word
+/// where they disagree, or None if they agree on all of them.
+pub fn find_disagreement(a: &HLSubstitutionList, b: &HLSubstitutionList, words: &[Vec<AugGlyph>]) -> Option<Vec<AugGlyph>> {
+  find_low_level_disagreement(&a.low_level(), &b.low_level(), words)
+}
+
+/// find_disagreement for lists that are already lowered.
+pub fn find_low_level_disagreement(a_low_level: &s2::SubstitutionList, b_low_level: &s2::SubstitutionList, words: &[Vec<AugGlyph>]) -> Option<Vec<AugGlyph>> {
+  words.par_iter().find_first(|word| {
+    let mut by_a = (*word).clone();
+    s2::apply_all(&mut by_a, a_low_level);
+    let mut by_b = (*word).clone();
+    s2::apply_all(&mut by_b, b_low_level);
+    by_a != by_b
+  }).cloned()
+}
+
+pub fn minimize_lookups(rules: &HLSubstitutionList, words: &[Vec<AugGlyph>], debug: bool) -> LookupMinimizationResult {
+  let initial_lookups = num_lookups(rules);
+  let mut working = rules.clone();
+  let mut lookups = initial_lookups;
+
+  loop {
+    let mut any_change = false;
+
+    for from in 0 .. working.substitutions.len() {
+      for to in 0 .. working.substitutions.len() {
+        if to == from { continue; }
+
+        let mut substitutions = working.substitutions.clone();
+        let sub = substitutions.remove(from);
+        substitutions.insert(to, sub);
+        let candidate = HLSubstitutionList { substitutions };
+
+        if candidate.check_back_refs().is_err() { continue; }
+
+        let candidate_lookups = num_lookups(&candidate);
+        if candidate_lookups >= lookups { continue; }
+
+        // Always compare against the original list, so that small differences
+        // can't accumulate over several moves.
+        if let Some(word) = find_disagreement(rules, &candidate, words) {
+          if debug { println!("Moving {:?} from {} to {} changes {}", working.substitutions[from], from, to, crate::glyphs::aug_encode(&word)); }
+          continue;
+        }
+
+        if debug { println!("Moving {:?} from {} to {}: {} -> {} lookups", working.substitutions[from], from, to, lookups, candidate_lookups); }
+        working = candidate;
+        lookups = candidate_lookups;
+        any_change = true;
+        break;
+      }
+    }
+
+    if !any_change {
+      break;
+    }
+  }
+
+  let original = rules.low_level();
+  let mut posterior_order: Vec<usize> = (0 .. working.substitutions.len()).rev().collect();
+
+  loop {
+    let mut any_change = false;
+
+    for from in 0 .. posterior_order.len() {
+      for to in 0 .. posterior_order.len() {
+        if to == from { continue; }
+
+        let mut candidate = posterior_order.clone();
+        let i = candidate.remove(from);
+        candidate.insert(to, i);
+
+        let low_level = working.low_level_with_posterior_order(&candidate);
+        if low_level.lookups.len() >= lookups { continue; }
+
+        if let Some(word) = find_low_level_disagreement(&original, &low_level, words) {
+          if debug { println!("Moving the posterior of {:?} from {} to {} changes {}", working.substitutions[i], from, to, crate::glyphs::aug_encode(&word)); }
+          continue;
+        }
+
+        if debug { println!("Moving the posterior of {:?} from {} to {}: {} -> {} lookups", working.substitutions[i], from, to, lookups, low_level.lookups.len()); }
+        posterior_order = candidate;
+        lookups = low_level.lookups.len();
+        any_change = true;
+        break;
+      }
+    }
+
+    if !any_change {
+      break;
+    }
+  }
+
+  LookupMinimizationResult {
+    rules: working,
+    posterior_order,
+    initial_lookups,
+    final_lookups: lookups
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dictionary::from_words;
+
+  #[test]
+  fn reorder_reduces_lookups() {
+    let rules = HLSubstitutionList::decode("
+      a[b]→0→c
+      [a]→1→d
+      x[y]→2→z
+      [x]→3→w
+    ").unwrap();
+
+    let dictionary = from_words(&[("ab", "ab", 1.0), ("xy", "xy", 1.0), ("abxy", "abxy", 1.0), ("xyab", "xyab", 1.0), ("ba", "ba", 1.0)]);
+
+    let words = equivalence_words(&rules, &dictionary, 2000, 0);
+    let result = minimize_lookups(&rules, &words, false);
+
+    assert_eq!(result.initial_lookups, 4);
+    assert_eq!(result.final_lookups, 3);
+    assert_eq!(num_lookups(&result.rules), 3);
+    assert!(find_disagreement(&rules, &result.rules, &words).is_none());
+  }
+
+  #[test]
+  fn regroups_posteriors() {
+    // The rules can't move: [ab] has to come before [a], and the back references
+    // keep [e] before [ab] and [a] before [d]. Applied last rule first, the
+    // posteriors need three lookups, but {3} and {1} can go together and so can
+    // {2} and {0}.
+    let rules = HLSubstitutionList::decode("
+      [e]→0→i
+      [ab]→1→{0}
+      [a]→2→o
+      [d]→3→{2}
+    ").unwrap();
+    let words = random_words(&rules, 2000, 4, 2);
+    let result = minimize_lookups(&rules, &words, false);
+
+    assert_eq!(result.rules.substitutions, rules.substitutions);
+    assert_eq!((result.initial_lookups, result.final_lookups), (5, 4));
+    assert_eq!(result.final_lookups, result.low_level().lookups.len());
+    assert!(find_low_level_disagreement(&rules.low_level(), &result.low_level(), &words).is_none());
+  }
+
+  #[test]
+  fn keeps_order_when_it_matters() {
+    let rules = HLSubstitutionList::decode("
+      [a]→0→x
+      b[a]→1→y
+      [b]→2→z
+    ").unwrap();
+
+    let words = random_words(&rules, 2000, 4, 1);
+    let result = minimize_lookups(&rules, &words, false);
+
+    assert!(find_disagreement(&rules, &result.rules, &words).is_none());
+  }
+
+  #[test]
+  fn disagreement_is_found() {
+    let a = HLSubstitutionList::decode("
+      [a]→0→x
+      [ab]→1→y
+    ").unwrap();
+    let b = HLSubstitutionList::decode("
+      [ab]→1→y
+      [a]→0→x
+    ").unwrap();
+
+    let words = vec![crate::glyphs::aug_decode("ab")];
+    assert_eq!(find_disagreement(&a, &b, &words), Some(crate::glyphs::aug_decode("ab")));
+  }
+}
@@ -0,0 +1,163 @@
+
+/* An inverted index from glyph n-grams to the words that contain them.
+ *
+ * An anterior only ever matches where its at_key appears verbatim in a word's
+ * transformed spelling, so the words containing the key are the only ones a
+ * candidate rule can change. Scoring a candidate exactly then means visiting
+ * those words instead of the whole dictionary, which for most keys is a small
+ * fraction of it.
+ *
+ * Every n-gram up to max_n glyphs long is indexed, synthetic glyphs included,
+ * since keys of later rules are often made of earlier rules' mids. Longer keys
+ * are looked up by intersecting the postings of their max_n-grams, which can
+ * let through a few words that have all the pieces but not the whole key.
+ *
+ * IterativeSystem keeps one up to date as it applies rules.
+ */
+
+use crate::astarlike2::AugDictionary;
+use crate::glyphs::AugGlyph;
+use crate::high_level_substitutions2::HLSubstitution;
+use std::collections::{BTreeSet, HashMap};
+
+pub const DEFAULT_MAX_N: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NgramIndex {
+  max_n: usize,
+  words: usize,
+  postings: HashMap<Vec<AugGlyph>, BTreeSet<usize>>
+}
+
+impl NgramIndex {
+  pub fn build(dictionary: &AugDictionary, max_n: usize) -> NgramIndex {
+    assert!(max_n > 0, "max_n must be at least 1");
+    let mut res = NgramIndex { max_n, words: dictionary.words.len(), postings: HashMap::new() };
+    for (i, w) in dictionary.words.iter().enumerate() {
+      res.add(i, &w.transformed_spelling);
+    }
+    res
+  }
+
+  fn ngrams(max_n: usize, spelling: &[AugGlyph]) -> impl Iterator<Item = &[AugGlyph]> {
+    (1 ..= max_n).flat_map(move |n| spelling.windows(n))
+  }
+
+  fn add(&mut self, word_i: usize, spelling: &[AugGlyph]) {
+    for gram in Self::ngrams(self.max_n, spelling) {
+      self.postings.entry(gram.to_vec()).or_default().insert(word_i);
+    }
+  }
+
+  fn remove(&mut self, word_i: usize, spelling: &[AugGlyph]) {
+    for gram in Self::ngrams(self.max_n, spelling) {
+      if let Some(words) = self.postings.get_mut(gram) {
+        words.remove(&word_i);
+        if words.is_empty() {
+          self.postings.remove(gram);
+        }
+      }
+    }
+  }
+
+  /// Has to be called whenever a word's transformed spelling changes.
+  pub fn update(&mut self, word_i: usize, old: &[AugGlyph], new: &[AugGlyph]) {
+    self.remove(word_i, old);
+    self.add(word_i, new);
+  }
+
+  /// Every word whose spelling contains `key`, in dictionary order. Keys longer
+  /// than max_n can also bring in words that only contain all of their n-grams.
+  pub fn containing(&self, key: &[AugGlyph]) -> Vec<usize> {
+    if key.is_empty() {
+      return (0 .. self.words).collect();
+    }
+    let mut grams: Vec<&BTreeSet<usize>> = vec![];
+    for gram in key.windows(self.max_n.min(key.len())) {
+      match self.postings.get(gram) {
+        Some(words) => grams.push(words),
+        None => return vec![]
+      }
+    }
+    // Starting from the rarest keeps the intersection small.
+    grams.sort_by_key(|words| words.len());
+    grams[0].iter().copied().filter(|i| grams[1 ..].iter().all(|words| words.contains(i))).collect()
+  }
+
+  /// The only words applying `sub` could change.
+  pub fn affected_by(&self, sub: &HLSubstitution) -> Vec<usize> {
+    self.containing(&sub.anterior.at_key)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::astarlike2::{IterativeSystem, Outcome};
+  use crate::gaussian_astarlike22::GaussianSystem;
+  use crate::high_level_substitutions2::HLSubstitutionList;
+
+  fn brute_force(dictionary: &AugDictionary, key: &[AugGlyph]) -> Vec<usize> {
+    dictionary.words.iter().enumerate().filter(|(_, w)| w.transformed_spelling.windows(key.len()).any(|g| g == key)).map(|(i, _)| i).collect()
+  }
+
+  #[test]
+  fn stays_up_to_date() {
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(300);
+    let mut system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+    for _ in 0 .. 4 {
+      let Outcome::FoundImprovement(..) = system.find_next_rule(&GaussianSystem { scale: 4.0 }, false) else { panic!("Failed to find improvement") };
+    }
+    assert_eq!(system.index, NgramIndex::build(&system.dictionary, DEFAULT_MAX_N));
+
+    for w in system.dictionary.words.iter().take(50) {
+      for len in 1 ..= w.transformed_spelling.len().min(5) {
+        let key = &w.transformed_spelling[.. len];
+        let found = system.index.containing(key);
+        let expected = brute_force(&system.dictionary, key);
+        if len <= DEFAULT_MAX_N {
+          assert_eq!(found, expected);
+        }
+        else {
+          assert!(expected.iter().all(|i| found.contains(i)));
+        }
+      }
+    }
+    // Keys made of the new rules' synthetic glyphs are indexed too.
+    for sub in &system.rules.substitutions {
+      let key = vec![AugGlyph::Synthetic(sub.mid)];
+      assert_eq!(system.index.containing(&key), brute_force(&system.dictionary, &key));
+    }
+  }
+
+  #[test]
+  fn indexed_exact_scores_match() {
+    use crate::astarlike2::{HLEditList, HLEditSystem};
+    use crate::constraints::Constraints;
+    use crate::distance_metrics::Levenshtein;
+    use crate::genastarlike;
+    use crate::objective::WeightedObjective;
+
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(300);
+    let mut system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+    for _ in 0 .. 2 {
+      system.find_next_rule(&GaussianSystem { scale: 4.0 }, false);
+    }
+
+    let objective = WeightedObjective::default();
+    let guard = Constraints::default().guard(&system.rules);
+    let mut edit_sys = HLEditSystem { mid: system.rules.next_open_mid(), class_contexts: false, objective: &objective, guard: &guard, index: None, alignment: None };
+    let (plain, edit_list) = HLEditList::from_state(&system.dictionary, &system.rules);
+    let r = genastarlike::init_ref_data(&GaussianSystem { scale: 4.0 }, &edit_sys, &plain, &edit_list);
+
+    let samples = crate::calibration::sample_edits(&system.dictionary, &system.rules, &Levenshtein, 20, 50, 0);
+    let full: Vec<f64> = samples.edits.iter().map(|e| genastarlike::exact_score(&edit_sys, &r, &e.edit, 0.0)).collect();
+    edit_sys.index = Some(&system.index);
+    for (e, full) in samples.edits.iter().zip(full) {
+      assert!(system.index.affected_by(&e.edit).len() < plain.words.len());
+      assert!((genastarlike::exact_score(&edit_sys, &r, &e.edit, 0.0) - full).abs() < 1e-9);
+    }
+  }
+}
@@ -0,0 +1,287 @@
+
+/* What the rule searches minimize.
+ *
+ * An Objective splits into three kinds of term, because the searches can only
+ * handle some kinds in some places:
+ *
+ * - Word costs are summed over the dictionary, weighted by frequency. They're
+ *   whole numbers per word, so the astarlike searches estimate them exactly the
+ *   way they always estimated distance.
+ * - Rule costs depend only on the rule and the rules before it. The searches add
+ *   them to an edit's best and worst possible alike, the way astarlike adds
+ *   edit_size_cost.
+ * - Dictionary costs depend on all the words at once (homograph collisions, say)
+ *   so they can't be estimated a word at a time. Only exact scoring, as done by
+ *   beam search and annealing, sees them; the astarlike searches refuse an
+ *   objective that has any rather than quietly optimize something else.
+ *
+ * WeightedObjective::default() is what the searches always used: Levenshtein
+ * distance plus 0.001 per glyph of rule.
+ */
+
+use crate::glyphs::{AugGlyph, augment};
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList};
+use crate::distance_metrics::{DistanceMetric, Levenshtein, load_metric};
+use crate::dictionary::Dictionary;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use rayon::prelude::*;
+
+pub trait Objective: Send + Sync {
+  /// What a word whose rules produce `output` contributes, before weighting by
+  /// its frequency.
+  fn word_cost(&self, output: &[AugGlyph], pronunciation: &[AugGlyph], spelling: &[AugGlyph]) -> u32;
+
+  /// What adding `sub` to the end of `rules` costs, whatever the words.
+  fn rule_cost(&self, rules: &HLSubstitutionList, sub: &HLSubstitution) -> f64;
+
+  /// Terms over the whole dictionary at once. `outputs` is in dictionary order.
+  fn dictionary_cost(&self, _outputs: &[Vec<AugGlyph>], _dictionary: &Dictionary) -> f64 {
+    0.0
+  }
+
+  /// Whether dictionary_cost can be anything but 0.
+  fn has_dictionary_cost(&self) -> bool {
+    false
+  }
+
+  /// What getting one glyph wrong typically adds to a word cost, so that the
+  /// Gaussian estimators can size their sigmas to match.
+  fn word_unit(&self) -> f64 {
+    1.0
+  }
+}
+
+/// How many key glyphs and edge conditions a rule has.
+pub fn rule_size(sub: &HLSubstitution) -> usize {
+  let a = &sub.anterior;
+  a.pre_key.len() + a.at_key.len() + a.post_key.len() + (a.at_start as usize) + (a.at_end as usize)
+}
+
+pub fn synthetic_count(sub: &HLSubstitution) -> usize {
+  sub.anterior.glyphs().iter().chain(sub.posterior.content.iter()).filter(|g| matches!(g, AugGlyph::Synthetic(_))).count()
+}
+
+/// The total frequency of words that read the same as some more frequent word
+/// but are pronounced differently, beyond what the spellings already had. Words
+/// spelled the same collide whatever the rules do, so they aren't counted. Ties
+/// go to whichever word comes first.
+pub fn homograph_collisions(outputs: &[Vec<AugGlyph>], dictionary: &Dictionary) -> f64 {
+  let spellings: Vec<Vec<AugGlyph>> = dictionary.words.iter().map(|w| augment(&w.spelling)).collect();
+  collisions(outputs, dictionary) - collisions(&spellings, dictionary)
+}
+
+fn collisions(outputs: &[Vec<AugGlyph>], dictionary: &Dictionary) -> f64 {
+  let mut groups: HashMap<&Vec<AugGlyph>, Vec<usize>> = HashMap::new();
+  for (i, output) in outputs.iter().enumerate() {
+    groups.entry(output).or_default().push(i);
+  }
+
+  groups.values().filter(|g| g.len() > 1).map(|g| {
+    let words = &dictionary.words;
+    let &main = g.iter().reduce(|a, b| if words[*b].frequency > words[*a].frequency { b } else { a }).unwrap();
+    g.iter().filter(|&&i| words[i].pronunciation != words[main].pronunciation).map(|&i| words[i].frequency).sum::<f64>()
+  }).sum()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObjectiveWeights {
+  /// Per unit of distance from the pronunciation.
+  pub distance: u32,
+  /// Per unit of distance from the original spelling.
+  pub divergence: u32,
+  /// Per key glyph and edge condition.
+  pub complexity: f64,
+  /// Per synthetic glyph a rule mentions.
+  pub synthetic: f64,
+  /// Per lookup a rule adds to low_level(). Computing this means lowering the
+  /// whole list for every candidate, so it's slow.
+  pub lookups: f64,
+  /// Per unit of homograph_collisions.
+  pub homographs: f64
+}
+
+impl Default for ObjectiveWeights {
+  fn default() -> ObjectiveWeights {
+    ObjectiveWeights {
+      distance: 1,
+      divergence: 0,
+      complexity: 0.001,
+      synthetic: 0.0,
+      lookups: 0.0,
+      homographs: 0.0
+    }
+  }
+}
+
+impl ObjectiveWeights {
+  /// Missing fields keep their defaults.
+  pub fn load(path: &str) -> Result<ObjectiveWeights, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))
+  }
+}
+
+#[derive(Clone)]
+pub struct WeightedObjective {
+  pub weights: ObjectiveWeights,
+  /// Used for both distance and divergence.
+  pub metric: Arc<dyn DistanceMetric>
+}
+
+impl Default for WeightedObjective {
+  fn default() -> WeightedObjective {
+    WeightedObjective {
+      weights: ObjectiveWeights::default(),
+      metric: Arc::new(Levenshtein)
+    }
+  }
+}
+
+impl Objective for WeightedObjective {
+  fn word_cost(&self, output: &[AugGlyph], pronunciation: &[AugGlyph], spelling: &[AugGlyph]) -> u32 {
+    let w = &self.weights;
+    let mut res = 0;
+    if w.distance != 0 {
+      res += w.distance * self.metric.distance(output, pronunciation);
+    }
+    if w.divergence != 0 {
+      res += w.divergence * self.metric.distance(output, spelling);
+    }
+    res
+  }
+
+  fn rule_cost(&self, rules: &HLSubstitutionList, sub: &HLSubstitution) -> f64 {
+    let w = &self.weights;
+    let mut res = w.complexity * rule_size(sub) as f64 + w.synthetic * synthetic_count(sub) as f64;
+    if w.lookups != 0.0 {
+      let mut with = rules.clone();
+      with.substitutions.push(sub.clone());
+      let added = with.low_level().lookups.len() as f64 - rules.low_level().lookups.len() as f64;
+      res += w.lookups * added;
+    }
+    res
+  }
+
+  fn dictionary_cost(&self, outputs: &[Vec<AugGlyph>], dictionary: &Dictionary) -> f64 {
+    if self.weights.homographs == 0.0 {
+      return 0.0;
+    }
+    self.weights.homographs * homograph_collisions(outputs, dictionary)
+  }
+
+  fn has_dictionary_cost(&self) -> bool {
+    self.weights.homographs != 0.0
+  }
+
+  /// One wrong glyph can cost both distance and divergence, so this errs wide.
+  fn word_unit(&self) -> f64 {
+    let w = &self.weights;
+    ((w.distance + w.divergence).max(1) * self.metric.unit()) as f64
+  }
+}
+
+/// The default weights and Levenshtein, unless either file is given.
+pub fn load_objective(costs: Option<&str>, weights: Option<&str>) -> Result<Arc<dyn Objective>, String> {
+  Ok(Arc::new(WeightedObjective {
+    weights: match weights {
+      Some(path) => ObjectiveWeights::load(path)?,
+      None => ObjectiveWeights::default()
+    },
+    metric: load_metric(costs)?
+  }))
+}
+
+/// Everything about a rule list that exact scoring needs.
+#[derive(Clone)]
+pub struct Evaluation {
+  pub outputs: Vec<Vec<AugGlyph>>,
+  pub word_costs: Vec<u32>,
+  pub rule_cost: f64,
+  pub score: f64
+}
+
+pub fn weighted_word_cost(word_costs: &[u32], dictionary: &Dictionary) -> f64 {
+  dictionary.words.iter().zip(word_costs).map(|(w, &c)| w.frequency * (c as f64)).sum()
+}
+
+/// The rule costs of a whole list, each rule costed against the ones before it.
+pub fn rules_cost(objective: &dyn Objective, rules: &HLSubstitutionList) -> f64 {
+  let mut prior = HLSubstitutionList { substitutions: vec![] };
+  let mut res = 0.0;
+  for sub in &rules.substitutions {
+    res += objective.rule_cost(&prior, sub);
+    prior.substitutions.push(sub.clone());
+  }
+  res
+}
+
+/// Scores a list exactly, with every term.
+pub fn evaluate(objective: &dyn Objective, rules: &HLSubstitutionList, dictionary: &Dictionary) -> Evaluation {
+  let (outputs, word_costs): (Vec<Vec<AugGlyph>>, Vec<u32>) = dictionary.words.par_iter().map(|w| {
+    let spelling = augment(&w.spelling);
+    let output = rules.apply_copied_always(&spelling);
+    let cost = objective.word_cost(&output, &augment(&w.pronunciation), &spelling);
+    (output, cost)
+  }).unzip();
+
+  let rule_cost = rules_cost(objective, rules);
+  let score = weighted_word_cost(&word_costs, dictionary) + rule_cost + objective.dictionary_cost(&outputs, dictionary);
+  Evaluation { outputs, word_costs, rule_cost, score }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dictionary::from_words as dictionary;
+  use crate::glyphs::aug_decode;
+
+  #[test]
+  fn default_is_distance_and_size() {
+    let dictionary = dictionary(&[("cat", "kæt", 2.0), ("cot", "kɔt", 1.0)]);
+    let rules = HLSubstitutionList::decode("
+      [c]→0→k
+      ^[{0}a]→1→{0}æ
+    ").unwrap();
+
+    let e = evaluate(&WeightedObjective::default(), &rules, &dictionary);
+    assert_eq!(e.word_costs, vec![0, 1]);
+    assert!((e.score - (1.0 + 0.001 * (1 + 3) as f64)).abs() < 1e-9);
+  }
+
+  #[test]
+  fn other_terms() {
+    let dictionary = dictionary(&[("read", "rid", 3.0), ("read", "rεd", 1.0), ("rid", "rɪd", 1.0)]);
+    let rules = HLSubstitutionList::decode("[ea]→0→i").unwrap();
+    let objective = WeightedObjective {
+      weights: ObjectiveWeights {
+        distance: 0,
+        divergence: 2,
+        complexity: 0.0,
+        synthetic: 0.5,
+        lookups: 1.0,
+        homographs: 10.0
+      },
+      metric: Arc::new(Levenshtein)
+    };
+
+    // The reads collide with or without rules, so only rid pays for now
+    // reading the same as them.
+    let e = evaluate(&objective, &rules, &dictionary);
+    assert_eq!(e.word_costs, vec![4, 4, 0]);
+    assert_eq!(homograph_collisions(&e.outputs, &dictionary), 1.0);
+    assert!(objective.has_dictionary_cost());
+    let unchanged = evaluate(&objective, &HLSubstitutionList { substitutions: vec![] }, &dictionary);
+    assert_eq!(homograph_collisions(&unchanged.outputs, &dictionary), 0.0);
+    assert_eq!(e.score, 3.0 * 4.0 + 4.0 + 2.0 + 10.0);
+
+    // A rule that refers back to [ea] mentions two synthetic glyphs, and needs new
+    // lookups on both the anterior and the posterior side.
+    let sub = HLSubstitution::decode("r[{0}]→1→{0}").unwrap();
+    assert_eq!(objective.rule_cost(&rules, &sub), 0.5 * 2.0 + 2.0);
+
+    assert_eq!(objective.word_cost(&aug_decode("rid"), &aug_decode("rid"), &aug_decode("read")), 4);
+  }
+}
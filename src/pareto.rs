@@ -0,0 +1,200 @@
+
+/* What each prefix of a rule list costs and buys.
+ *
+ * After each rule of a search, or of a saved rule list being replayed, this
+ * records the mean word cost along with what the rules so far would cost to
+ * ship: how many rules, how many lookups they compile to, how many synthetic
+ * glyphs the font needs, and how big the GSUB table gets. Later rules refer to
+ * earlier rules' glyphs, so only prefixes are real candidates; the Pareto front
+ * along one of those measures shows which prefixes are worth considering, and
+ * best_within picks one for a budget.
+ *
+ * Compiling with fonttools gives the real GSUB size but needs fonttools and a
+ * font; estimate_gsub_size adds up the tables feaLib would write instead.
+ */
+
+use crate::astarlike2::IterativeSystem;
+use crate::dictionary::Dictionary;
+use crate::high_level_substitutions2::HLSubstitutionList;
+use crate::objective::Objective;
+use crate::substitutions2 as s2;
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Axis {
+  Rules,
+  Lookups,
+  Synthetics,
+  GsubBytes
+}
+
+pub const AXES: [Axis; 4] = [Axis::Rules, Axis::Lookups, Axis::Synthetics, Axis::GsubBytes];
+
+impl Axis {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Axis::Rules => "rules",
+      Axis::Lookups => "lookups",
+      Axis::Synthetics => "synthetics",
+      Axis::GsubBytes => "gsub_bytes"
+    }
+  }
+
+  pub fn parse(s: &str) -> Result<Axis, String> {
+    AXES.iter().find(|a| a.name() == s).copied().ok_or_else(|| format!("unknown axis {:?}", s))
+  }
+
+  pub fn of(&self, p: &Point) -> usize {
+    match self {
+      Axis::Rules => p.rules,
+      Axis::Lookups => p.lookups,
+      Axis::Synthetics => p.synthetics,
+      Axis::GsubBytes => p.gsub_bytes
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+  pub rules: usize,
+  /// The one just added, encoded; None before the first.
+  pub rule: Option<String>,
+  pub lookups: usize,
+  /// Distinct synthetic glyphs the rules introduce.
+  pub synthetics: usize,
+  pub gsub_bytes: usize,
+  /// As IterativeSystem::mean_word_cost.
+  pub cost: f64
+}
+
+/// The point for `system`'s current rules. `gsub_size` sizes the compiled rules.
+pub fn measure(system: &IterativeSystem, gsub_size: &dyn Fn(&s2::SubstitutionList) -> usize) -> Point {
+  let rules = &system.rules.substitutions;
+  let low_level = system.rules.low_level();
+  Point {
+    rules: rules.len(),
+    rule: rules.last().map(|s| s.encode()),
+    lookups: low_level.lookups.len(),
+    synthetics: rules.iter().map(|s| s.mid).collect::<HashSet<u32>>().len(),
+    gsub_bytes: gsub_size(&low_level),
+    cost: system.mean_word_cost()
+  }
+}
+
+/// A point for no rules and for each prefix of `rules`.
+pub fn replay(dictionary: &Dictionary, rules: &HLSubstitutionList, objective: Arc<dyn Objective>, gsub_size: &dyn Fn(&s2::SubstitutionList) -> usize) -> Vec<Point> {
+  let mut system = IterativeSystem::setup(dictionary, HLSubstitutionList { substitutions: vec![] });
+  system.objective = objective;
+  let mut res = vec![measure(&system, gsub_size)];
+  for sub in &rules.substitutions {
+    system.push_rule(sub.clone());
+    res.push(measure(&system, gsub_size));
+  }
+  res
+}
+
+/// The points no other point beats on both `axis` and cost, cheapest first.
+pub fn front(points: &[Point], axis: Axis) -> Vec<&Point> {
+  let mut sorted: Vec<&Point> = points.iter().collect();
+  sorted.sort_by(|a, b| axis.of(a).cmp(&axis.of(b)).then(a.cost.total_cmp(&b.cost)).then(a.rules.cmp(&b.rules)));
+  let mut res: Vec<&Point> = vec![];
+  for p in sorted {
+    if res.last().is_none_or(|best| p.cost < best.cost) {
+      res.push(p);
+    }
+  }
+  res
+}
+
+/// The lowest-cost point with at most `budget` on `axis`.
+pub fn best_within(points: &[Point], axis: Axis, budget: usize) -> Option<&Point> {
+  front(points, axis).into_iter().take_while(|p| axis.of(p) <= budget).last()
+}
+
+fn key_elem_glyphs(e: &s2::KeyElem) -> usize {
+  match e {
+    s2::KeyElem::Glyph(_) => 1,
+    s2::KeyElem::Class(gs) => gs.len(),
+    s2::KeyElem::AnyLetter => 26
+  }
+}
+
+fn coverage_bytes(glyphs: usize) -> usize {
+  4 + 2 * glyphs
+}
+
+/// Roughly what feaLib writes: a chained context subtable with a coverage table
+/// per position for every line, plus a lookup for each line's replacement.
+pub fn estimate_gsub_size(slist: &s2::SubstitutionList) -> usize {
+  // Header, and empty script and feature lists.
+  let mut res = 10 + 30;
+  let mut lookups = 0;
+  for lookup in &slist.lookups {
+    lookups += 1;
+    res += 6 + 2 * lookup.substitutions.len();
+    for sub in &lookup.substitutions {
+      let positions = sub.pre_key.len() + sub.at_key.len() + sub.post_key.len();
+      res += 10 + 2 * positions;
+      res += sub.pre_key.iter().chain(&sub.post_key).map(|e| coverage_bytes(key_elem_glyphs(e))).sum::<usize>();
+      res += sub.at_key.len() * coverage_bytes(1);
+      if let s2::SubContent::Sub(content) = &sub.sub_content {
+        res += 4;
+        lookups += 1;
+        res += 8 + coverage_bytes(1) + match (sub.at_key.len(), content.len()) {
+          (1, 1) => 2,
+          (1, n) => 4 + 2 * n,
+          (k, _) => 8 + 2 * k
+        };
+      }
+    }
+  }
+  res + 2 + 2 * lookups
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::astarlike2::Outcome;
+  use crate::gaussian_astarlike22::GaussianSystem;
+  use crate::objective::WeightedObjective;
+
+  fn point(rules: usize, lookups: usize, cost: f64) -> Point {
+    Point { rules, rule: None, lookups, synthetics: rules, gsub_bytes: 100 * lookups, cost }
+  }
+
+  #[test]
+  fn picks_from_the_front() {
+    let points = vec![point(0, 1, 3.0), point(1, 2, 2.0), point(2, 2, 1.5), point(3, 4, 1.6), point(4, 4, 1.0)];
+    let rules: Vec<usize> = front(&points, Axis::Rules).iter().map(|p| p.rules).collect();
+    assert_eq!(rules, vec![0, 1, 2, 4]);
+    // Adding the second rule was free in lookups.
+    let rules: Vec<usize> = front(&points, Axis::Lookups).iter().map(|p| p.rules).collect();
+    assert_eq!(rules, vec![0, 2, 4]);
+
+    assert_eq!(best_within(&points, Axis::Lookups, 3).unwrap().rules, 2);
+    assert_eq!(best_within(&points, Axis::Rules, 3).unwrap().rules, 2);
+    assert_eq!(best_within(&points, Axis::GsubBytes, 1000).unwrap().rules, 4);
+    assert!(best_within(&points, Axis::Lookups, 0).is_none());
+  }
+
+  #[test]
+  fn replays_a_search() {
+    let mut dictionary = crate::dictionary::load_dictionary().unwrap();
+    dictionary.words.truncate(200);
+    let mut system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+    let mut searched = vec![measure(&system, &estimate_gsub_size)];
+    for _ in 0 .. 4 {
+      let Outcome::FoundImprovement(..) = system.find_next_rule(&GaussianSystem { scale: 4.0 }, false) else { panic!("Failed to find improvement") };
+      searched.push(measure(&system, &estimate_gsub_size));
+    }
+
+    let replayed = replay(&dictionary, &system.rules, Arc::new(WeightedObjective::default()), &estimate_gsub_size);
+    assert_eq!(replayed, searched);
+    assert_eq!(replayed.iter().map(|p| p.synthetics).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+    assert!(replayed.windows(2).all(|w| w[1].cost < w[0].cost && w[1].gsub_bytes > w[0].gsub_bytes));
+    assert_eq!(front(&replayed, Axis::Rules).len(), 5);
+  }
+}
@@ -0,0 +1,267 @@
+
+/* Compares two HLSubstitutionLists, e.g. the output of two search runs.
+ *
+ * Mids are just names, and two runs that find the same rules will usually
+ * number them differently, so rules are matched up by what they mean: every
+ * {n} is replaced by the rule that produces n, recursively. Matched rules whose
+ * relative order changed are reported as moved.
+ *
+ * The word report replays the dictionary through both lists and lists every
+ * word whose output changed, biggest frequency-weighted distance change first.
+ */
+
+use crate::glyphs::{Glyph, augment, strip_aug};
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList};
+use crate::dictionary::Dictionary;
+use crate::distance_metrics::DistanceMetric;
+use regex::Regex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use float_ord::FloatOrd;
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleChange {
+  Added { sub: HLSubstitution, index: usize },
+  Removed { sub: HLSubstitution, index: usize },
+  Moved { sub: HLSubstitution, from: usize, to: usize }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordImpact {
+  pub spelling: Vec<Glyph>,
+  pub pronunciation: Vec<Glyph>,
+  pub before: Vec<Glyph>,
+  pub after: Vec<Glyph>,
+  pub distance_before: u32,
+  pub distance_after: u32,
+  pub frequency: f64
+}
+
+impl WordImpact {
+  /// Negative is an improvement.
+  pub fn weighted_change(&self) -> f64 {
+    self.frequency * (self.distance_after as f64 - self.distance_before as f64)
+  }
+}
+
+pub struct RuleSetDiff {
+  pub changes: Vec<RuleChange>,
+  pub impacts: Vec<WordImpact>,
+  pub score_before: f64,
+  pub score_after: f64
+}
+
+/// A mid-independent description of each rule, in list order.
+pub fn canonical_forms(rules: &HLSubstitutionList) -> Vec<String> {
+  let synthetic = Regex::new(r"\{(\d+)\}").unwrap();
+  let mut by_mid: HashMap<u32, String> = HashMap::new();
+
+  rules.substitutions.iter().map(|s| {
+    // Back-refs always point at earlier rules, so by_mid already has them.
+    let expand = |text: &str| synthetic.replace_all(text, |c: &regex::Captures| {
+      let mid: u32 = c[1].parse().unwrap();
+      format!("⟨{}⟩", by_mid.get(&mid).cloned().unwrap_or_else(|| format!("?{}", mid)))
+    }).into_owned();
+    let form = format!("{}→{}", expand(&s.anterior.encode()), expand(&crate::glyphs::aug_encode(&s.posterior.content)));
+    by_mid.insert(s.mid, form.clone());
+    form
+  }).collect()
+}
+
+/// Indices (into `a` and `b`) of a longest common subsequence.
+fn longest_common_subsequence(a: &[&String], b: &[&String]) -> Vec<(usize, usize)> {
+  let mut table = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+  for i in (0 .. a.len()).rev() {
+    for j in (0 .. b.len()).rev() {
+      table[i][j] = if a[i] == b[j] { table[i+1][j+1] + 1 } else { table[i+1][j].max(table[i][j+1]) };
+    }
+  }
+
+  let mut res = vec![];
+  let (mut i, mut j) = (0, 0);
+  while i < a.len() && j < b.len() {
+    if a[i] == b[j] {
+      res.push((i, j));
+      i += 1;
+      j += 1;
+    }
+    else if table[i+1][j] >= table[i][j+1] {
+      i += 1;
+    }
+    else {
+      j += 1;
+    }
+  }
+  res
+}
+
+pub fn rule_changes(old: &HLSubstitutionList, new: &HLSubstitutionList) -> Vec<RuleChange> {
+  let old_forms = canonical_forms(old);
+  let new_forms = canonical_forms(new);
+
+  let old_set: HashSet<&String> = old_forms.iter().collect();
+  let new_set: HashSet<&String> = new_forms.iter().collect();
+
+  let mut removed: Vec<usize> = (0 .. old_forms.len()).filter(|&i| !new_set.contains(&old_forms[i])).collect();
+  let mut added: Vec<usize> = (0 .. new_forms.len()).filter(|&i| !old_set.contains(&new_forms[i])).collect();
+
+  // Of the rules in both, the ones outside a longest common subsequence have moved.
+  let old_common: Vec<(usize, &String)> = old_forms.iter().enumerate().filter(|(_, f)| new_set.contains(f)).collect();
+  let new_common: Vec<(usize, &String)> = new_forms.iter().enumerate().filter(|(_, f)| old_set.contains(f)).collect();
+  let pairs = longest_common_subsequence(
+    &old_common.iter().map(|(_, f)| *f).collect::<Vec<_>>(),
+    &new_common.iter().map(|(_, f)| *f).collect::<Vec<_>>()
+  );
+  let kept_old: HashSet<usize> = pairs.iter().map(|&(i, _)| i).collect();
+  let kept_new: HashSet<usize> = pairs.iter().map(|&(_, j)| j).collect();
+
+  // A repeated rule can be both kept and moved, so a moved rule is paired with an
+  // occurrence the subsequence didn't use. Occurrences left over on either side
+  // are plain additions or removals.
+  let mut unpaired_old: HashMap<&String, VecDeque<usize>> = HashMap::new();
+  for (i, &(from, form)) in old_common.iter().enumerate() {
+    if !kept_old.contains(&i) {
+      unpaired_old.entry(form).or_default().push_back(from);
+    }
+  }
+  let mut moved = vec![];
+  for (j, &(to, form)) in new_common.iter().enumerate() {
+    if !kept_new.contains(&j) {
+      match unpaired_old.get_mut(form).and_then(|froms| froms.pop_front()) {
+        Some(from) => moved.push(RuleChange::Moved { sub: new.substitutions[to].clone(), from, to }),
+        None => added.push(to)
+      }
+    }
+  }
+  removed.extend(unpaired_old.into_values().flatten());
+  removed.sort();
+  added.sort();
+
+  removed.into_iter().map(|index| RuleChange::Removed { sub: old.substitutions[index].clone(), index })
+    .chain(added.into_iter().map(|index| RuleChange::Added { sub: new.substitutions[index].clone(), index }))
+    .chain(moved)
+    .collect()
+}
+
+fn transform(rules: &HLSubstitutionList, spelling: &Vec<Glyph>) -> Vec<Glyph> {
+  strip_aug(&rules.apply_copied_always(&augment(spelling)))
+}
+
+pub fn word_impacts(old: &HLSubstitutionList, new: &HLSubstitutionList, dictionary: &Dictionary, metric: &dyn DistanceMetric) -> Vec<WordImpact> {
+  let mut impacts: Vec<WordImpact> = dictionary.words.par_iter().filter_map(|w| {
+    let before = transform(old, &w.spelling);
+    let after = transform(new, &w.spelling);
+    if before == after {
+      return None;
+    }
+    Some(WordImpact {
+      distance_before: metric.distanceg(&before, &w.pronunciation),
+      distance_after: metric.distanceg(&after, &w.pronunciation),
+      spelling: w.spelling.clone(),
+      pronunciation: w.pronunciation.clone(),
+      before,
+      after,
+      frequency: w.frequency
+    })
+  }).collect();
+
+  impacts.sort_by_key(|i| FloatOrd(-i.weighted_change().abs()));
+  impacts
+}
+
+pub fn diff_rule_sets(old: &HLSubstitutionList, new: &HLSubstitutionList, dictionary: &Dictionary, metric: &dyn DistanceMetric) -> RuleSetDiff {
+  RuleSetDiff {
+    changes: rule_changes(old, new),
+    impacts: word_impacts(old, new, dictionary, metric),
+    score_before: crate::consensus_finding::score_hl2_slist(old, dictionary, metric),
+    score_after: crate::consensus_finding::score_hl2_slist(new, dictionary, metric)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dictionary::from_words;
+  use crate::distance_metrics::Levenshtein;
+  use crate::glyphs::decode;
+
+  #[test]
+  fn renumbered_rules_match() {
+    let old = HLSubstitutionList::decode("
+      [c]→0→k
+      [a]→1→æ
+      [{0}h]→2→ʧ
+    ").unwrap();
+    let new = HLSubstitutionList::decode("
+      [a]→0→æ
+      [c]→1→k
+      [{1}h]→2→ʧ
+    ").unwrap();
+
+    let changes = rule_changes(&old, &new);
+    assert_eq!(changes.len(), 1);
+    assert!(matches!(&changes[0], RuleChange::Moved { from: 0, to: 1, .. } | RuleChange::Moved { from: 1, to: 0, .. }));
+  }
+
+  #[test]
+  fn repeated_rule_moves_from_its_own_index() {
+    let old = HLSubstitutionList::decode("
+      [a]→0→æ
+      [b]→1→b
+      [c]→2→k
+      [a]→3→æ
+    ").unwrap();
+    let new = HLSubstitutionList::decode("
+      [a]→0→æ
+      [a]→1→æ
+      [b]→2→b
+      [c]→3→k
+    ").unwrap();
+
+    // The first [a] stays put, so the one that moved is the second.
+    assert_eq!(rule_changes(&old, &new), vec![RuleChange::Moved { sub: new.substitutions[1].clone(), from: 3, to: 1 }]);
+  }
+
+  #[test]
+  fn added_and_removed() {
+    let old = HLSubstitutionList::decode("
+      [c]→0→k
+      [{0}h]→1→ʧ
+    ").unwrap();
+    let new = HLSubstitutionList::decode("
+      [c]→0→s
+      [{0}h]→1→ʧ
+      [a]→2→æ
+    ").unwrap();
+
+    // [{0}h] refers to a different rule now, so it counts as changed too.
+    let changes = rule_changes(&old, &new);
+    assert_eq!(changes, vec![
+      RuleChange::Removed { sub: old.substitutions[0].clone(), index: 0 },
+      RuleChange::Removed { sub: old.substitutions[1].clone(), index: 1 },
+      RuleChange::Added { sub: new.substitutions[0].clone(), index: 0 },
+      RuleChange::Added { sub: new.substitutions[1].clone(), index: 1 },
+      RuleChange::Added { sub: new.substitutions[2].clone(), index: 2 },
+    ]);
+  }
+
+  #[test]
+  fn impacts_sorted_by_weight() {
+    let dictionary = from_words(&[("cat", "kæt", 1.0), ("can", "kæn", 5.0), ("dog", "dog", 10.0)]);
+    let old = HLSubstitutionList::decode("[c]→0→k").unwrap();
+    let new = HLSubstitutionList::decode("
+      [c]→0→k
+      [a]→1→æ
+    ").unwrap();
+
+    let diff = diff_rule_sets(&old, &new, &dictionary, &Levenshtein);
+
+    assert_eq!(diff.impacts.len(), 2);
+    assert_eq!(diff.impacts[0].spelling, decode("can"));
+    assert_eq!(diff.impacts[0].before, decode("kan"));
+    assert_eq!(diff.impacts[0].after, decode("kæn"));
+    assert_eq!(diff.impacts[0].weighted_change(), -5.0);
+    assert_eq!(diff.impacts[1].spelling, decode("cat"));
+    assert_eq!(diff.score_before - diff.score_after, 6.0);
+  }
+}
@@ -0,0 +1,288 @@
+
+/* A file format for high_level_substitutions2 rule lists, so rule sets can be
+ * kept as data instead of string literals.
+ *
+ *   # Comments at the top of the file, followed by a blank line, are the header.
+ *
+ *   [th]→0→ϑ
+ *   # Comments directly above a rule belong to it.
+ *   soft_c: [c]{eiy}→1→s
+ *     tags: tier1 ccmp
+ *     example: city
+ *     note: Soft before front vowels.
+ *
+ * Each rule is one line in the usual debug syntax, optionally prefixed with a
+ * name. Indented `key: value` lines after a rule add metadata to it: `tags` is a
+ * space-separated list, and `example` and `note` can be repeated.
+ *
+ * `encode` gives the canonical form, which is what should be checked in.
+ */
+
+use crate::glyphs::AugGlyph;
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList};
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleEntry {
+  pub name: Option<String>,
+  pub sub: HLSubstitution,
+  pub comments: Vec<String>,
+  pub tags: Vec<String>,
+  pub examples: Vec<String>,
+  pub notes: Vec<String>
+}
+
+impl RuleEntry {
+  pub fn new(sub: HLSubstitution) -> RuleEntry {
+    RuleEntry {
+      name: None,
+      sub,
+      comments: vec![],
+      tags: vec![],
+      examples: vec![],
+      notes: vec![]
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct RuleFile {
+  pub header: Vec<String>,
+  pub entries: Vec<RuleEntry>,
+  pub trailing_comments: Vec<String>
+}
+
+fn is_valid_name(name: &str) -> bool {
+  !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn parse_rule_line(line: &str) -> Result<(Option<String>, HLSubstitution), String> {
+  let (name, rule_str) = match line.split_once(':') {
+    Some((name, rule_str)) => {
+      let name = name.trim();
+      if !is_valid_name(name) {
+        return Err(format!("Bad rule name \"{}\"", name));
+      }
+      (Some(name.to_owned()), rule_str.trim())
+    },
+    None => (None, line)
+  };
+
+  Ok((name, HLSubstitution::decode(rule_str)?))
+}
+
+impl RuleFile {
+  pub fn from_list(rules: &HLSubstitutionList) -> RuleFile {
+    RuleFile {
+      header: vec![],
+      entries: rules.substitutions.iter().map(|s| RuleEntry::new(s.clone())).collect(),
+      trailing_comments: vec![]
+    }
+  }
+
+  pub fn to_list(&self) -> HLSubstitutionList {
+    HLSubstitutionList {
+      substitutions: self.entries.iter().map(|e| e.sub.clone()).collect()
+    }
+  }
+
+  pub fn load(path: &str) -> Result<RuleFile, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    RuleFile::decode(&text).map_err(|e| format!("{}:{}", path, e))
+  }
+
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    std::fs::write(path, self.encode()).map_err(|e| format!("{}: {}", path, e))
+  }
+
+  /// Errors are reported as "line N: message", with N counting from 1.
+  pub fn decode(text: &str) -> Result<RuleFile, String> {
+    let mut res = RuleFile::default();
+    let mut pending_comments: Vec<String> = vec![];
+    let mut rule_lines: Vec<usize> = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+      let line_number = i + 1;
+      let err = |e: String| format!("line {}: {}", line_number, e);
+
+      let trimmed = line.trim();
+
+      if trimmed.is_empty() {
+        // A comment block at the very top that's followed by a blank line is the header.
+        if res.entries.is_empty() && res.header.is_empty() {
+          res.header = std::mem::take(&mut pending_comments);
+        }
+      }
+      else if let Some(comment) = trimmed.strip_prefix('#') {
+        pending_comments.push(comment.trim().to_owned());
+      }
+      else if line.starts_with(' ') || line.starts_with('\t') {
+        if !pending_comments.is_empty() {
+          return Err(err("Comments can't come between a rule and its properties".to_owned()));
+        }
+        let Some(entry) = res.entries.last_mut() else {
+          return Err(err("Property before any rule".to_owned()));
+        };
+        let Some((key, value)) = trimmed.split_once(':') else {
+          return Err(err(format!("Expected \"key: value\", got \"{}\"", trimmed)));
+        };
+        let value = value.trim().to_owned();
+        match key.trim() {
+          "tags" => entry.tags.extend(value.split_whitespace().map(|t| t.to_owned())),
+          "example" => entry.examples.push(value),
+          "note" => entry.notes.push(value),
+          key => return Err(err(format!("Unknown property \"{}\"", key)))
+        }
+      }
+      else {
+        let (name, sub) = parse_rule_line(trimmed).map_err(err)?;
+        let mut entry = RuleEntry::new(sub);
+        entry.name = name;
+        entry.comments = std::mem::take(&mut pending_comments);
+        res.entries.push(entry);
+        rule_lines.push(line_number);
+      }
+    }
+
+    res.trailing_comments = pending_comments;
+
+    // The same checks as HLSubstitutionList::check_back_refs, but saying where.
+    let mut seen_mids: HashSet<u32> = HashSet::new();
+    let mut seen_names: HashSet<&str> = HashSet::new();
+    for (entry, &line_number) in res.entries.iter().zip(rule_lines.iter()) {
+      let sub = &entry.sub;
+      if seen_mids.contains(&sub.mid) {
+        return Err(format!("line {}: mid {} is already used", line_number, sub.mid));
+      }
+      for g in sub.anterior.glyphs().iter().chain(sub.posterior.content.iter()) {
+        if let AugGlyph::Synthetic(n) = g {
+          if !seen_mids.contains(n) {
+            return Err(format!("line {}: {{{}}} is used before the rule that makes it", line_number, n));
+          }
+        }
+      }
+      seen_mids.insert(sub.mid);
+
+      if let Some(name) = &entry.name {
+        if !seen_names.insert(name) {
+          return Err(format!("line {}: name {} is already used", line_number, name));
+        }
+      }
+    }
+
+    Ok(res)
+  }
+
+  pub fn encode(&self) -> String {
+    let mut res = String::new();
+
+    for c in &self.header {
+      res.push_str(&comment_line(c));
+    }
+    if !self.header.is_empty() {
+      res.push('\n');
+    }
+
+    for (i, entry) in self.entries.iter().enumerate() {
+      // Commented rules get some space so the comment clearly goes with them.
+      if i > 0 && !entry.comments.is_empty() {
+        res.push('\n');
+      }
+      for c in &entry.comments {
+        res.push_str(&comment_line(c));
+      }
+      match &entry.name {
+        Some(name) => res.push_str(&format!("{}: {}\n", name, entry.sub.encode())),
+        None => res.push_str(&format!("{}\n", entry.sub.encode()))
+      }
+      if !entry.tags.is_empty() {
+        res.push_str(&format!("  tags: {}\n", entry.tags.join(" ")));
+      }
+      for example in &entry.examples {
+        res.push_str(&format!("  example: {}\n", example));
+      }
+      for note in &entry.notes {
+        res.push_str(&format!("  note: {}\n", note));
+      }
+    }
+
+    if !self.trailing_comments.is_empty() {
+      res.push('\n');
+      for c in &self.trailing_comments {
+        res.push_str(&comment_line(c));
+      }
+    }
+
+    res
+  }
+}
+
+fn comment_line(comment: &str) -> String {
+  if comment.is_empty() {
+    "#\n".to_owned()
+  }
+  else {
+    format!("# {}\n", comment)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EXAMPLE: &str = "# Example rules.
+
+[th]→0→ϑ
+
+# Soft c.
+soft_c: [c]{eiy}→1→s
+  tags: tier1 ccmp
+  example: city
+  example: cent
+  note: Not in loanwords.
+[c]→2→k
+
+# That's all.
+";
+
+  #[test]
+  fn round_trip() {
+    let file = RuleFile::decode(EXAMPLE).unwrap();
+
+    assert_eq!(file.header, vec!["Example rules."]);
+    assert_eq!(file.entries.len(), 3);
+    assert_eq!(file.entries[1].name, Some("soft_c".to_owned()));
+    assert_eq!(file.entries[1].comments, vec!["Soft c."]);
+    assert_eq!(file.entries[1].tags, vec!["tier1", "ccmp"]);
+    assert_eq!(file.entries[1].examples, vec!["city", "cent"]);
+    assert_eq!(file.entries[1].notes, vec!["Not in loanwords."]);
+    assert_eq!(file.trailing_comments, vec!["That's all."]);
+
+    assert_eq!(file.encode(), EXAMPLE);
+  }
+
+  #[test]
+  fn canonical_form() {
+    let messy = "[th]→0→ϑ\n#Soft c.\nsoft_c :   [c]{eiy}→1→s\n\t  tags:  tier1   ccmp\n";
+    let file = RuleFile::decode(messy).unwrap();
+    assert_eq!(file.encode(), "[th]→0→ϑ\n\n# Soft c.\nsoft_c: [c]{eiy}→1→s\n  tags: tier1 ccmp\n");
+  }
+
+  #[test]
+  fn errors_have_line_numbers() {
+    assert_eq!(RuleFile::decode("[a]→0→æ\n\n[b]→1→b\n  colour: red\n"), Err("line 4: Unknown property \"colour\"".to_owned()));
+    assert_eq!(RuleFile::decode("  note: hi\n"), Err("line 1: Property before any rule".to_owned()));
+    assert_eq!(RuleFile::decode("[a]→0→æ\n[{1}]→1→b\n"), Err("line 2: {1} is used before the rule that makes it".to_owned()));
+    assert_eq!(RuleFile::decode("[a]→0→æ\n# x\n[b]→0→b\n"), Err("line 3: mid 0 is already used".to_owned()));
+    assert_eq!(RuleFile::decode("a: [a]→0→æ\na: [b]→1→b\n"), Err("line 2: name a is already used".to_owned()));
+    assert!(RuleFile::decode("[a]→0\n").unwrap_err().starts_with("line 1: "));
+  }
+
+  #[test]
+  fn set_1_file_matches_set_1() {
+    let file = RuleFile::load("res/rules/set_1.rules").unwrap();
+    let set_1 = HLSubstitutionList::set_1();
+    assert_eq!(file.entries.iter().map(|e| &e.sub).collect::<Vec<_>>(), set_1.substitutions.iter().collect::<Vec<_>>());
+    assert_eq!(file.encode(), std::fs::read_to_string("res/rules/set_1.rules").unwrap());
+  }
+}
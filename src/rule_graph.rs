@@ -0,0 +1,194 @@
+
+/* The mid dependency graph of a HLSubstitutionList, for looking at with
+ * Graphviz or other tools.
+ *
+ * There's a node per rule and an edge from rule A to rule B whenever B mentions
+ * {A's mid}, either in its anterior (B only matches after A has fired) or in its
+ * posterior (B's output is rendered through A's posterior). Nodes can optionally
+ * carry how many dictionary words the rule fires on and a few of those words.
+ */
+
+use crate::glyphs::{AugGlyph, augment, encode};
+use crate::high_level_substitutions2::HLSubstitutionList;
+use crate::dictionary::Dictionary;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EdgeKind {
+  Anterior,
+  Posterior
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleNode {
+  pub index: usize,
+  pub mid: u32,
+  pub rule: String,
+  pub fire_count: Option<usize>,
+  pub examples: Vec<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleEdge {
+  pub from_mid: u32,
+  pub to_mid: u32,
+  pub kind: EdgeKind
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleGraph {
+  pub nodes: Vec<RuleNode>,
+  pub edges: Vec<RuleEdge>
+}
+
+impl RuleGraph {
+  pub fn build(rules: &HLSubstitutionList) -> RuleGraph {
+    let nodes = rules.substitutions.iter().enumerate().map(|(index, s)| RuleNode {
+      index,
+      mid: s.mid,
+      rule: s.encode(),
+      fire_count: None,
+      examples: vec![]
+    }).collect();
+
+    let mut edges = vec![];
+    for s in &rules.substitutions {
+      let mut add_edges = |glyphs: Vec<AugGlyph>, kind: EdgeKind| {
+        let mut mids: Vec<u32> = glyphs.iter().filter_map(|g| match g { AugGlyph::Synthetic(n) => Some(*n), AugGlyph::Real(_) => None }).collect();
+        mids.sort();
+        mids.dedup();
+        for from_mid in mids {
+          edges.push(RuleEdge { from_mid, to_mid: s.mid, kind });
+        }
+      };
+      add_edges(s.anterior.glyphs(), EdgeKind::Anterior);
+      add_edges(s.posterior.content.clone(), EdgeKind::Posterior);
+    }
+
+    RuleGraph { nodes, edges }
+  }
+
+  /// Fills in fire counts and up to `max_examples` example words per rule. A rule
+  /// fires on a word if its anterior changes the word at least once.
+  pub fn add_usage(&mut self, rules: &HLSubstitutionList, dictionary: &Dictionary, max_examples: usize) {
+    let mut fire_counts = vec![0; rules.substitutions.len()];
+    let mut examples: Vec<Vec<String>> = vec![vec![]; rules.substitutions.len()];
+
+    for w in &dictionary.words {
+      let mut working = augment(&w.spelling);
+      for (i, s) in rules.substitutions.iter().enumerate() {
+        if s.apply_anterior(&mut working) {
+          fire_counts[i] += 1;
+          if examples[i].len() < max_examples {
+            examples[i].push(encode(&w.spelling));
+          }
+        }
+      }
+    }
+
+    for (node, (fire_count, examples)) in self.nodes.iter_mut().zip(fire_counts.into_iter().zip(examples)) {
+      node.fire_count = Some(fire_count);
+      node.examples = examples;
+    }
+  }
+
+  /// The longest chain of dependencies ending at each mid, counted in rules.
+  pub fn depths(&self) -> HashMap<u32, usize> {
+    let mut res: HashMap<u32, usize> = HashMap::new();
+    // Nodes are in list order, and edges only ever point forwards in the list.
+    for node in &self.nodes {
+      let depth = self.edges.iter().filter(|e| e.to_mid == node.mid).map(|e| res.get(&e.from_mid).copied().unwrap_or(0) + 1).max().unwrap_or(0);
+      res.insert(node.mid, depth);
+    }
+    res
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).unwrap()
+  }
+
+  pub fn to_dot(&self) -> String {
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+
+    let mut res = String::new();
+    res.push_str("digraph rules {\n");
+    res.push_str("  rankdir=LR;\n");
+    res.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+
+    for node in &self.nodes {
+      let mut lines = vec![format!("{}: {}", node.index, node.rule)];
+      if let Some(fire_count) = node.fire_count {
+        lines.push(format!("fires on {} words", fire_count));
+      }
+      if !node.examples.is_empty() {
+        lines.push(format!("e.g. {}", node.examples.join(", ")));
+      }
+      let label = lines.iter().map(|l| escape(l)).collect::<Vec<_>>().join("\\n");
+      res.push_str(&format!("  m{} [label=\"{}\"];\n", node.mid, label));
+    }
+
+    for edge in &self.edges {
+      let style = match edge.kind {
+        EdgeKind::Anterior => "solid",
+        EdgeKind::Posterior => "dashed"
+      };
+      res.push_str(&format!("  m{} -> m{} [style={}];\n", edge.from_mid, edge.to_mid, style));
+    }
+
+    res.push_str("}\n");
+    res
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dictionary::from_words;
+
+  fn rules() -> HLSubstitutionList {
+    HLSubstitutionList::decode("
+      [c]→0→k
+      [{0}h]→1→ʧ
+      [e]→2→ϵ
+      [{1}{2}]→3→{1}i
+    ").unwrap()
+  }
+
+  #[test]
+  fn edges_and_depths() {
+    let graph = RuleGraph::build(&rules());
+
+    assert_eq!(graph.edges, vec![
+      RuleEdge { from_mid: 0, to_mid: 1, kind: EdgeKind::Anterior },
+      RuleEdge { from_mid: 1, to_mid: 3, kind: EdgeKind::Anterior },
+      RuleEdge { from_mid: 2, to_mid: 3, kind: EdgeKind::Anterior },
+      RuleEdge { from_mid: 1, to_mid: 3, kind: EdgeKind::Posterior },
+    ]);
+
+    let depths = graph.depths();
+    assert_eq!(depths[&0], 0);
+    assert_eq!(depths[&1], 1);
+    assert_eq!(depths[&2], 0);
+    assert_eq!(depths[&3], 2);
+  }
+
+  #[test]
+  fn usage_and_dot() {
+    let dictionary = from_words(&[("check", "check", 1.0), ("cat", "cat", 1.0), ("echo", "echo", 1.0)]);
+
+    let mut graph = RuleGraph::build(&rules());
+    graph.add_usage(&rules(), &dictionary, 1);
+
+    assert_eq!(graph.nodes[0].fire_count, Some(3));
+    assert_eq!(graph.nodes[0].examples, vec!["check"]);
+    assert_eq!(graph.nodes[3].fire_count, Some(1));
+
+    let dot = graph.to_dot();
+    assert!(dot.contains("  m3 [label=\"3: [{1}{2}]→3→{1}i\\nfires on 1 words\\ne.g. check\"];\n"));
+    assert!(dot.contains("  m1 -> m3 [style=dashed];\n"));
+
+    let decoded: RuleGraph = serde_json::from_str(&graph.to_json()).unwrap();
+    assert_eq!(decoded, graph);
+  }
+}
@@ -0,0 +1,321 @@
+
+/* Post-optimization pass over a finished HLSubstitutionList.
+ *
+ * The iterative search adds rules greedily, so by the time it's done some of
+ * the early rules are often redundant: later, more specific rules cover all the
+ * words they were introduced for. This tries deleting, merging and reordering
+ * rules, and keeps each change only if the frequency-weighted distance over the
+ * dictionary, by whichever metric the search used, doesn't get worse.
+ *
+ * Deleting and merging are accepted when the score is unchanged (fewer rules is
+ * better on its own), but moving is only accepted when the score strictly
+ * improves, since otherwise the pass could swap two rules back and forth forever.
+ */
+
+use crate::glyphs::AugGlyph;
+use crate::high_level_substitutions2::{HLSubstitution, HLSubstitutionList, Anterior, Posterior, ContextElem};
+use crate::consensus_finding::score_hl2_slist;
+use crate::dictionary::Dictionary;
+use crate::distance_metrics::DistanceMetric;
+
+const SCORE_TOLERANCE: f64 = 1e-9;
+
+#[derive(Debug, Clone)]
+pub enum Change {
+  Deleted { sub: HLSubstitution },
+  Merged { kept: Box<HLSubstitution>, removed: Box<HLSubstitution>, result: Box<HLSubstitution> },
+  Moved { sub: HLSubstitution, from: usize, to: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggedChange {
+  pub change: Change,
+  pub score_before: f64,
+  pub score_after: f64
+}
+
+pub struct MinimizationResult {
+  pub rules: HLSubstitutionList,
+  pub log: Vec<LoggedChange>,
+  pub initial_score: f64,
+  pub final_score: f64
+}
+
+fn anterior_refers_to(anterior: &Anterior, mid: u32) -> bool {
+  anterior.glyphs().contains(&AugGlyph::Synthetic(mid))
+}
+
+fn substitute_glyph(glyphs: &[AugGlyph], from: AugGlyph, to: &[AugGlyph]) -> Vec<AugGlyph> {
+  glyphs.iter().flat_map(|g| {
+    if *g == from { to.to_vec() } else { vec![*g] }
+  }).collect()
+}
+
+/// Expands every synthetic glyph in `content` through the posteriors of `rules`,
+/// giving what the content will finally be rendered as.
+pub fn expand_posterior(rules: &HLSubstitutionList, content: &[AugGlyph]) -> Vec<AugGlyph> {
+  let mut working = content.to_vec();
+  for sub in rules.substitutions.iter().rev() {
+    sub.apply_posterior(&mut working);
+  }
+  working
+}
+
+/// Removes rule `i`. Later posteriors that mention its mid get its posterior inlined, so
+/// they still produce the same output. Returns None if some anterior depends on the mid.
+pub fn without_rule(rules: &HLSubstitutionList, i: usize) -> Option<HLSubstitutionList> {
+  let removed = &rules.substitutions[i];
+
+  if rules.substitutions.iter().enumerate().any(|(k, s)| k != i && anterior_refers_to(&s.anterior, removed.mid)) {
+    return None;
+  }
+
+  let substitutions = rules.substitutions.iter().enumerate().filter(|(k, _)| *k != i).map(|(_, s)| {
+    HLSubstitution {
+      anterior: s.anterior.clone(),
+      mid: s.mid,
+      posterior: Posterior {
+        content: substitute_glyph(&s.posterior.content, AugGlyph::Synthetic(removed.mid), &removed.posterior.content)
+      }
+    }
+  }).collect();
+
+  let res = HLSubstitutionList { substitutions };
+  res.check_back_refs().ok()?;
+  Some(res)
+}
+
+fn substitute_context_elem(e: &ContextElem, from: AugGlyph, to: AugGlyph) -> ContextElem {
+  match e {
+    ContextElem::Glyph(g) if *g == from => ContextElem::Glyph(to),
+    ContextElem::Not(inner) => ContextElem::Not(Box::new(substitute_context_elem(inner, from, to))),
+    ContextElem::Optional(inner) => ContextElem::Optional(Box::new(substitute_context_elem(inner, from, to))),
+    _ => e.clone()
+  }
+}
+
+fn substitute_context(context: &[ContextElem], from: AugGlyph, to: AugGlyph) -> Vec<ContextElem> {
+  context.iter().map(|e| substitute_context_elem(e, from, to)).collect()
+}
+
+fn common_suffix<T: Clone + PartialEq>(a: &[T], b: &[T]) -> Vec<T> {
+  let n = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+  a[a.len() - n ..].to_vec()
+}
+
+fn common_prefix<T: Clone + PartialEq>(a: &[T], b: &[T]) -> Vec<T> {
+  let n = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+  a[.. n].to_vec()
+}
+
+/// Merges rule `j` into the earlier rule `i`. This only makes sense when both rewrite the
+/// same key to the same final output, in which case rule `i` keeps the context they
+/// share and references to `j`'s mid are renamed to `i`'s.
+pub fn merged_rules(rules: &HLSubstitutionList, i: usize, j: usize) -> Option<HLSubstitutionList> {
+  if i >= j { return None; }
+
+  let first = &rules.substitutions[i];
+  let second = &rules.substitutions[j];
+
+  if first.anterior.at_key != second.anterior.at_key {
+    return None;
+  }
+  if expand_posterior(rules, &first.posterior.content) != expand_posterior(rules, &second.posterior.content) {
+    return None;
+  }
+
+  let anterior = Anterior {
+    pre_key: common_suffix(&first.anterior.pre_key, &second.anterior.pre_key),
+    at_key: first.anterior.at_key.clone(),
+    post_key: common_prefix(&first.anterior.post_key, &second.anterior.post_key),
+    at_start: first.anterior.at_start && second.anterior.at_start,
+    at_end: first.anterior.at_end && second.anterior.at_end,
+  };
+
+  let renamed = vec![AugGlyph::Synthetic(first.mid)];
+  let rename = |glyphs: &[AugGlyph]| substitute_glyph(glyphs, AugGlyph::Synthetic(second.mid), &renamed);
+  let rename_context = |context: &[ContextElem]| substitute_context(context, AugGlyph::Synthetic(second.mid), AugGlyph::Synthetic(first.mid));
+
+  let substitutions = rules.substitutions.iter().enumerate().filter(|(k, _)| *k != j).map(|(k, s)| {
+    if k == i {
+      HLSubstitution {
+        anterior: anterior.clone(),
+        mid: s.mid,
+        posterior: s.posterior.clone()
+      }
+    }
+    else {
+      HLSubstitution {
+        anterior: Anterior {
+          pre_key: rename_context(&s.anterior.pre_key),
+          at_key: rename(&s.anterior.at_key),
+          post_key: rename_context(&s.anterior.post_key),
+          at_start: s.anterior.at_start,
+          at_end: s.anterior.at_end,
+        },
+        mid: s.mid,
+        posterior: Posterior {
+          content: rename(&s.posterior.content)
+        }
+      }
+    }
+  }).collect();
+
+  let res = HLSubstitutionList { substitutions };
+  res.check_back_refs().ok()?;
+  Some(res)
+}
+
+/// Moves rule `from` so that it ends up at index `to`. Returns None if that would put
+/// a rule before one of the mids it refers to.
+pub fn moved_rule(rules: &HLSubstitutionList, from: usize, to: usize) -> Option<HLSubstitutionList> {
+  let mut substitutions = rules.substitutions.clone();
+  let sub = substitutions.remove(from);
+  substitutions.insert(to, sub);
+
+  let res = HLSubstitutionList { substitutions };
+  res.check_back_refs().ok()?;
+  Some(res)
+}
+
+pub fn minimize(rules: &HLSubstitutionList, dictionary: &Dictionary, metric: &dyn DistanceMetric, debug: bool) -> MinimizationResult {
+  let mut working = rules.clone();
+  let initial_score = score_hl2_slist(&working, dictionary, metric);
+  let mut score = initial_score;
+  let mut log: Vec<LoggedChange> = vec![];
+
+  loop {
+    let mut any_change = false;
+
+    // Going from the end means earlier indices are still valid after a deletion.
+    for i in (0 .. working.substitutions.len()).rev() {
+      if i >= working.substitutions.len() { continue; }
+      let Some(candidate) = without_rule(&working, i) else { continue };
+      let new_score = score_hl2_slist(&candidate, dictionary, metric);
+      if new_score <= score + SCORE_TOLERANCE {
+        let change = Change::Deleted { sub: working.substitutions[i].clone() };
+        if debug { println!("{:?} {} -> {}", change, score, new_score); }
+        log.push(LoggedChange { change, score_before: score, score_after: new_score });
+        working = candidate;
+        score = new_score;
+        any_change = true;
+      }
+    }
+
+    let mut i = 0;
+    while i < working.substitutions.len() {
+      let mut j = i + 1;
+      while j < working.substitutions.len() {
+        if let Some(candidate) = merged_rules(&working, i, j) {
+          let new_score = score_hl2_slist(&candidate, dictionary, metric);
+          if new_score <= score + SCORE_TOLERANCE {
+            let change = Change::Merged {
+              kept: Box::new(working.substitutions[i].clone()),
+              removed: Box::new(working.substitutions[j].clone()),
+              result: Box::new(candidate.substitutions[i].clone())
+            };
+            if debug { println!("{:?} {} -> {}", change, score, new_score); }
+            log.push(LoggedChange { change, score_before: score, score_after: new_score });
+            working = candidate;
+            score = new_score;
+            any_change = true;
+            continue;
+          }
+        }
+        j += 1;
+      }
+      i += 1;
+    }
+
+    for i in 0 .. working.substitutions.len().saturating_sub(1) {
+      let Some(candidate) = moved_rule(&working, i, i + 1) else { continue };
+      let new_score = score_hl2_slist(&candidate, dictionary, metric);
+      if new_score < score - SCORE_TOLERANCE {
+        let change = Change::Moved { sub: working.substitutions[i].clone(), from: i, to: i + 1 };
+        if debug { println!("{:?} {} -> {}", change, score, new_score); }
+        log.push(LoggedChange { change, score_before: score, score_after: new_score });
+        working = candidate;
+        score = new_score;
+        any_change = true;
+      }
+    }
+
+    if !any_change {
+      break;
+    }
+  }
+
+  MinimizationResult {
+    rules: working,
+    log,
+    initial_score,
+    final_score: score
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dictionary::from_words as dictionary;
+  use crate::distance_metrics::Levenshtein;
+
+  fn encoded(rules: &HLSubstitutionList) -> Vec<String> {
+    rules.substitutions.iter().map(|s| s.encode()).collect()
+  }
+
+  #[test]
+  fn removes_shadowed_rule() {
+    let dictionary = dictionary(&[("cat", "kæt", 1.0), ("at", "æt", 0.5)]);
+    let rules = HLSubstitutionList::decode("
+      [c]→0→k
+      [a]→1→æ
+      {0}[a]→2→æ
+    ").unwrap();
+
+    let result = minimize(&rules, &dictionary, &Levenshtein, false);
+
+    assert_eq!(encoded(&result.rules), vec!["[c]→0→k", "[a]→1→æ"]);
+    assert!(result.final_score <= result.initial_score);
+    assert_eq!(result.log.len(), 1);
+  }
+
+  #[test]
+  fn respects_back_refs() {
+    let dictionary = dictionary(&[("cat", "kæt", 1.0)]);
+    let rules = HLSubstitutionList::decode("
+      [c]→0→k
+      [{0}a]→1→kæ
+    ").unwrap();
+
+    assert!(without_rule(&rules, 0).is_none());
+
+    let result = minimize(&rules, &dictionary, &Levenshtein, false);
+    assert_eq!(encoded(&result.rules), vec!["[c]→0→k", "[{0}a]→1→kæ"]);
+    assert!(result.log.is_empty());
+  }
+
+  #[test]
+  fn inlines_posterior_refs_on_delete() {
+    let rules = HLSubstitutionList::decode("
+      [x]→0→ks
+      [q]→1→{0}
+    ").unwrap();
+
+    let without = without_rule(&rules, 0).unwrap();
+    assert_eq!(encoded(&without), vec!["[q]→1→ks"]);
+  }
+
+  #[test]
+  fn merges_rules_sharing_output() {
+    let dictionary = dictionary(&[("cat", "cæt", 1.0), ("hat", "hæt", 1.0)]);
+    let rules = HLSubstitutionList::decode("
+      c[a]t→0→æ
+      h[a]t→1→æ
+    ").unwrap();
+
+    let result = minimize(&rules, &dictionary, &Levenshtein, false);
+
+    assert_eq!(encoded(&result.rules), vec!["[a]t→0→æ"]);
+    assert_eq!(result.final_score, 0.0);
+  }
+}
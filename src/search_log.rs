@@ -0,0 +1,237 @@
+
+/* Structured telemetry for the astarlike searches.
+ *
+ * The debug flag prints everything, and printing everything is slow enough to
+ * change how the search behaves in practice. A SearchLog instead records a
+ * handful of events per step as JSON lines, each stamped with the rule being
+ * searched for and the milliseconds since the log was opened:
+ *
+ *   {"rule":3,"ms":1520.4,"event":"introduced","word":"ϑe","edits":41,"new_edits":12}
+ *
+ * Attach one to the working data with set_log, or to an IterativeSystem, which
+ * carries it from rule to rule. summarize turns a log back into per-rule
+ * convergence and cost figures; the search_summary binary prints them.
+ */
+
+use serde::{Serialize, Deserialize};
+use howlong::SteadyTimer;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SearchEvent {
+  /// A search for the next rule began.
+  Started { words: usize },
+  /// A word's improving edits were added to the working table.
+  Introduced { word: String, edits: usize, new_edits: usize },
+  /// A batch of edits had their estimates refined.
+  Advanced { edits: usize, words: usize },
+  /// Edits that could no longer win were dropped.
+  Culled { removed: usize, remaining: usize },
+  Winner { edit: String, score: f64 },
+  /// The best edit doesn't improve on the current rules.
+  Failed { edit: String, score: f64 },
+  /// No word had any improving edits left to propose.
+  Exhausted,
+  /// The winner and its closest rivals, scored exactly after the search.
+  Verified { winner: String, winner_score: f64, best: String, best_score: f64, checked: usize }
+}
+
+impl SearchEvent {
+  pub fn name(&self) -> &'static str {
+    match self {
+      SearchEvent::Started { .. } => "started",
+      SearchEvent::Introduced { .. } => "introduced",
+      SearchEvent::Advanced { .. } => "advanced",
+      SearchEvent::Culled { .. } => "culled",
+      SearchEvent::Winner { .. } => "winner",
+      SearchEvent::Failed { .. } => "failed",
+      SearchEvent::Exhausted => "exhausted",
+      SearchEvent::Verified { .. } => "verified"
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+  /// How many rules had been found when this happened.
+  pub rule: usize,
+  pub ms: f64,
+  #[serde(flatten)]
+  pub event: SearchEvent
+}
+
+pub struct SearchLog {
+  out: Box<dyn Write + Send + Sync>,
+  timer: SteadyTimer,
+  /// Stamped on every record; whoever drives the search keeps it up to date.
+  pub rule: usize
+}
+
+impl SearchLog {
+  pub fn new(out: Box<dyn Write + Send + Sync>) -> SearchLog {
+    SearchLog {
+      out,
+      timer: SteadyTimer::new(),
+      rule: 0
+    }
+  }
+
+  pub fn create(path: &str) -> Result<SearchLog, String> {
+    let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(SearchLog::new(Box::new(std::io::BufWriter::new(file))))
+  }
+
+  pub fn log(&mut self, event: SearchEvent) {
+    let record = LogRecord {
+      rule: self.rule,
+      ms: self.timer.elapsed().as_secs_f64() * 1000.0,
+      event
+    };
+    // Losing telemetry isn't worth stopping a search over.
+    let _ = serde_json::to_writer(&mut self.out, &record);
+    let _ = self.out.write_all(b"\n");
+  }
+
+  pub fn flush(&mut self) {
+    let _ = self.out.flush();
+  }
+}
+
+impl Drop for SearchLog {
+  fn drop(&mut self) {
+    self.flush();
+  }
+}
+
+pub fn read_log(path: &str) -> Result<Vec<LogRecord>, String> {
+  let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+  let mut res = vec![];
+  for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+    let line = line.map_err(|e| format!("{}: {}", path, e))?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    res.push(serde_json::from_str(&line).map_err(|e| format!("{}: line {}: {}", path, i + 1, e))?);
+  }
+  Ok(res)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleSummary {
+  pub rule: usize,
+  /// The encoded winner, or None if the search for it never finished.
+  pub winner: Option<String>,
+  pub score: Option<f64>,
+  pub ms: f64,
+  /// Since the first record, when this search ended.
+  pub cumulative_ms: f64,
+  pub words_introduced: usize,
+  pub edits_introduced: usize,
+  pub edits_advanced: usize,
+  pub words_advanced: usize,
+  pub edits_culled: usize,
+  /// How much better than the winner the best verified candidate was.
+  pub regret: Option<f64>,
+  /// Milliseconds attributed to each kind of event: the time since the record
+  /// before it goes to the event that ended it.
+  pub ms_by_event: BTreeMap<String, f64>
+}
+
+/// One summary per rule that appears in `records`, in order.
+pub fn summarize(records: &[LogRecord]) -> Vec<RuleSummary> {
+  let mut res: Vec<RuleSummary> = vec![];
+  let start = records.first().map_or(0.0, |r| r.ms);
+  let mut prev_ms = start;
+
+  for record in records {
+    if res.last().is_none_or(|s| s.rule != record.rule) {
+      res.push(RuleSummary { rule: record.rule, ..RuleSummary::default() });
+    }
+    let s = res.last_mut().unwrap();
+
+    let spent = record.ms - prev_ms;
+    prev_ms = record.ms;
+    s.ms += spent;
+    s.cumulative_ms = record.ms - start;
+    *s.ms_by_event.entry(record.event.name().to_owned()).or_insert(0.0) += spent;
+
+    match &record.event {
+      SearchEvent::Started { .. } | SearchEvent::Exhausted => {},
+      SearchEvent::Introduced { new_edits, .. } => {
+        s.words_introduced += 1;
+        s.edits_introduced += new_edits;
+      },
+      SearchEvent::Advanced { edits, words } => {
+        s.edits_advanced += edits;
+        s.words_advanced += words;
+      },
+      SearchEvent::Culled { removed, .. } => s.edits_culled += removed,
+      SearchEvent::Winner { edit, score } | SearchEvent::Failed { edit, score } => {
+        s.winner = Some(edit.clone());
+        s.score = Some(*score);
+      },
+      SearchEvent::Verified { winner_score, best_score, .. } => s.regret = Some(winner_score - best_score)
+    }
+  }
+
+  res
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::astarlike2::{IterativeSystem, Outcome};
+  use crate::dictionary::from_words;
+  use crate::gaussian_astarlike22::GaussianSystem;
+  use crate::high_level_substitutions2::HLSubstitutionList;
+
+  #[test]
+  fn round_trips_and_summarizes() {
+    let records = vec![
+      LogRecord { rule: 0, ms: 1.0, event: SearchEvent::Started { words: 3 } },
+      LogRecord { rule: 0, ms: 3.0, event: SearchEvent::Introduced { word: "ϑe".to_owned(), edits: 5, new_edits: 5 } },
+      LogRecord { rule: 0, ms: 7.0, event: SearchEvent::Advanced { edits: 5, words: 15 } },
+      LogRecord { rule: 0, ms: 8.0, event: SearchEvent::Culled { removed: 4, remaining: 1 } },
+      LogRecord { rule: 0, ms: 9.0, event: SearchEvent::Winner { edit: "[th]→0→ϑ".to_owned(), score: -2.5 } },
+      LogRecord { rule: 0, ms: 9.0, event: SearchEvent::Verified { winner: "[th]→0→ϑ".to_owned(), winner_score: -2.5, best: "[t]→0→ϑ".to_owned(), best_score: -2.75, checked: 3 } },
+      LogRecord { rule: 1, ms: 10.0, event: SearchEvent::Started { words: 3 } },
+      LogRecord { rule: 1, ms: 12.0, event: SearchEvent::Exhausted }
+    ];
+    let line = serde_json::to_string(&records[1]).unwrap();
+    assert_eq!(line, r#"{"rule":0,"ms":3.0,"event":"introduced","word":"ϑe","edits":5,"new_edits":5}"#);
+    assert_eq!(serde_json::from_str::<LogRecord>(&line).unwrap(), records[1]);
+
+    let summaries = summarize(&records);
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[0].winner.as_deref(), Some("[th]→0→ϑ"));
+    assert_eq!(summaries[0].ms, 8.0);
+    assert_eq!(summaries[0].ms_by_event["advanced"], 4.0);
+    assert_eq!(summaries[0].regret, Some(0.25));
+    assert_eq!((summaries[0].edits_introduced, summaries[0].words_advanced, summaries[0].edits_culled), (5, 15, 4));
+    // The time between one rule's winner and the next rule starting goes to the next rule.
+    assert_eq!((summaries[1].ms, summaries[1].cumulative_ms, summaries[1].winner.clone()), (3.0, 11.0, None));
+  }
+
+  #[test]
+  fn iterative_system_logs() {
+    let dictionary = from_words(&[("the", "ϑʌ", 10.0), ("this", "ϑɪs", 1.0), ("that", "ϑæt", 1.0)]);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("search.jsonl").to_str().unwrap().to_owned();
+
+    let mut iter_system = IterativeSystem::setup(&dictionary, HLSubstitutionList { substitutions: vec![] });
+    iter_system.log = Some(SearchLog::create(&path).unwrap());
+    let Outcome::FoundImprovement(first, _) = iter_system.find_next_rule(&GaussianSystem { scale: 4.0 }, false) else { panic!("Failed to find improvement") };
+    iter_system.find_next_rule(&GaussianSystem { scale: 4.0 }, false);
+    iter_system.log = None;
+
+    let records = read_log(&path).unwrap();
+    assert_eq!(records[0].event, SearchEvent::Started { words: 3 });
+    assert!(records.iter().any(|r| matches!(r.event, SearchEvent::Introduced { .. })));
+    let summaries = summarize(&records);
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[0].winner, Some(first.encode()));
+    assert!(records.windows(2).all(|w| w[0].ms <= w[1].ms));
+  }
+}
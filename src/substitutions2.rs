@@ -14,6 +14,7 @@ pub struct Substitution {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum KeyElem {
   Glyph(AugGlyph),
+  Class(Vec<AugGlyph>),
   AnyLetter
 }
 
@@ -36,6 +37,7 @@ pub struct SubstitutionList {
 pub fn matches(x: &AugGlyph, y: &KeyElem) -> bool {
   match y {
     KeyElem::Glyph(y) => x == y,
+    KeyElem::Class(ys) => ys.contains(x),
     KeyElem::AnyLetter => x.is_letter_or_phonetic()
   }
 }